@@ -0,0 +1,146 @@
+//! Instructions per second of the interpreter over the examples and the programs in `benches/programs`
+//!
+//! `generic` runs every instruction through [`Process::run_instruction`] with a `dyn` supervisor, the way the
+//! interpreter worked before decoding the instructions, `decoded` runs [`Process::run_until_finish`] and `jit`,
+//! with the `jit` feature, the native code of the program. The ffi functions do nothing so the output does not get
+//! in the way
+//!
+//! `cargo bench --bench dispatch [--features jit]`
+
+use std::{fs, time::Duration};
+
+use avmir::{
+  parser::{forth::Forth, structured::Structured, v2::Simple, wat::Wat, Parser},
+  vm::{
+    instruction::ProcessInstruction, memory::{Access, MemoryHandler, ProcessMemory, UnitFault}, process::{ProcesSupervisor, Process, ProcessFault},
+    program::Program, stack::StackValue
+  }
+};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+
+const PROGRAMS: [&str; 8] = [
+  "examples/hello_world.txt",
+  "examples/structured/fibonacci.avs",
+  "examples/forth/squares.fs",
+  "examples/wasm/factorial.wat",
+  "benches/programs/fib.avs",
+  "benches/programs/loop.avs",
+  "benches/programs/collatz.fs",
+  "benches/programs/sum.wat"
+];
+
+struct BenchSupervisor {
+  memory: ProcessMemory
+}
+
+impl ProcesSupervisor for BenchSupervisor {
+  fn get_pid(&self) -> usize {
+    0
+  }
+
+  fn set_memory(&mut self, _: Option<usize>, _: Access) -> Result<(), UnitFault> {
+    Ok(())
+  }
+
+  fn get_memory(&mut self) -> MemoryHandler<'_> {
+    MemoryHandler::MemoryRef(&mut self.memory)
+  }
+
+  fn create_unit(&mut self, _: usize) -> Result<usize, UnitFault> {
+    panic!("the benchmarks do not use shared memory")
+  }
+
+  fn destroy_unit(&mut self, _: usize) -> Result<(), UnitFault> {
+    panic!("the benchmarks do not use shared memory")
+  }
+
+  fn fork(&self, _: Process) {}
+
+  fn checkpoint(&mut self, _: &Process) {}
+
+  fn invoke_ffi(&mut self, _: &[u8], _: &mut Process) -> Result<Option<StackValue>, ProcessFault> {
+    Ok(None)
+  }
+
+  fn unit_memory(&mut self, unit: Option<usize>) -> Result<MemoryHandler<'_>, UnitFault> {
+    match unit {
+      Some(_) => panic!("the benchmarks do not use shared memory"),
+      None => Ok(MemoryHandler::MemoryRef(&mut self.memory))
+    }
+  }
+
+  fn process_memory(&mut self) -> &mut ProcessMemory {
+    &mut self.memory
+  }
+
+  fn local_memory(&mut self) -> Option<&mut ProcessMemory> {
+    Some(&mut self.memory)
+  }
+}
+
+fn load(path: &str) -> Program {
+  let source = fs::read_to_string(path).expect("benchmark program not found");
+  let mut program = Program::with_name(path);
+  let parsed = match path.rsplit_once('.').map(|(_, extension)| extension) {
+    Some("avs") => Structured::parse(&mut program, &source).map_err(|err| err.to_string()),
+    Some("fs") => Forth::parse(&mut program, &source).map_err(|err| err.to_string()),
+    Some("wat") => Wat::parse(&mut program, &source).map_err(|err| err.to_string()),
+    _ => Simple::parse(&mut program, &source).map_err(|err| err.to_string())
+  };
+  parsed.unwrap_or_else(|err| panic!("{}: {}", path, err));
+  program
+}
+
+fn setup(program: &Program) -> (Process, BenchSupervisor, Vec<ProcessInstruction>) {
+  let generic = program.instructions.iter().map(|x| (*x).into()).collect();
+  (Process::new(program.clone()), BenchSupervisor { memory: ProcessMemory::new(program) }, generic)
+}
+
+/// Run every instruction as a generic one, returning the count
+fn run_generic(process: &mut Process, supervisor: &mut dyn ProcesSupervisor, instructions: &[ProcessInstruction]) -> u64 {
+  let mut count = 0;
+  while let Some(&instruction) = instructions.get(process.pc) {
+    process.pc += 1;
+    process.run_instruction(supervisor, instruction).unwrap_or_else(|fault| panic!("{}", fault));
+    count += 1;
+  }
+  count
+}
+
+fn dispatch(c: &mut Criterion) {
+  for path in PROGRAMS {
+    let program = load(path);
+    let (mut process, mut supervisor, instructions) = setup(&program);
+    let count = run_generic(&mut process, &mut supervisor, &instructions);
+
+    let mut group = c.benchmark_group(path);
+    group.throughput(Throughput::Elements(count));
+    group.bench_function("generic", |b| b.iter_batched_ref(
+      || setup(&program),
+      |(process, supervisor, instructions)| run_generic(process, supervisor, instructions),
+      BatchSize::SmallInput
+    ));
+    group.bench_function("decoded", |b| b.iter_batched_ref(
+      || setup(&program),
+      |(process, supervisor, _)| process.run_until_finish(supervisor),
+      BatchSize::SmallInput
+    ));
+    #[cfg(feature = "jit")]
+    {
+      let jit = avmir::vm::jit::JitProgram::compile(&program).expect("unable to compile");
+      group.bench_function("jit", |b| b.iter_batched_ref(
+        || setup(&program),
+        |(process, supervisor, _)| jit.run(process, supervisor),
+        BatchSize::SmallInput
+      ));
+    }
+    group.finish();
+  }
+}
+
+criterion_group! {
+  name = benches;
+  config = Criterion::default().sample_size(20).measurement_time(Duration::from_secs(2));
+  targets = dispatch
+}
+criterion_main!(benches);
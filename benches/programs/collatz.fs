@@ -0,0 +1,8 @@
+\ longest collatz sequence below a limit, stack words and the return stack
+VARIABLE longest
+
+: step ( n -- n' ) DUP 2 MOD IF 3 * 1+ ELSE 2 / THEN ;
+: length ( n -- len ) 0 SWAP BEGIN DUP 1 > WHILE step SWAP 1+ SWAP REPEAT DROP ;
+: search ( limit -- ) 1 DO I length DUP longest @ > IF longest ! ELSE DROP THEN LOOP ;
+
+3000 search
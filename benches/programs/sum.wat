@@ -0,0 +1,22 @@
+;; sum of a sequence stored in the linear memory
+(module
+  (memory 1)
+
+  (func $fill (param $n i64) (local $i i64)
+    (loop $next
+      (i64.store (i64.mul (local.get $i) (i64.const 8)) (local.get $i))
+      (local.set $i (i64.add (local.get $i) (i64.const 1)))
+      (br_if $next (i64.lt_s (local.get $i) (local.get $n)))))
+
+  (func $sum (param $n i64) (result i64) (local $i i64) (local $acc i64)
+    (loop $next
+      (local.set $acc (i64.add (local.get $acc) (i64.load (i64.mul (local.get $i) (i64.const 8)))))
+      (local.set $i (i64.add (local.get $i) (i64.const 1)))
+      (br_if $next (i64.lt_s (local.get $i) (local.get $n))))
+    (local.get $acc))
+
+  (func $main (export "main")
+    (call $fill (i64.const 4000))
+    (drop (call $sum (i64.const 4000)))
+    (call $fill (i64.const 4000))
+    (drop (call $sum (i64.const 4000)))))
@@ -0,0 +1,16 @@
+\ sum of squares, a countdown and some chars
+VARIABLE total
+10 CONSTANT limit
+
+: square ( n -- n*n ) DUP * ;
+: sum-squares ( n -- ) 0 DO I square total +! LOOP ;
+: countdown ( n -- ) BEGIN DUP . 1- DUP 0= UNTIL DROP CR ;
+: factorial ( n -- n! ) DUP 1 > IF DUP 1- RECURSE * ELSE DROP 1 THEN ;
+
+." sum of squares below " limit . ." is "
+limit sum-squares total @ . CR
+5 countdown
+." 10! = " 10 factorial . CR
+65 EMIT 66 EMIT 67 EMIT CR
+3 0 DO 2 0 DO J 10 * I + . LOOP LOOP CR
+7 3 MOD . 7 3 / . 7 3 - . 1 2 < . 3 1 2 ROT . . . CR
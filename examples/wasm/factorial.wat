@@ -0,0 +1,51 @@
+;; factorials with a loop and with recursion, a counter in a global and a message in the linear memory
+(module
+  (import "env" "std_reg_println" (func $print (param i64)))
+  (import "memory" "std_println" (func $println (param i64 i64)))
+
+  (memory 1)
+  (data (i32.const 16) "factorials:")
+  (global $calls (mut i64) (i64.const 0))
+
+  (func $factorial (param $n i64) (result i64) (local $acc i64)
+    (local.set $acc (i64.const 1))
+    (block $done
+      (loop $next
+        (br_if $done (i64.le_s (local.get $n) (i64.const 1)))
+        (local.set $acc (i64.mul (local.get $acc) (local.get $n)))
+        (local.set $n (i64.sub (local.get $n) (i64.const 1)))
+        (br $next)))
+    (local.get $acc))
+
+  (func $recursive (param $n i64) (result i64)
+    global.get $calls
+    i64.const 1
+    i64.add
+    global.set $calls
+    local.get $n
+    i64.const 2
+    i64.lt_s
+    if (result i64)
+      i64.const 1
+    else
+      local.get $n
+      local.get $n
+      i64.const 1
+      i64.sub
+      call $recursive
+      i64.mul
+    end)
+
+  (func $main (export "main") (local $i i64)
+    (call $println (i64.const 16) (i64.const 11))
+    (loop $next
+      (call $print (call $factorial (local.get $i)))
+      (local.set $i (i64.add (local.get $i) (i64.const 1)))
+      (br_if $next (i64.lt_s (local.get $i) (i64.const 6))))
+
+    ;; store and load through the linear memory
+    (i64.store offset=8 (i64.const 0) (call $recursive (i64.const 10)))
+    (call $print (i64.load offset=8 (i64.const 0)))
+    (call $print (global.get $calls))
+    (call $print (i64.trunc_f64_s (f64.div (f64.convert_i64_s (i64.const 7)) (f64.const 2.0))))
+    (call $print (select (i64.const 10) (i64.const 20) (i64.rem_s (i64.const 7) (i64.const 2))))))
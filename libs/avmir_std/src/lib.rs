@@ -1,4 +1,8 @@
-use avmir::vm::{memory::Memory, process::{ProcesSupervisor, Process, PublicRegisters}, stack::StackValue};
+use std::io::{self, Write};
+
+use avmir::{
+  export_c_ffi, vm::{memory::Memory, process::{ProcesSupervisor, Process, ProcessFault, PublicRegisters}, stack::StackValue}
+};
 
 /// hello world function to know everything worked
 #[no_mangle]
@@ -14,17 +18,23 @@ fn std_sum_registers(regs: &PublicRegisters) -> Option<StackValue> {
   Some(StackValue::Int(regs.iter().map(|x| Into::<i64>::into(*x)).sum()))
 }
 
-/// print a chunk of memory
+/// print a chunk of memory, a memory fault goes to the standard error
 #[no_mangle]
 fn std_print(regs: &PublicRegisters, memory: &mut dyn Memory) -> Option<StackValue> {
-  print!("{}", String::from_utf8_lossy(&memory.read(regs[0].into(), regs[1].into())));
+  match memory.try_read(regs[0].into(), regs[1].into()) {
+    Ok(bytes) => print!("{}", String::from_utf8_lossy(bytes)),
+    Err(fault) => eprintln!("{}", fault)
+  }
   None
 }
 
-/// print a chunk of memory with line end
+/// print a chunk of memory with line end, a memory fault goes to the standard error
 #[no_mangle]
 fn std_println(regs: &PublicRegisters, memory: &mut dyn Memory) -> Option<StackValue> {
-  println!("{}", String::from_utf8_lossy(&memory.read(regs[0].into(), regs[1].into())));
+  match memory.try_read(regs[0].into(), regs[1].into()) {
+    Ok(bytes) => println!("{}", String::from_utf8_lossy(bytes)),
+    Err(fault) => eprintln!("{}", fault)
+  }
   None
 }
 
@@ -42,18 +52,34 @@ fn std_reg_println(regs: &PublicRegisters) -> Option<StackValue> {
   None
 }
 
-/// debug trap while handle the program showing the current instruction until it finishes
+/// debug trap while handle the program showing the current instruction until it finishes or faults
 #[no_mangle]
-fn std_trap_debug(process: &mut Process, supervisor: &mut dyn ProcesSupervisor) -> Option<StackValue> {
+fn std_trap_debug(process: &mut Process, supervisor: &mut dyn ProcesSupervisor) -> Result<Option<StackValue>, ProcessFault> {
   loop {
     if let Some(intruction) = process.get_current_instruction() {
       println!("RUNNING: {}", intruction);
     }
 
-    if !process.run_next(supervisor) {
+    if !process.run_next(supervisor)? {
       break
     }
   }
   println!("DONE!");
+  Ok(None)
+}
+
+/// flush the standard output, the print functions without line end do not flush it
+#[no_mangle]
+fn std_flush() -> Option<StackValue> {
+  let _ = io::stdout().flush();
   None
-}
\ No newline at end of file
+}
+
+// the functions for programs translated to C, traps can not be called from them
+export_c_ffi!(avmir_c_std_hello_world => |_, _| std_hello_world());
+export_c_ffi!(avmir_c_std_sum_registers => |registers, _| std_sum_registers(registers));
+export_c_ffi!(avmir_c_std_print => |registers, memory| std_print(registers, memory));
+export_c_ffi!(avmir_c_std_println => |registers, memory| std_println(registers, memory));
+export_c_ffi!(avmir_c_std_reg_print => |registers, _| std_reg_print(registers));
+export_c_ffi!(avmir_c_std_reg_println => |registers, _| std_reg_println(registers));
+export_c_ffi!(avmir_c_std_flush => |_, _| std_flush());
@@ -1,17 +1,30 @@
 use std::str::FromStr;
 use thiserror::Error;
 
-use clap::Parser;
+use clap::{Parser, Subcommand, ValueEnum};
+
+use crate::vm::memory::Access;
 
 #[derive(Debug, Clone)]
-pub enum MemoryInput {
-  Virtual {
-    size: usize
+pub enum MemorySource {
+  Zeroed,
+  /// Window of a file mapped as the memory
+  File {
+    path: String,
+    offset: u64,
+    grow: bool
   },
-  FileMap {
-    size: usize,
-    path: String
-  }
+  /// POSIX shared memory object, shared with other instances
+  Shared(String)
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryInput {
+  /// Name to mount the unit with `%name`
+  pub name: Option<String>,
+  pub size: usize,
+  pub source: MemorySource,
+  pub access: Access
 }
 
 #[derive(Debug, Error)]
@@ -21,28 +34,151 @@ pub struct NotAValidMemoryError;
 impl FromStr for MemoryInput {
   type Err = NotAValidMemoryError;
 
+  /// `[name=]size[:path|:shm:/object][:ro|wo|rw][:offset=N][:grow]`, the offset and growth for files
   fn from_str(s: &str) -> Result<Self, Self::Err> {
-    if let Ok(size) = s.parse() {
-      return Ok(Self::Virtual { size });
+    let (name, s) = match s.split_once('=') {
+      Some((name, unit)) if !name.is_empty() && name.chars().all(|c| c.is_alphanumeric() || c == '_') => (Some(name.into()), unit),
+      _ => (None, s)
+    };
+
+    let (mut s, mut access, mut offset, mut grow) = (s, Access::READ_WRITE, None, false);
+    while let Some((unit, option)) = s.rsplit_once(':') {
+      match option {
+        "ro" => access = Access::READ_ONLY,
+        "wo" => access = Access::WRITE_ONLY,
+        "rw" => access = Access::READ_WRITE,
+        "grow" => grow = true,
+        _ => match option.strip_prefix("offset=") {
+          Some(value) => offset = Some(value.parse().map_err(|_| NotAValidMemoryError)?),
+          None => break
+        }
+      }
+      s = unit;
     }
 
-    let mut content = s.split(":");
-    match (content.next().and_then(|x| x.parse().ok()), content.next()) {
-      (Some(size), Some(path)) => Ok(Self::FileMap { size, path: path.into() }),
-      _ => Err(NotAValidMemoryError)
+    let (size, source) = match s.split_once(':') {
+      Some((size, path)) => match path.strip_prefix("shm:") {
+        Some(object) => (size, MemorySource::Shared(object.into())),
+        None => (size, MemorySource::File { path: path.into(), offset: offset.unwrap_or(0), grow })
+      },
+      None => (s, MemorySource::Zeroed)
+    };
+    if !matches!(source, MemorySource::File { .. }) && (offset.is_some() || grow) {
+      return Err(NotAValidMemoryError)
     }
+    let size = size.parse().map_err(|_| NotAValidMemoryError)?;
+    Ok(MemoryInput { name, size, source, access })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Frontend {
+  /// chosen by the file extension
+  Auto,
+  V2,
+  Structured,
+  Forth,
+  Wat
+}
+
+impl Frontend {
+  /// The front-end for a file, `.avs` is the structured language, `.fs`, `.fth` and `.4th` are forth,
+  /// `.wat` is webassembly text and v2 otherwise
+  pub fn of(self, file: &str) -> Frontend {
+    if self != Frontend::Auto {
+      return self
+    }
+    match file.rsplit_once('.').map(|(_, extension)| extension.to_lowercase()).as_deref() {
+      Some("avs") => Frontend::Structured,
+      Some("fs" | "fth" | "4th") => Frontend::Forth,
+      Some("wat") => Frontend::Wat,
+      _ => Frontend::V2
+    }
+  }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+  /// convert a source written for the deprecated v1 parser into v2
+  Migrate {
+    input: String,
+
+    /// write the v2 source into a file instead of the standard output
+    #[arg(short)]
+    output: Option<String>
+  },
+
+  /// format v2 sources in place
+  Fmt {
+    files: Vec<String>,
+
+    /// only check the files are formatted, failing otherwise
+    #[arg(long)]
+    check: bool
+  },
+
+  /// run the language server over stdio
+  Lsp,
+
+  /// translate a program into a C source to build a standalone binary
+  Translate {
+    input: String,
+
+    /// write the C source into a file instead of the standard output
+    #[arg(short)]
+    output: Option<String>,
+
+    /// ffi library opened by the binary
+    #[arg(short)]
+    library: Vec<String>,
+
+    /// language of the file
+    #[arg(long, value_enum, default_value_t = Frontend::Auto)]
+    frontend: Frontend,
+
+    /// optimize the program before translating it
+    #[arg(short = 'O', long)]
+    optimize: bool
   }
 }
 
 #[derive(Parser)]
-#[command(version, about)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
 pub struct Args {
+  #[command(subcommand)]
+  pub command: Option<Command>,
+
   #[arg(short)]
   pub memory: Vec<MemoryInput>,
 
   #[arg(short)]
   pub library: Vec<String>,
 
+  /// language of the files
+  #[arg(long, value_enum, default_value_t = Frontend::Auto)]
+  pub frontend: Frontend,
+
+  /// link all the files into a single program, the first file being the entry point
+  #[arg(long)]
+  pub link: bool,
+
+  /// optimize the programs before running them
+  #[arg(short = 'O', long)]
+  pub optimize: bool,
+
+  /// save the checkpoints of the processes into the directory
+  #[arg(long, value_name = "DIR")]
+  pub checkpoint_dir: Option<String>,
+
+  /// the files are checkpoints of processes to resume
+  #[arg(long, conflicts_with_all = ["link", "optimize", "frontend"])]
+  pub resume: bool,
+
+  /// compile hot programs to native code
+  #[cfg(feature = "jit")]
+  #[arg(long)]
+  pub jit: bool,
+
   #[arg()]
   pub files: Vec<String>
 }
\ No newline at end of file
@@ -0,0 +1,278 @@
+//! Translation of programs to C source, built into standalone binaries by a C compiler
+//!
+//! Every instruction becomes a label in a single function and the program counter is only materialized on dynamic
+//! jumps, which go through a table of label addresses (computed goto, a GCC and Clang extension). The process keeps
+//! the stack, registers and memory of the interpreter, the static data is embedded and forks run in new threads:
+//! `cc program.c -o program -ldl -lpthread`
+//!
+//! The libraries are opened when the binary starts and the ffi functions are looked up with `dlsym` under the
+//! [`FFI_PREFIX`], following the C convention of [`CFunction`]. Libraries export them with [`export_c_ffi`](crate::export_c_ffi).
+//! Shared memory units and trap functions are not supported, using a unit or invoking with the trap flag aborts.
+//! Writes to the read only chunks of the static data abort, the ffi functions get the memory unprotected
+
+use std::{fmt::Write, slice};
+
+use libloading::library_filename;
+
+use crate::vm::{
+  memory::Memory,
+  process::{PublicRegisters, PUBLIC_REGISTERS_COUNT},
+  program::{Instruction, InstructionParam, Opcode, Program},
+  stack::StackValue
+};
+
+/// Prefix of the symbols looked up for the ffi functions
+pub const FFI_PREFIX: &str = "avmir_c_";
+
+/// The C runtime, the definitions of the program go after the includes, in place of the `/* program */` mark
+const RUNTIME: &str = include_str!("runtime.c");
+
+/// A value as seen from C, a tag (0 for int, 1 for float) and the bits of the value
+#[repr(C)]
+#[derive(Clone, Copy, Debug)]
+pub struct CValue {
+  pub tag: i64,
+  pub bits: u64
+}
+
+impl From<StackValue> for CValue {
+  fn from(value: StackValue) -> Self {
+    match value {
+      StackValue::Int(x) => CValue { tag: 0, bits: x as u64 },
+      StackValue::Float(x) => CValue { tag: 1, bits: x.to_bits() }
+    }
+  }
+}
+
+impl From<CValue> for StackValue {
+  fn from(value: CValue) -> Self {
+    match value.tag {
+      0 => StackValue::Int(value.bits as i64),
+      _ => StackValue::Float(f64::from_bits(value.bits))
+    }
+  }
+}
+
+/// The public registers, the active memory and its size, returns whether the result was written
+pub type CFunction = unsafe extern "C" fn (*mut CValue, *mut u8, usize, *mut CValue) -> bool;
+
+/// Call an ffi function with the arguments of a [`CFunction`]
+///
+/// The standard output is flushed before returning, the translated program exits without the Rust runtime doing it
+///
+/// # Safety
+/// `registers` must point to [`PUBLIC_REGISTERS_COUNT`] values, `memory` to `size` bytes and `result` to a value
+pub unsafe fn call_c_ffi(
+  registers: *mut CValue, memory: *mut u8, size: usize, result: *mut CValue,
+  function: impl FnOnce(&mut PublicRegisters, &mut dyn Memory) -> Option<StackValue>
+) -> bool {
+  let registers = &mut *(registers as *mut [CValue; PUBLIC_REGISTERS_COUNT]);
+  let mut values: PublicRegisters = registers.map(Into::into);
+  let mut memory = slice::from_raw_parts_mut(memory, size);
+  let output = function(&mut values, &mut memory);
+  *registers = values.map(Into::into);
+  let _ = std::io::Write::flush(&mut std::io::stdout());
+  match output {
+    Some(value) => {
+      *result = value.into();
+      true
+    }
+    None => false
+  }
+}
+
+/// Export a function to translated programs as a [`CFunction`] named with the [`FFI_PREFIX`]
+///
+/// `export_c_ffi!(avmir_c_std_print => |registers, memory| std_print(registers, memory));`
+#[macro_export]
+macro_rules! export_c_ffi {
+  ($symbol: ident => $function: expr) => {
+    /// # Safety
+    /// See [`call_c_ffi`](avmir::backend::c::call_c_ffi)
+    #[no_mangle]
+    pub unsafe extern "C" fn $symbol(
+      registers: *mut $crate::backend::c::CValue, memory: *mut u8, size: usize, result: *mut $crate::backend::c::CValue
+    ) -> bool {
+      $crate::backend::c::call_c_ffi(registers, memory, size, result, $function)
+    }
+  };
+}
+
+fn value(param: InstructionParam) -> String {
+  match param {
+    InstructionParam::Int(i64::MIN) => "INT(INT64_MIN)".into(),
+    InstructionParam::Int(x) => format!("INT(INT64_C({}))", x),
+    InstructionParam::Float(x) if x.is_nan() => "FLOAT(NAN)".into(),
+    InstructionParam::Float(x) if x.is_infinite() => format!("FLOAT({}INFINITY)", if x < 0.0 { "-" } else { "" }),
+    InstructionParam::Float(x) => format!("FLOAT({:?})", x)
+  }
+}
+
+fn operand(param: Option<InstructionParam>) -> String {
+  param.map(value).unwrap_or("pop(p)".into())
+}
+
+/// Assign `a` and `b` like the interpreter, missing operands popped from the top of the stack
+fn both(Instruction(_, a, b): Instruction) -> String {
+  format!("a = {}; b = {}; ", operand(a), operand(b))
+}
+
+fn first(Instruction(_, a, _): Instruction) -> String {
+  format!("a = {}; ", operand(a))
+}
+
+fn statement(instruction: Instruction, instructions: usize) -> String {
+  let Instruction(opcode, a, b) = instruction;
+  let binary = |function: &str| format!("{}push(p, {}(a, b));", both(instruction), function);
+  let read = |function: &str| format!("{}push(p, {}(p, a));", first(instruction), function);
+  let write = |function: &str| format!("{}{}(p, a, b);", both(instruction), function);
+  match opcode {
+    Opcode::Noop => String::new(),
+    Opcode::Debug => "debug(p);".into(),
+
+    Opcode::Add => binary("add"),
+    Opcode::Sub => binary("sub"),
+    Opcode::Mul => binary("mul"),
+    Opcode::Div => binary("divide"),
+    Opcode::Gt => binary("gt"),
+    Opcode::Ls => binary("ls"),
+    Opcode::Gteq => binary("gteq"),
+    Opcode::Lseq => binary("lseq"),
+    Opcode::Eq => binary("eq"),
+    Opcode::Noteq => binary("noteq"),
+
+    Opcode::Int => format!("{}push(p, to_int(a));", first(instruction)),
+    Opcode::Float => format!("{}push(p, to_float(a));", first(instruction)),
+
+    Opcode::Discard => "discard_top(p);".into(),
+    Opcode::Clone => "clone_top(p);".into(),
+    Opcode::Push => [a, b].into_iter().flatten().map(|x| format!("push(p, {});", value(x))).collect::<Vec<_>>().join(" "),
+    Opcode::Swap => format!("{}push(p, a); push(p, b);", both(instruction)),
+    Opcode::Over => format!("{}push(p, b); push(p, a); push(p, b);", both(instruction)),
+
+    Opcode::Reg => format!("{}push(p, p->registers[reg_index(a, \"expecting: reg :: int\")]);", first(instruction)),
+    Opcode::SetReg =>
+      format!("{}p->registers[reg_index(a, \"expecting: registry :: int, value :: any\")] = b;", both(instruction)),
+
+    Opcode::WriteInt64 => write("write_int64"),
+    Opcode::ReadInt64 => read("read_int64"),
+    Opcode::WriteInt32 => write("write_int32"),
+    Opcode::ReadInt32 => read("read_int32"),
+    Opcode::WriteInt16 => write("write_int16"),
+    Opcode::ReadInt16 => read("read_int16"),
+    Opcode::WriteInt8 => write("write_int8"),
+    Opcode::ReadInt8 => read("read_int8"),
+    Opcode::WriteFloat64 => write("write_float64"),
+    Opcode::ReadFloat64 => read("read_float64"),
+    Opcode::WriteFloat32 => write("write_float32"),
+    Opcode::ReadFloat32 => read("read_float32"),
+    Opcode::ReadUInt32 => read("read_uint32"),
+    Opcode::ReadUInt16 => read("read_uint16"),
+    Opcode::ReadUInt8 => read("read_uint8"),
+    Opcode::WriteInt64BE => write("write_int64_be"),
+    Opcode::ReadInt64BE => read("read_int64_be"),
+    Opcode::WriteInt32BE => write("write_int32_be"),
+    Opcode::ReadInt32BE => read("read_int32_be"),
+    Opcode::ReadUInt32BE => read("read_uint32_be"),
+    Opcode::WriteInt16BE => write("write_int16_be"),
+    Opcode::ReadInt16BE => read("read_int16_be"),
+    Opcode::ReadUInt16BE => read("read_uint16_be"),
+    Opcode::WriteFloat64BE => write("write_float64_be"),
+    Opcode::ReadFloat64BE => read("read_float64_be"),
+    Opcode::WriteFloat32BE => write("write_float32_be"),
+    Opcode::ReadFloat32BE => read("read_float32_be"),
+
+    Opcode::Mount | Opcode::MountReadOnly | Opcode::CreateUnit | Opcode::DestroyUnit => "fault(\"shared memory units are not supported\");".into(),
+    Opcode::Unmount | Opcode::Msync | Opcode::Checkpoint => String::new(),
+
+    Opcode::MemCopy => format!("{}mem_copy(p, a, b);", both(instruction)),
+    Opcode::MemFill => format!("{}mem_fill(p, a, b);", both(instruction)),
+    Opcode::MemCompare => format!("{}push(p, mem_compare(p, a, b));", both(instruction)),
+    Opcode::MemFind => format!("{}push(p, mem_find(p, a, b));", both(instruction)),
+
+    Opcode::Alloc => format!("{}push(p, alloc(p, a));", first(instruction)),
+    Opcode::Free => format!("{}free_allocation(p, a);", first(instruction)),
+    Opcode::Realloc => format!("{}push(p, reallocate(p, a, b));", both(instruction)),
+
+    Opcode::Jump => match a {
+      Some(InstructionParam::Int(pc)) if (0..instructions as i64).contains(&pc) =>
+        format!("{}if (jump(a, b)) goto i{};", both(instruction), pc),
+      _ => format!("{}if (jump(a, b)) goto *labels[target(a)];", both(instruction))
+    }
+    Opcode::Fork => format!("{}fork_process(p, a);", first(instruction)),
+    Opcode::Exit => "goto end;".into(),
+    Opcode::ThreadSleep => format!("{}sleep_millis(a);", first(instruction)),
+
+    Opcode::PrepareInvoke => format!("{}prepare_invoke(p, a, b);", both(instruction)),
+    Opcode::Invoke => "invoke(p);".into(),
+    Opcode::FastInvoke => format!("{}prepare_invoke(p, a, b); invoke(p);", both(instruction)),
+
+    Opcode::Pid => "push(p, INT(p->pid));".into()
+  }
+}
+
+fn c_string(text: &str) -> String {
+  let mut output = String::from("\"");
+  for byte in text.bytes() {
+    match byte {
+      b'"' | b'\\' => write!(output, "\\{}", byte as char).unwrap(),
+      b' '..=b'~' => output.push(byte as char),
+      _ => write!(output, "\\{:03o}", byte).unwrap()
+    }
+  }
+  output.push('"');
+  output
+}
+
+/// Translate the program into a C source opening the given ffi libraries, named like for [`FFILoader`](crate::vm::ffi::FFILoader)
+pub fn translate(program: &Program, libraries: &[impl AsRef<str>]) -> String {
+  let instructions = program.instructions.len();
+  let (includes, runtime) = RUNTIME.split_once("/* program */\n").expect("program mark in the runtime");
+  let mut c = String::new();
+
+  writeln!(c, "/* {} translated by avmir */", program.name.replace("*/", "* /")).unwrap();
+  c.push_str(includes);
+  writeln!(c, "#define INSTRUCTIONS {}", instructions).unwrap();
+  writeln!(c, "#define MEMORY_SIZE {}", program.memory().len()).unwrap();
+  writeln!(c, "#define STATIC_DATA_SIZE {}", program.static_data.len()).unwrap();
+  writeln!(c).unwrap();
+
+  // at least one byte, arrays can not be empty
+  c.push_str("static const unsigned char static_data[] = {");
+  for (idx, byte) in program.static_data.iter().chain(program.static_data.is_empty().then_some(&0)).enumerate() {
+    c.push_str(if idx % 16 == 0 { "\n  " } else { " " });
+    write!(c, "{},", byte).unwrap();
+  }
+  c.push_str("\n};\n\n");
+
+  let read_only: Vec<_> = program.static_data_meta.iter().filter(|(_, size)| *size > 0).collect();
+  writeln!(c, "#define READ_ONLY_CHUNKS {}", read_only.len()).unwrap();
+  c.push_str("static const uint64_t read_only[][2] = {");
+  for (idx, (address, size)) in read_only.iter().chain(read_only.is_empty().then_some(&&(0, 0))).enumerate() {
+    c.push_str(if idx % 8 == 0 { "\n  " } else { " " });
+    write!(c, "{{{}, {}}},", address, size).unwrap();
+  }
+  c.push_str("\n};\n\n");
+
+  c.push_str("static const char *const libraries[] = { ");
+  for library in libraries {
+    write!(c, "{}, ", c_string(&library_filename(library.as_ref()).to_string_lossy())).unwrap();
+  }
+  c.push_str("NULL };\n\n");
+
+  c.push_str(runtime);
+
+  c.push_str("\nstatic void *run(void *arg) {\n  static void *const labels[] = {");
+  for idx in 0..instructions {
+    c.push_str(if idx % 8 == 0 { "\n    " } else { " " });
+    write!(c, "&&i{},", idx).unwrap();
+  }
+  c.push_str("\n    &&end\n  };\n");
+  c.push_str("  process *p = arg;\n  avmir_value a, b;\n  (void)a;\n  (void)b;\n  goto *labels[p->pc];\n\n");
+  for (idx, instruction) in program.instructions.iter().enumerate() {
+    writeln!(c, "i{}: /* {} */", idx, instruction).unwrap();
+    writeln!(c, "  {}", statement(*instruction, instructions)).unwrap();
+  }
+  c.push_str("end:\n  end_process(p);\n  return NULL;\n}\n");
+  c
+}
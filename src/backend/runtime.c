@@ -0,0 +1,593 @@
+#include <dlfcn.h>
+#include <errno.h>
+#include <inttypes.h>
+#include <math.h>
+#include <pthread.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#if __BYTE_ORDER__ != __ORDER_LITTLE_ENDIAN__
+#error "the memory of avmir programs is little endian"
+#endif
+
+/* program */
+
+#define STACK_SIZE 32
+#define REGISTERS 24
+#define FLAG_INVOKE_TRAP 11
+#define TAG_INT 0
+#define TAG_FLOAT 1
+#define FFI_PREFIX "avmir_c_"
+#define HEAP_ALIGN 8
+#define HEAP_LIMIT ((uint64_t)1 << 32)
+
+typedef struct {
+  int64_t tag;
+  union {
+    int64_t i;
+    double f;
+  } as;
+} avmir_value;
+
+/* registers 0 to 9, the active memory and its size, returns whether there is a result */
+typedef int (*avmir_function)(avmir_value *registers, uint8_t *memory, size_t size, avmir_value *result);
+
+/* blocks of the heap sorted by address */
+typedef struct {
+  struct { uint64_t address, size; } *items;
+  size_t len, capacity;
+} heap_blocks;
+
+typedef struct {
+  avmir_value stack[STACK_SIZE];
+  size_t sp;
+  avmir_value registers[REGISTERS];
+  uint64_t pc;
+  int64_t pid;
+  uint8_t *memory;
+  uint64_t memory_size;
+  uint64_t heap_top;
+  heap_blocks allocated;
+  heap_blocks freed;
+  char *invoke_target;
+  size_t invoke_size;
+} process;
+
+#define INT(x) ((avmir_value){ .tag = TAG_INT, .as.i = (x) })
+#define FLOAT(x) ((avmir_value){ .tag = TAG_FLOAT, .as.f = (x) })
+
+static void *handles[sizeof(libraries) / sizeof(*libraries)];
+static pthread_mutex_t active_lock = PTHREAD_MUTEX_INITIALIZER;
+static pthread_cond_t process_ended = PTHREAD_COND_INITIALIZER;
+static size_t active = 0;
+static int64_t pid_counter = 0;
+
+static void *run(void *arg);
+
+static inline void fault(const char *message) {
+  fflush(stdout);
+  fprintf(stderr, "%s\n", message);
+  abort();
+}
+
+static inline void push(process *p, avmir_value value) {
+  if (p->sp == STACK_SIZE) fault("stack overflow");
+  p->stack[p->sp++] = value;
+}
+
+static inline avmir_value pop(process *p) {
+  if (p->sp == 0) fault("expecting argument on the stack");
+  return p->stack[--p->sp];
+}
+
+static inline void discard_top(process *p) {
+  if (p->sp > 0) p->sp--;
+}
+
+static inline void clone_top(process *p) {
+  if (p->sp > 0) push(p, p->stack[p->sp - 1]);
+}
+
+static inline void same_type(avmir_value a, avmir_value b) {
+  if (a.tag != b.tag) fault("operands must be same type");
+}
+
+/* integers wrap on overflow */
+#define ARITHMETIC(name, op) \
+  static inline avmir_value name(avmir_value a, avmir_value b) { \
+    same_type(a, b); \
+    if (a.tag == TAG_FLOAT) return FLOAT(a.as.f op b.as.f); \
+    return INT((int64_t)((uint64_t)a.as.i op (uint64_t)b.as.i)); \
+  }
+
+ARITHMETIC(add, +)
+ARITHMETIC(sub, -)
+ARITHMETIC(mul, *)
+
+static inline avmir_value divide(avmir_value a, avmir_value b) {
+  same_type(a, b);
+  if (a.tag == TAG_FLOAT) return FLOAT(a.as.f / b.as.f);
+  if (b.as.i == 0) fault("attempt to divide by zero");
+  if (a.as.i == INT64_MIN && b.as.i == -1) fault("attempt to divide with overflow");
+  return INT(a.as.i / b.as.i);
+}
+
+#define COMPARISON(name, op) \
+  static inline avmir_value name(avmir_value a, avmir_value b) { \
+    same_type(a, b); \
+    return INT(a.tag == TAG_INT ? a.as.i op b.as.i : a.as.f op b.as.f); \
+  }
+
+COMPARISON(gt, >)
+COMPARISON(ls, <)
+COMPARISON(gteq, >=)
+COMPARISON(lseq, <=)
+COMPARISON(eq, ==)
+COMPARISON(noteq, !=)
+
+/* saturating, NaN is 0 */
+static inline avmir_value to_int(avmir_value a) {
+  if (a.tag == TAG_INT) return a;
+  if (isnan(a.as.f)) return INT(0);
+  if (a.as.f >= 9223372036854775808.0) return INT(INT64_MAX);
+  if (a.as.f <= -9223372036854775808.0) return INT(INT64_MIN);
+  return INT((int64_t)a.as.f);
+}
+
+static inline avmir_value to_float(avmir_value a) {
+  return a.tag == TAG_FLOAT ? a : FLOAT((double)a.as.i);
+}
+
+/* the value as an unsigned size is not 0 */
+static inline int is_set(avmir_value a) {
+  return a.tag == TAG_INT ? a.as.i != 0 : a.as.f >= 1.0;
+}
+
+static inline size_t reg_index(avmir_value a, const char *message) {
+  if (a.tag != TAG_INT) fault(message);
+  if ((uint64_t)a.as.i >= REGISTERS) fault("register out of bounds");
+  return (size_t)a.as.i;
+}
+
+static inline uint8_t *address(process *p, avmir_value a, uint64_t size, const char *message) {
+  if (a.tag != TAG_INT) fault(message);
+  if ((uint64_t)a.as.i > p->memory_size || p->memory_size - (uint64_t)a.as.i < size) {
+    char message[96];
+    snprintf(message, sizeof(message), "memory fault accessing %" PRIu64 " bytes at %" PRIu64 " of the process memory", size, (uint64_t)a.as.i);
+    fault(message);
+  }
+  return p->memory + a.as.i;
+}
+
+/* the read only chunks of the static data */
+static inline void check_writable(avmir_value a, uint64_t size) {
+  uint64_t start = (uint64_t)a.as.i;
+  for (size_t i = 0; i < READ_ONLY_CHUNKS; i++) {
+    if (read_only[i][0] < start + size && start < read_only[i][0] + read_only[i][1]) {
+      char message[96];
+      snprintf(message, sizeof(message), "memory fault accessing %" PRIu64 " bytes at %" PRIu64 " of the process memory, it is read only", size, start);
+      fault(message);
+    }
+  }
+}
+
+#define READ(name, type, make) \
+  static inline avmir_value name(process *p, avmir_value a) { \
+    type value; \
+    memcpy(&value, address(p, a, sizeof(type), "expecting: address :: int"), sizeof(type)); \
+    return make(value); \
+  }
+
+READ(read_int64, int64_t, INT)
+READ(read_int32, int32_t, INT)
+READ(read_int16, int16_t, INT)
+READ(read_int8, int8_t, INT)
+READ(read_float64, double, FLOAT)
+READ(read_float32, float, FLOAT)
+READ(read_uint32, uint32_t, INT)
+READ(read_uint16, uint16_t, INT)
+READ(read_uint8, uint8_t, INT)
+
+/* the bytes swapped to and from big endian, through an unsigned integer of the same width */
+#define READ_BE(name, type, bits, make) \
+  static inline avmir_value name(process *p, avmir_value a) { \
+    uint##bits##_t raw; \
+    memcpy(&raw, address(p, a, sizeof(type), "expecting: address :: int"), sizeof(type)); \
+    raw = __builtin_bswap##bits(raw); \
+    type value; \
+    memcpy(&value, &raw, sizeof(type)); \
+    return make(value); \
+  }
+
+READ_BE(read_int64_be, int64_t, 64, INT)
+READ_BE(read_int32_be, int32_t, 32, INT)
+READ_BE(read_uint32_be, uint32_t, 32, INT)
+READ_BE(read_int16_be, int16_t, 16, INT)
+READ_BE(read_uint16_be, uint16_t, 16, INT)
+READ_BE(read_float64_be, double, 64, FLOAT)
+READ_BE(read_float32_be, float, 32, FLOAT)
+
+#define WRITE(name, type, value_tag, field, message) \
+  static inline void name(process *p, avmir_value a, avmir_value b) { \
+    if (a.tag != TAG_INT || b.tag != value_tag) fault(message); \
+    type value = (type)b.as.field; \
+    uint8_t *target = address(p, a, sizeof(type), message); \
+    check_writable(a, sizeof(type)); \
+    memcpy(target, &value, sizeof(type)); \
+  }
+
+WRITE(write_int64, int64_t, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE(write_int32, int32_t, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE(write_int16, int16_t, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE(write_int8, int8_t, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE(write_float64, double, TAG_FLOAT, f, "expecting: address :: int, value :: float")
+WRITE(write_float32, float, TAG_FLOAT, f, "expecting: address :: int, value :: float")
+
+#define WRITE_BE(name, type, bits, value_tag, field, message) \
+  static inline void name(process *p, avmir_value a, avmir_value b) { \
+    if (a.tag != TAG_INT || b.tag != value_tag) fault(message); \
+    type value = (type)b.as.field; \
+    uint##bits##_t raw; \
+    memcpy(&raw, &value, sizeof(type)); \
+    raw = __builtin_bswap##bits(raw); \
+    uint8_t *target = address(p, a, sizeof(type), message); \
+    check_writable(a, sizeof(type)); \
+    memcpy(target, &raw, sizeof(type)); \
+  }
+
+WRITE_BE(write_int64_be, int64_t, 64, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE_BE(write_int32_be, int32_t, 32, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE_BE(write_int16_be, int16_t, 16, TAG_INT, i, "expecting: address :: int, value :: int")
+WRITE_BE(write_float64_be, double, 64, TAG_FLOAT, f, "expecting: address :: int, value :: float")
+WRITE_BE(write_float32_be, float, 32, TAG_FLOAT, f, "expecting: address :: int, value :: float")
+
+/* the process memory selected by a unit operand, -1, shared units are not supported */
+static inline void process_unit(avmir_value unit, const char *message) {
+  if (unit.tag != TAG_INT || unit.as.i < -1) fault(message);
+  if (unit.as.i != -1) fault("shared memory units are not supported");
+}
+
+/* the length popped after the other arguments */
+static inline uint64_t length(avmir_value value, const char *message) {
+  if (value.tag != TAG_INT || value.as.i < 0) fault(message);
+  return (uint64_t)value.as.i;
+}
+
+static inline void mem_copy(process *p, avmir_value a, avmir_value b) {
+  const char *message = "expecting: unit :: int >= -1, address :: int, source :: int >= -1, from :: int, length :: int >= 0";
+  avmir_value source = pop(p), from = pop(p);
+  uint64_t size = length(pop(p), message);
+  process_unit(a, message);
+  process_unit(source, message);
+  if (b.tag != TAG_INT) fault(message);
+  const uint8_t *bytes = address(p, from, size, message);
+  uint8_t *target = address(p, b, size, message);
+  check_writable(b, size);
+  memmove(target, bytes, size);
+}
+
+static inline void mem_fill(process *p, avmir_value a, avmir_value b) {
+  const char *message = "expecting: unit :: int >= -1, address :: int, byte :: int, length :: int >= 0";
+  avmir_value byte = pop(p);
+  uint64_t size = length(pop(p), message);
+  process_unit(a, message);
+  if (b.tag != TAG_INT || byte.tag != TAG_INT) fault(message);
+  uint8_t *target = address(p, b, size, message);
+  check_writable(b, size);
+  memset(target, (uint8_t)byte.as.i, size);
+}
+
+static inline avmir_value mem_compare(process *p, avmir_value a, avmir_value b) {
+  const char *message = "expecting: unit :: int >= -1, address :: int, other_unit :: int >= -1, other :: int, length :: int >= 0";
+  avmir_value other_unit = pop(p), other = pop(p);
+  uint64_t size = length(pop(p), message);
+  process_unit(a, message);
+  process_unit(other_unit, message);
+  if (b.tag != TAG_INT || other.tag != TAG_INT) fault(message);
+  int order = memcmp(address(p, b, size, message), address(p, other, size, message), size);
+  return INT(order < 0 ? -1 : order > 0);
+}
+
+static inline avmir_value mem_find(process *p, avmir_value a, avmir_value b) {
+  const char *message = "expecting: unit :: int >= -1, address :: int, length :: int >= 0, byte :: int";
+  uint64_t size = length(pop(p), message);
+  avmir_value byte = pop(p);
+  process_unit(a, message);
+  if (b.tag != TAG_INT || byte.tag != TAG_INT) fault(message);
+  const uint8_t *bytes = address(p, b, size, message);
+  const uint8_t *found = memchr(bytes, (uint8_t)byte.as.i, size);
+  return INT(found ? (int64_t)(found - bytes) : -1);
+}
+
+static inline int jump(avmir_value a, avmir_value b) {
+  if (a.tag != TAG_INT || b.tag != TAG_INT) fault("expecting: pc :: int, cond :: int");
+  return b.as.i != 0;
+}
+
+/* the label of an instruction, past the end is the end of the program */
+static inline uint64_t target(avmir_value a) {
+  return (uint64_t)a.as.i < INSTRUCTIONS ? (uint64_t)a.as.i : INSTRUCTIONS;
+}
+
+static inline void sleep_millis(avmir_value a) {
+  if (a.tag != TAG_INT) fault("expecting: millis :: int");
+  uint64_t millis = (uint64_t)a.as.i;
+  struct timespec time = { .tv_sec = (time_t)(millis / 1000), .tv_nsec = (long)(millis % 1000) * 1000000 };
+  while (nanosleep(&time, &time) == -1 && errno == EINTR);
+}
+
+/* the initial memory of the process and an empty heap after it */
+static inline void new_memory(process *p) {
+  p->memory = calloc(MEMORY_SIZE ? MEMORY_SIZE : 1, 1);
+  if (!p->memory) fault("out of memory");
+  memcpy(p->memory, static_data, STATIC_DATA_SIZE);
+  p->memory_size = MEMORY_SIZE;
+  p->heap_top = (MEMORY_SIZE + HEAP_ALIGN - 1) / HEAP_ALIGN * HEAP_ALIGN;
+  p->allocated = (heap_blocks){ 0 };
+  p->freed = (heap_blocks){ 0 };
+}
+
+/* index of the first block at or after the address */
+static inline size_t find_block(heap_blocks *blocks, uint64_t address) {
+  size_t i = 0;
+  while (i < blocks->len && blocks->items[i].address < address) i++;
+  return i;
+}
+
+static inline void insert_block(heap_blocks *blocks, size_t i, uint64_t address, uint64_t size) {
+  if (blocks->len == blocks->capacity) {
+    blocks->capacity = blocks->capacity ? blocks->capacity * 2 : 16;
+    blocks->items = realloc(blocks->items, blocks->capacity * sizeof(*blocks->items));
+    if (!blocks->items) fault("out of memory");
+  }
+  memmove(blocks->items + i + 1, blocks->items + i, (blocks->len - i) * sizeof(*blocks->items));
+  blocks->items[i].address = address;
+  blocks->items[i].size = size;
+  blocks->len++;
+}
+
+static inline void remove_block(heap_blocks *blocks, size_t i) {
+  memmove(blocks->items + i, blocks->items + i + 1, (blocks->len - i - 1) * sizeof(*blocks->items));
+  blocks->len--;
+}
+
+/* the allocation does not fit below the end of the heap */
+static inline void out_of_memory(uint64_t size) {
+  char message[96];
+  snprintf(message, sizeof(message), "allocation of %" PRIu64 " bytes, the heap can not grow past the address %" PRIu64, size, HEAP_LIMIT);
+  fault(message);
+}
+
+/* the first free block that fits, a free block at the end or the end of the heap */
+static inline uint64_t heap_alloc(process *p, uint64_t requested) {
+  if (requested > HEAP_LIMIT) out_of_memory(requested);
+  uint64_t size = (requested ? requested + HEAP_ALIGN - 1 : HEAP_ALIGN) / HEAP_ALIGN * HEAP_ALIGN;
+  uint64_t address = p->heap_top;
+  size_t i = 0;
+  while (i < p->freed.len && p->freed.items[i].size < size) i++;
+  if (i < p->freed.len) {
+    address = p->freed.items[i].address;
+    if (p->freed.items[i].size > size) {
+      p->freed.items[i].address += size;
+      p->freed.items[i].size -= size;
+    } else {
+      remove_block(&p->freed, i);
+    }
+  } else {
+    if (p->freed.len > 0 && p->freed.items[p->freed.len - 1].address + p->freed.items[p->freed.len - 1].size == p->heap_top) {
+      address = p->freed.items[--p->freed.len].address;
+    }
+    if (address + size > HEAP_LIMIT) out_of_memory(requested);
+    p->heap_top = address + size;
+    if (p->heap_top > p->memory_size) {
+      p->memory = realloc(p->memory, p->heap_top);
+      if (!p->memory) fault("out of memory");
+      memset(p->memory + p->memory_size, 0, p->heap_top - p->memory_size);
+      p->memory_size = p->heap_top;
+    }
+  }
+  insert_block(&p->allocated, find_block(&p->allocated, address), address, size);
+  return address;
+}
+
+/* the index of the allocation, faulting on a double free or an address never allocated */
+static inline size_t allocation(process *p, uint64_t address) {
+  size_t i = find_block(&p->allocated, address);
+  if (i < p->allocated.len && p->allocated.items[i].address == address) return i;
+  size_t j = find_block(&p->freed, address + 1);
+  int freed = j > 0 && address < p->freed.items[j - 1].address + p->freed.items[j - 1].size;
+  char message[96];
+  if (freed) {
+    snprintf(message, sizeof(message), "double free of the address %" PRIu64, address);
+  } else {
+    snprintf(message, sizeof(message), "free of the address %" PRIu64 ", it is not an allocation", address);
+  }
+  fault(message);
+  return 0;
+}
+
+static inline void heap_free(process *p, uint64_t address) {
+  size_t i = allocation(p, address);
+  uint64_t start = address, end = address + p->allocated.items[i].size;
+  remove_block(&p->allocated, i);
+  size_t j = find_block(&p->freed, address);
+  if (j < p->freed.len && p->freed.items[j].address == end) {
+    end += p->freed.items[j].size;
+    remove_block(&p->freed, j);
+  }
+  if (j > 0 && p->freed.items[j - 1].address + p->freed.items[j - 1].size == start) {
+    start = p->freed.items[--j].address;
+    remove_block(&p->freed, j);
+  }
+  insert_block(&p->freed, j, start, end - start);
+}
+
+static inline avmir_value alloc(process *p, avmir_value a) {
+  if (a.tag != TAG_INT || a.as.i < 0) fault("expecting: size :: int >= 0");
+  return INT((int64_t)heap_alloc(p, (uint64_t)a.as.i));
+}
+
+static inline void free_allocation(process *p, avmir_value a) {
+  if (a.tag != TAG_INT) fault("expecting: address :: int");
+  heap_free(p, (uint64_t)a.as.i);
+}
+
+static inline avmir_value reallocate(process *p, avmir_value a, avmir_value b) {
+  if (a.tag != TAG_INT || b.tag != TAG_INT || b.as.i < 0) fault("expecting: address :: int, size :: int >= 0");
+  uint64_t address = (uint64_t)a.as.i, size = (uint64_t)b.as.i;
+  uint64_t old = p->allocated.items[allocation(p, address)].size;
+  uint64_t moved = heap_alloc(p, size);
+  memmove(p->memory + moved, p->memory + address, old < size ? old : size);
+  heap_free(p, address);
+  return INT((int64_t)moved);
+}
+
+static inline void release(process *p) {
+  free(p->memory);
+  free(p->allocated.items);
+  free(p->freed.items);
+  free(p->invoke_target);
+  free(p);
+}
+
+static inline void spawn(process *p) {
+  if (p->pc >= INSTRUCTIONS) {
+    release(p);
+    return;
+  }
+  pthread_mutex_lock(&active_lock);
+  active++;
+  p->pid = pid_counter++;
+  pthread_mutex_unlock(&active_lock);
+
+  pthread_t thread;
+  if (pthread_create(&thread, NULL, run, p)) fault("error creating thread");
+  pthread_detach(thread);
+}
+
+static inline void end_process(process *p) {
+  if (p->allocated.len > 0) {
+    uint64_t bytes = 0;
+    for (size_t i = 0; i < p->allocated.len; i++) bytes += p->allocated.items[i].size;
+    fflush(stdout);
+    fprintf(stderr, "process %" PRId64 " leaked %" PRIu64 " bytes in %zu allocations\n", p->pid, bytes, p->allocated.len);
+  }
+  release(p);
+  pthread_mutex_lock(&active_lock);
+  active--;
+  pthread_cond_broadcast(&process_ended);
+  pthread_mutex_unlock(&active_lock);
+}
+
+static inline void set_invoke_target(process *p, const char *name, size_t size) {
+  free(p->invoke_target);
+  p->invoke_target = malloc(size + 1);
+  if (!p->invoke_target) fault("out of memory");
+  memcpy(p->invoke_target, name, size);
+  p->invoke_target[size] = '\0';
+  p->invoke_size = size;
+}
+
+/* a clone of the process with a new memory */
+static inline void fork_process(process *p, avmir_value a) {
+  if (a.tag != TAG_INT) fault("expecting: pc :: int");
+  process *forked = malloc(sizeof(process));
+  if (!forked) fault("out of memory");
+  *forked = *p;
+  forked->pc = target(a);
+  new_memory(forked);
+  forked->invoke_target = NULL;
+  if (p->invoke_target) set_invoke_target(forked, p->invoke_target, p->invoke_size);
+  spawn(forked);
+}
+
+static inline void prepare_invoke(process *p, avmir_value a, avmir_value b) {
+  const char *message = "expecting: address :: int, size :: int";
+  if (b.tag != TAG_INT) fault(message);
+  set_invoke_target(p, (const char *)address(p, a, (uint64_t)b.as.i, message), (size_t)b.as.i);
+}
+
+static inline void invoke(process *p) {
+  if (is_set(p->registers[FLAG_INVOKE_TRAP])) fault("trap functions are not supported");
+
+  char *symbol = malloc(sizeof(FFI_PREFIX) + p->invoke_size);
+  if (!symbol) fault("out of memory");
+  memcpy(symbol, FFI_PREFIX, sizeof(FFI_PREFIX) - 1);
+  if (p->invoke_target) memcpy(symbol + sizeof(FFI_PREFIX) - 1, p->invoke_target, p->invoke_size);
+  symbol[sizeof(FFI_PREFIX) - 1 + p->invoke_size] = '\0';
+
+  avmir_function function = NULL;
+  for (size_t i = 0; libraries[i] && !function; i++) {
+    *(void **)&function = dlsym(handles[i], symbol);
+  }
+  free(symbol);
+  if (!function) fault("unable to find symbol");
+
+  avmir_value result;
+  if (function(p->registers, p->memory, p->memory_size, &result)) push(p, result);
+}
+
+/* like the debug format of the interpreter: the shortest digits reading back the same, with an exponent out of [1e-4, 1e16) */
+static inline void print_float(double x) {
+  if (isnan(x)) {
+    printf("NaN");
+    return;
+  }
+  if (isinf(x)) {
+    printf(x > 0 ? "inf" : "-inf");
+    return;
+  }
+  char buffer[32];
+  int precision = 0;
+  snprintf(buffer, sizeof(buffer), "%.*e", precision, x);
+  while (strtod(buffer, NULL) != x && precision < 16) {
+    snprintf(buffer, sizeof(buffer), "%.*e", ++precision, x);
+  }
+  char *exponent = strchr(buffer, 'e');
+  int power = atoi(exponent + 1);
+  if (x != 0 && (fabs(x) < 1e-4 || fabs(x) >= 1e16)) {
+    *exponent = '\0';
+    printf("%se%d", buffer, power);
+  } else {
+    printf("%.*f", precision - power > 1 ? precision - power : 1, x);
+  }
+}
+
+static inline void debug(process *p) {
+  printf("[");
+  for (size_t i = 0; i < p->sp; i++) {
+    if (i > 0) printf(", ");
+    if (p->stack[i].tag == TAG_INT) {
+      printf("Int(%" PRId64 ")", p->stack[i].as.i);
+    } else {
+      printf("Float(");
+      print_float(p->stack[i].as.f);
+      printf(")");
+    }
+  }
+  printf("]\n");
+  fflush(stdout);
+}
+
+int main(void) {
+  for (size_t i = 0; libraries[i]; i++) {
+    handles[i] = dlopen(libraries[i], RTLD_NOW | RTLD_LOCAL);
+    if (!handles[i]) {
+      fprintf(stderr, "%s\n", dlerror());
+      return 1;
+    }
+  }
+
+  process *p = calloc(1, sizeof(process));
+  if (!p) fault("out of memory");
+  new_memory(p);
+  spawn(p);
+
+  pthread_mutex_lock(&active_lock);
+  while (active > 0) pthread_cond_wait(&process_ended, &active_lock);
+  pthread_mutex_unlock(&active_lock);
+  return 0;
+}
@@ -1,2 +1,3 @@
 pub mod vm;
-pub mod parser;
\ No newline at end of file
+pub mod parser;
+pub mod backend;
\ No newline at end of file
@@ -0,0 +1,334 @@
+//! Language server for v2 sources over stdio
+//!
+//! Provides diagnostics, hover docs for opcodes and tags, completion of opcodes and tags,
+//! go to definition, find references and rename of tags
+
+use std::{collections::HashMap, str::FromStr};
+
+use lsp_server::{Connection, ExtractError, Message, Notification, ProtocolError, Request, RequestId, Response};
+use lsp_types::{
+  notification::{DidChangeTextDocument, DidCloseTextDocument, DidOpenTextDocument, Notification as _, PublishDiagnostics},
+  request::{Completion, GotoDefinition, HoverRequest, References, Rename, Request as _},
+  CompletionItem, CompletionItemKind, CompletionOptions, CompletionResponse, Diagnostic, DiagnosticSeverity,
+  GotoDefinitionResponse, Hover, HoverContents, HoverProviderCapability, Location, MarkupContent, MarkupKind,
+  OneOf, Position, PublishDiagnosticsParams, Range, ServerCapabilities, TextDocumentSyncCapability,
+  TextDocumentSyncKind, TextEdit, Url, WorkspaceEdit
+};
+use strum::{EnumMessage, VariantNames};
+use thiserror::Error;
+
+use crate::{parser::{diagnostic::Span, v2::{self, SourceIndex, Tag}}, vm::program::Opcode};
+
+#[derive(Debug, Error)]
+pub enum LspError {
+  #[error("{0}")]
+  Protocol(#[from] ProtocolError),
+
+  #[error("{0}")]
+  Json(#[from] serde_json::Error),
+
+  #[error("bad request: {0}")]
+  BadRequest(String),
+
+  #[error("{0}")]
+  Io(#[from] std::io::Error),
+
+  #[error("client disconnected")]
+  Disconnected
+}
+
+impl<T> From<ExtractError<T>> for LspError {
+  fn from(value: ExtractError<T>) -> Self {
+    match value {
+      ExtractError::JsonError { method, error } => LspError::BadRequest(format!("{}: {}", method, error)),
+      ExtractError::MethodMismatch(_) => LspError::BadRequest("method mismatch".into())
+    }
+  }
+}
+
+fn position(source: &str, line: usize, column: usize) -> Position {
+  let text = source.lines().nth(line).unwrap_or_default();
+  let character = text.get(..column).unwrap_or(text).encode_utf16().count();
+  Position::new(line as u32, character as u32)
+}
+
+/// The line and byte column of a position
+fn offset(source: &str, position: Position) -> (usize, usize) {
+  let text = source.lines().nth(position.line as usize).unwrap_or_default();
+  let mut units = 0;
+  for (idx, c) in text.char_indices() {
+    if units >= position.character as usize {
+      return (position.line as usize, idx)
+    }
+    units += c.len_utf16();
+  }
+  (position.line as usize, text.len())
+}
+
+fn range(source: &str, span: Span) -> Range {
+  Range::new(position(source, span.line, span.column), position(source, span.line, span.column + span.length))
+}
+
+fn contains(span: &Span, (line, column): (usize, usize)) -> bool {
+  span.line == line && span.column <= column && column <= span.column + span.length
+}
+
+/// The word under the position and the character before it
+fn word(source: &str, (line, column): (usize, usize)) -> (String, Option<char>) {
+  let text = source.lines().nth(line).unwrap_or_default();
+  let is_word = |c: char| !c.is_whitespace() && !matches!(c, '$' | '@' | '^' | ':' | '#');
+  let start = text[..column.min(text.len())].rfind(|c: char| !is_word(c)).map(|x| x + 1).unwrap_or(0);
+  let end = text[start..].find(|c: char| !is_word(c)).map(|x| x + start).unwrap_or(text.len());
+  (text[start..end].into(), text[..start].chars().last())
+}
+
+fn describe_tag(tag: &Tag) -> String {
+  match tag {
+    Tag::Memory { address, size } => format!("memory chunk, address: {}, size: {}", address, size),
+    Tag::Instruction { line } => format!("instruction: {}", line),
+    Tag::Import => "imported tag".into()
+  }
+}
+
+fn describe_opcode(opcode: Opcode) -> String {
+  format!(
+    "**{}** `{}`\n\n{}",
+    opcode, opcode.get_message().unwrap_or_default(), opcode.get_detailed_message().unwrap_or_default()
+  )
+}
+
+struct Document {
+  source: String,
+  index: SourceIndex
+}
+
+impl Document {
+  fn new(source: String) -> Self {
+    let index = v2::Simple::index(&source);
+    Document { source, index }
+  }
+
+  fn tag_at(&self, position: Position) -> Option<String> {
+    let offset = offset(&self.source, position);
+    self.index.definitions.iter().filter(|x| x.span.length > 0).map(|x| (&x.name, &x.span))
+      .chain(self.index.references.iter().map(|x| (&x.name, &x.span)))
+      .find(|(_, span)| contains(span, offset))
+      .map(|(name, _)| name.clone())
+  }
+
+  fn locations(&self, uri: &Url, name: &str, definitions: bool, references: bool) -> Vec<Location> {
+    let definitions = self.index.definitions.iter()
+      .filter(|x| definitions && x.name == name)
+      .map(|x| x.span);
+    let references = self.index.references.iter()
+      .filter(|x| references && x.name == name)
+      .map(|x| x.span);
+    definitions.chain(references).map(|span| Location::new(uri.clone(), range(&self.source, span))).collect()
+  }
+
+  fn diagnostics(&self) -> Vec<Diagnostic> {
+    self.index.errors.iter().map(|err| Diagnostic {
+      range: range(&self.source, err.span),
+      severity: Some(DiagnosticSeverity::ERROR),
+      source: Some("avmir".into()),
+      message: err.error.to_string(),
+      ..Default::default()
+    }).collect()
+  }
+
+  fn hover(&self, position: Position) -> Option<Hover> {
+    let text = match self.tag_at(position) {
+      Some(name) => {
+        let definition = self.index.definitions.iter().find(|x| x.name == name)?;
+        format!("**{}** {}", name, describe_tag(&definition.tag))
+      }
+      None => describe_opcode(Opcode::from_str(&word(&self.source, offset(&self.source, position)).0).ok()?)
+    };
+    Some(Hover {
+      contents: HoverContents::Markup(MarkupContent { kind: MarkupKind::Markdown, value: text }),
+      range: None
+    })
+  }
+
+  fn completion(&self, position: Position) -> Vec<CompletionItem> {
+    match word(&self.source, offset(&self.source, position)).1 {
+      Some(access @ ('$' | '@' | '^')) => self.index.definitions.iter()
+        .filter(|x| access == '$' || !matches!(x.tag, Tag::Instruction { .. }))
+        .map(|x| CompletionItem {
+          label: x.name.clone(),
+          kind: Some(CompletionItemKind::VARIABLE),
+          detail: Some(describe_tag(&x.tag)),
+          ..Default::default()
+        }).collect(),
+      _ => Opcode::VARIANTS.iter().filter_map(|x| Opcode::from_str(x).ok()).map(|opcode| CompletionItem {
+        label: opcode.to_string(),
+        kind: Some(CompletionItemKind::KEYWORD),
+        detail: opcode.get_message().map(String::from),
+        documentation: opcode.get_detailed_message().map(|x| lsp_types::Documentation::String(x.into())),
+        ..Default::default()
+      }).collect()
+    }
+  }
+
+  fn rename(&self, uri: &Url, position: Position, new_name: &str) -> Result<Option<WorkspaceEdit>, LspError> {
+    if new_name.is_empty() || new_name.contains(|c: char| c.is_whitespace() || matches!(c, '$' | '@' | '^' | ':' | '#' | ';')) {
+      return Err(LspError::BadRequest(format!("not a valid tag: {}", new_name)))
+    }
+    let Some(name) = self.tag_at(position) else {
+      return Ok(None)
+    };
+    // implicit tags are the index of their line, with no text to rename the definition
+    if !self.index.definitions.iter().any(|x| x.name == name && x.span.length > 0) {
+      return Err(LspError::BadRequest(format!("{} is not written as a tag, write it on its line to rename it", name)))
+    }
+    // the file exporting it is not known, renaming it here only would break the link
+    if self.index.definitions.iter().any(|x| x.name == name && matches!(x.tag, Tag::Import)) {
+      return Err(LspError::BadRequest(format!("{} is imported, rename it where it is exported", name)))
+    }
+    let edits = self.index.definitions.iter()
+      .filter(|x| x.name == name && x.span.length > 0)
+      .map(|x| x.span)
+      .chain(self.index.references.iter().filter(|x| x.name == name).map(|x| x.span))
+      .map(|span| TextEdit::new(range(&self.source, span), new_name.into()))
+      .collect();
+    Ok(Some(WorkspaceEdit::new(HashMap::from([(uri.clone(), edits)]))))
+  }
+}
+
+struct Server {
+  connection: Connection,
+  documents: HashMap<Url, Document>
+}
+
+impl Server {
+  fn send(&self, message: impl Into<Message>) -> Result<(), LspError> {
+    self.connection.sender.send(message.into()).map_err(|_| LspError::Disconnected)
+  }
+
+  fn update(&mut self, uri: Url, source: String) -> Result<(), LspError> {
+    let document = Document::new(source);
+    let params = PublishDiagnosticsParams::new(uri.clone(), document.diagnostics(), None);
+    self.documents.insert(uri, document);
+    self.send(Notification::new(PublishDiagnostics::METHOD.into(), params))
+  }
+
+  fn document(&self, uri: &Url) -> Result<&Document, LspError> {
+    self.documents.get(uri).ok_or_else(|| LspError::BadRequest(format!("document not open: {}", uri)))
+  }
+
+  fn handle_notification(&mut self, notification: Notification) -> Result<(), LspError> {
+    match notification.method.as_str() {
+      DidOpenTextDocument::METHOD => {
+        let params: lsp_types::DidOpenTextDocumentParams = serde_json::from_value(notification.params)?;
+        self.update(params.text_document.uri, params.text_document.text)
+      }
+      DidChangeTextDocument::METHOD => {
+        let mut params: lsp_types::DidChangeTextDocumentParams = serde_json::from_value(notification.params)?;
+        match params.content_changes.pop() { // full synchronization, the last change is the whole document
+          Some(change) => self.update(params.text_document.uri, change.text),
+          None => Ok(())
+        }
+      }
+      DidCloseTextDocument::METHOD => {
+        let params: lsp_types::DidCloseTextDocumentParams = serde_json::from_value(notification.params)?;
+        self.documents.remove(&params.text_document.uri);
+        Ok(())
+      }
+      _ => Ok(())
+    }
+  }
+
+  fn handle_request(&self, request: Request) -> Result<Response, LspError> {
+    let id = request.id.clone();
+    let result = match request.method.as_str() {
+      HoverRequest::METHOD => {
+        let (_, params) = request.extract::<lsp_types::HoverParams>(HoverRequest::METHOD)?;
+        let target = params.text_document_position_params;
+        serde_json::to_value(self.document(&target.text_document.uri)?.hover(target.position))?
+      }
+      Completion::METHOD => {
+        let (_, params) = request.extract::<lsp_types::CompletionParams>(Completion::METHOD)?;
+        let target = params.text_document_position;
+        let items = self.document(&target.text_document.uri)?.completion(target.position);
+        serde_json::to_value(CompletionResponse::Array(items))?
+      }
+      GotoDefinition::METHOD => {
+        let (_, params) = request.extract::<lsp_types::GotoDefinitionParams>(GotoDefinition::METHOD)?;
+        let target = params.text_document_position_params;
+        let document = self.document(&target.text_document.uri)?;
+        let locations = document.tag_at(target.position)
+          .map(|name| document.locations(&target.text_document.uri, &name, true, false))
+          .unwrap_or_default();
+        serde_json::to_value(GotoDefinitionResponse::Array(locations))?
+      }
+      References::METHOD => {
+        let (_, params) = request.extract::<lsp_types::ReferenceParams>(References::METHOD)?;
+        let target = params.text_document_position;
+        let document = self.document(&target.text_document.uri)?;
+        let locations = document.tag_at(target.position)
+          .map(|name| document.locations(&target.text_document.uri, &name, params.context.include_declaration, true))
+          .unwrap_or_default();
+        serde_json::to_value(locations)?
+      }
+      Rename::METHOD => {
+        let (_, params) = request.extract::<lsp_types::RenameParams>(Rename::METHOD)?;
+        let target = params.text_document_position;
+        let edit = self.document(&target.text_document.uri)?.rename(&target.text_document.uri, target.position, &params.new_name)?;
+        serde_json::to_value(edit)?
+      }
+      method => return Ok(Response::new_err(
+        id, lsp_server::ErrorCode::MethodNotFound as i32, format!("method not supported: {}", method)
+      ))
+    };
+    Ok(Response::new_ok(id, result))
+  }
+
+  fn run(&mut self) -> Result<(), LspError> {
+    for message in self.connection.receiver.clone().iter() {
+      match message {
+        Message::Request(request) => {
+          if self.connection.handle_shutdown(&request)? {
+            return Ok(())
+          }
+          let id = request.id.clone();
+          let response = self.handle_request(request).unwrap_or_else(|err| error_response(id, err));
+          self.send(response)?;
+        }
+        Message::Notification(notification) => self.handle_notification(notification)?,
+        Message::Response(_) => ()
+      }
+    }
+    Ok(())
+  }
+}
+
+fn error_response(id: RequestId, err: LspError) -> Response {
+  Response::new_err(id, lsp_server::ErrorCode::InvalidParams as i32, err.to_string())
+}
+
+fn capabilities() -> ServerCapabilities {
+  ServerCapabilities {
+    text_document_sync: Some(TextDocumentSyncCapability::Kind(TextDocumentSyncKind::FULL)),
+    hover_provider: Some(HoverProviderCapability::Simple(true)),
+    completion_provider: Some(CompletionOptions {
+      trigger_characters: Some(vec!["$".into(), "@".into(), "^".into()]),
+      ..Default::default()
+    }),
+    definition_provider: Some(OneOf::Left(true)),
+    references_provider: Some(OneOf::Left(true)),
+    rename_provider: Some(OneOf::Left(true)),
+    ..Default::default()
+  }
+}
+
+/// Run the language server until the client asks for shutdown
+pub fn serve() -> Result<(), LspError> {
+  let (connection, io_threads) = Connection::stdio();
+  connection.initialize(serde_json::to_value(capabilities())?)?;
+
+  let mut server = Server { connection, documents: HashMap::new() };
+  server.run()?;
+  drop(server);
+
+  Ok(io_threads.join()?)
+}
@@ -1,43 +1,110 @@
-use std::{fs::{self, OpenOptions}, io};
+use std::{fs, io, process::ExitCode};
 
 use clap::Parser as ArgsParser;
-use memmap2::MmapOptions;
 use thiserror::Error;
-use vm::{ffi::{FFIError, FFILoader}, machine::MachineBuilder, program::Program};
+use vm::{
+  checkpoint::{Checkpoint, CheckpointError}, ffi::{FFIError, FFILoader}, machine::MachineBuilder, mapped::{MapOptions, MappedFile, MappedFileError},
+  memory::{Access, Memory}, optimizer, program::{Program, UnknownUnitsError}
+};
 
-use crate::{parser::{v2, Parser}, vm::machine::Machine};
+use crate::{
+  parser::{linker::{Linker, LinkerError}, format, migrate::{self, MigrationError}, forth::Forth, structured::Structured, v2, wat::Wat, Parser},
+  vm::machine::Machine
+};
+
+#[cfg(unix)]
+use vm::shm::{SharedMemory, SharedMemoryError};
 
 pub mod vm;
 pub mod parser;
+pub mod backend;
 mod args;
+mod lsp;
 
 #[derive(Debug, Error)]
 enum RuntimeError {
   #[error("io error: {0}")]
   Fs(#[from] io::Error),
 
-  #[error("parse error: {0}")]
-  ParsingError(#[from] v2::SimpleParserError),
+  #[error("parse error:\n{0}")]
+  ParsingError(String),
+
+  #[error("link error: {0}")]
+  LinkerError(#[from] LinkerError),
+
+  #[error("migration error: {0}")]
+  MigrationError(#[from] MigrationError),
+
+  #[error("not formatted: {}", .0.join(", "))]
+  NotFormatted(Vec<String>),
+
+  #[error("language server error: {0}")]
+  LspError(#[from] lsp::LspError),
+
+  #[error("{0}")]
+  FFIError(#[from] FFIError),
+
+  #[error("the memory unit %{0} is configured twice")]
+  DuplicateUnit(String),
+
+  #[error("{0}")]
+  UnknownUnits(#[from] UnknownUnitsError),
+
+  #[error("{0}, the translated programs do not support memory units")]
+  UntranslatedUnits(UnknownUnitsError),
+
+  #[error("{0}")]
+  Checkpoint(#[from] CheckpointError),
+
+  #[error("processes ended by a fault: {0}")]
+  Faults(usize),
+
+  #[error("{0}")]
+  MappedFile(#[from] MappedFileError),
 
+  #[cfg(unix)]
   #[error("{0}")]
-  FFIError(#[from] FFIError)
+  SharedMemory(#[from] SharedMemoryError),
+
+  #[cfg(not(unix))]
+  #[error("{0} is not supported in this system")]
+  Unsupported(&'static str)
+}
+
+fn add_memory(builder: MachineBuilder, input: &args::MemoryInput, memory: impl Memory + 'static) -> MachineBuilder {
+  match &input.name {
+    Some(name) => builder.add_named_memory(name, memory, input.access),
+    None => builder.add_memory_with_access(memory, input.access)
+  }
 }
 
 fn config_machine(args: &args::Args, mut builder: MachineBuilder) -> Result<MachineBuilder, RuntimeError> {
-  for mem in args.memory.iter() {
-    builder = match mem {
-      args::MemoryInput::Virtual { size } => builder.add_memory(vec![0; *size]),
-      args::MemoryInput::FileMap { size, path } => {
-        let file = OpenOptions::new().read(true).write(true).create(true).open(path)?;
-        let size = *size as u64;
-        if file.metadata()?.len() < size {
-          file.set_len(size)?;
-        }
-        builder.add_memory(unsafe { MmapOptions::new().map_mut(&file)? })
+  for (idx, mem) in args.memory.iter().enumerate() {
+    if let Some(name) = mem.name.as_ref().filter(|name| args.memory[..idx].iter().any(|x| x.name.as_ref() == Some(name))) {
+      return Err(RuntimeError::DuplicateUnit(name.clone()))
+    }
+    builder = match &mem.source {
+      args::MemorySource::Zeroed => add_memory(builder, mem, vec![0; mem.size]),
+      #[cfg(unix)]
+      args::MemorySource::Shared(object) => add_memory(builder, mem, SharedMemory::open(object, mem.size)?),
+      #[cfg(not(unix))]
+      args::MemorySource::Shared(_) => return Err(RuntimeError::Unsupported("POSIX shared memory")),
+      args::MemorySource::File { path, offset, grow } => {
+        let options = MapOptions { offset: *offset, read_only: mem.access == Access::READ_ONLY, grow: *grow };
+        add_memory(builder, mem, MappedFile::open(path, mem.size, options)?)
       }
     }
   }
 
+  if let Some(dir) = &args.checkpoint_dir {
+    builder = builder.checkpoint_dir(dir);
+  }
+
+  #[cfg(feature = "jit")]
+  {
+    builder = builder.jit(args.jit);
+  }
+
   for lib in args.library.iter() {
     builder = builder.add_ffi_loader(unsafe { FFILoader::new(lib)? })
   }
@@ -45,29 +112,130 @@ fn config_machine(args: &args::Args, mut builder: MachineBuilder) -> Result<Mach
   Ok(builder)
 }
 
+fn migrate(input: &str, output: Option<&str>) -> Result<(), RuntimeError> {
+  let source = migrate::v1_to_v2(fs::read_to_string(input)?)?;
+  match output {
+    Some(path) => fs::write(path, source)?,
+    None => print!("{}", source)
+  }
+  Ok(())
+}
+
+fn fmt(files: &[String], check: bool) -> Result<(), RuntimeError> {
+  let mut not_formatted = vec![];
+  for file in files.iter() {
+    let content = fs::read_to_string(file)?;
+    let formatted = format::format(&content).map_err(|err| RuntimeError::ParsingError(err.render(file, &content)))?;
+    if formatted != content {
+      if check {
+        not_formatted.push(file.clone());
+      } else {
+        fs::write(file, formatted)?;
+      }
+    }
+  }
+  if not_formatted.is_empty() {
+    Ok(())
+  } else {
+    Err(RuntimeError::NotFormatted(not_formatted))
+  }
+}
+
+fn translate(
+  input: &str, output: Option<&str>, libraries: &[String], frontend: args::Frontend, optimize: bool
+) -> Result<(), RuntimeError> {
+  let mut program = load(input, frontend)?;
+  // there are no units to name in a translated program
+  program.resolve_units(|_| None).map_err(RuntimeError::UntranslatedUnits)?;
+  if optimize {
+    optimizer::optimize(&mut program);
+  }
+  let source = backend::c::translate(&program, libraries);
+  match output {
+    Some(path) => fs::write(path, source)?,
+    None => print!("{}", source)
+  }
+  Ok(())
+}
+
+/// Parse a file with the front-end given or chosen by its extension, see [`args::Frontend::of`]
+fn load(file: &str, frontend: args::Frontend) -> Result<Program, RuntimeError> {
+  let mut program = Program::with_name(file);
+  let content = fs::read_to_string(file)?;
+  let rendered = match frontend.of(file) {
+    args::Frontend::Structured => Structured::parse(&mut program, &content).map_err(|err| err.render(file, &content)),
+    args::Frontend::Forth => Forth::parse(&mut program, &content).map_err(|err| err.render(file, &content)),
+    args::Frontend::Wat => Wat::parse(&mut program, &content).map_err(|err| err.render(file, &content)),
+    _ => v2::Simple::parse(&mut program, &content).map_err(|err| err.render(file, &content))
+  };
+  rendered.map_err(RuntimeError::ParsingError)?;
+  Ok(program)
+}
+
 fn run() -> Result<(), RuntimeError> {
   let args = args::Args::parse();
+
+  match &args.command {
+    Some(args::Command::Migrate { input, output }) => return migrate(input, output.as_deref()),
+    Some(args::Command::Fmt { files, check }) => return fmt(files, *check),
+    Some(args::Command::Lsp) => return Ok(lsp::serve()?),
+    Some(args::Command::Translate { input, output, library, frontend, optimize }) =>
+      return translate(input, output.as_deref(), library, *frontend, *optimize),
+    None => ()
+  }
+
   let machine_builder = MachineBuilder::new();
   let mut machine: Machine = config_machine(&args, machine_builder)?.build();
+  if args.resume {
+    let checkpoints = args.files.iter().map(Checkpoint::load).collect::<Result<Vec<_>, _>>()?;
+    for checkpoint in checkpoints {
+      machine.resume(checkpoint)?;
+    }
+    return wait(&mut machine)
+  }
 
-  args.files.iter().map(|file| -> Result<Program, RuntimeError> {
-    let mut program = Program::with_name(&file);
-    let content = fs::read_to_string(file)?;
-    v2::Simple::parse(&mut program, content)?;
-    Ok(program)
-  }).collect::<Result<Vec<Program>, _>>()?.into_iter().for_each(|program| machine.launch(program));
-
-  machine.wait();
+  let mut programs = if args.link {
+    let linker = args.files.iter().try_fold(Linker::new(), |linker, file| -> Result<Linker, RuntimeError> {
+      let content = fs::read_to_string(file)?;
+      let module = v2::Simple::parse_module(file, &content)
+        .map_err(|err| RuntimeError::ParsingError(err.render(file, &content)))?;
+      Ok(linker.add_module(module))
+    })?;
+    let name = args.files.first().cloned().unwrap_or_default();
+    vec![linker.link(name)?]
+  } else {
+    args.files.iter().map(|file| load(file, args.frontend)).collect::<Result<Vec<Program>, _>>()?
+  };
+
+  // every program is checked before any runs
+  for program in programs.iter_mut() {
+    machine.resolve_units(program)?;
+    if args.optimize {
+      optimizer::optimize(program);
+    }
+  }
+  for program in programs {
+    machine.launch(program)?;
+  }
 
-  Ok(())
+  wait(&mut machine)
 }
 
-fn main() -> Result<(), RuntimeError> {
-  if let Err(err) = run() {
-
-    eprintln!("{}", err.to_string());
+/// Wait for every process, failing when any ended by a fault, the faults are already written
+fn wait(machine: &mut Machine) -> Result<(), RuntimeError> {
+  machine.wait();
+  match machine.faults() {
+    0 => Ok(()),
+    faults => Err(RuntimeError::Faults(faults))
+  }
+}
 
-    return Err(err)
+fn main() -> ExitCode {
+  match run() {
+    Ok(()) => ExitCode::SUCCESS,
+    Err(err) => {
+      eprintln!("{}", err);
+      ExitCode::FAILURE
+    }
   }
-  Ok(())
 }
@@ -0,0 +1,61 @@
+//! Source locations and rendering of the errors found while parsing
+
+use std::fmt::Display;
+
+/// Location in the source, both line and column (in bytes) start in 0
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Span {
+  pub line: usize,
+  pub column: usize,
+  pub length: usize
+}
+
+impl Span {
+  pub fn new(line: usize, column: usize, length: usize) -> Self {
+    Span { line, column, length }
+  }
+}
+
+/// Render a message with the source line it refers to and carets under the span
+pub fn render(name: &str, source: &str, span: Span, message: impl Display) -> String {
+  let line = source.lines().nth(span.line).unwrap_or_default();
+  let before = line.get(..span.column).unwrap_or(line);
+  let length = line.get(span.column..(span.column + span.length)).map(|x| x.chars().count()).unwrap_or(0);
+
+  let number = (span.line + 1).to_string();
+  let padding = " ".repeat(number.len());
+  let indent: String = before.chars().map(|x| if x == '\t' { '\t' } else { ' ' }).collect();
+
+  format!(
+    "error: {}\n{}--> {}:{}:{}\n{} |\n{} | {}\n{} | {}{}\n",
+    message, padding, name, span.line + 1, before.chars().count() + 1,
+    padding, number, line, padding, indent, "^".repeat(length.max(1))
+  )
+}
+
+fn distance(a: &str, b: &str) -> usize {
+  let b: Vec<char> = b.chars().collect();
+  let mut row: Vec<usize> = (0..=b.len()).collect();
+  for (i, x) in a.chars().enumerate() {
+    let mut previous = row[0];
+    row[0] = i + 1;
+    for (j, y) in b.iter().enumerate() {
+      let current = row[j + 1];
+      row[j + 1] = (row[j] + 1).min(current + 1).min(previous + usize::from(x != *y));
+      previous = current;
+    }
+  }
+  row[b.len()]
+}
+
+/// The candidates closest to the word, ignoring case, best first
+pub fn suggestions<'a>(word: &str, candidates: impl IntoIterator<Item = &'a str>) -> Vec<String> {
+  let word = word.to_lowercase();
+  let limit = (word.chars().count() / 3).max(2);
+  let mut found: Vec<_> = candidates.into_iter()
+    .map(|candidate| (distance(&word, &candidate.to_lowercase()), candidate))
+    .filter(|(distance, _)| *distance <= limit)
+    .collect();
+  found.sort();
+  found.into_iter().take(3).map(|(_, candidate)| candidate.to_string()).collect()
+}
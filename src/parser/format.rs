@@ -0,0 +1,258 @@
+//! Canonical formatting of v2 sources
+//!
+//! The source is validated with the v2 parser and then written again:
+//! - directives first, then the memory chunks and then the instructions, keeping their relative order
+//! - comments stay attached to the line below them
+//! - memory chunks and instruction tags aligned in columns
+//! - a single space between the opcode and every operand
+//!
+//! Tags that were implicit (the line index) are written when they are referenced, as the lines will move, and renumbered
+//! when they would be taken by the implicit tag of a memory chunk in its new line
+
+use std::collections::{HashMap, HashSet};
+
+use super::v2::{self, SimpleParserErrors};
+
+enum Statement {
+  Directive(Vec<String>),
+  Memory {
+    tag: String,
+    chunk: String,
+    comment: Option<String>
+  },
+  Instruction {
+    tag: Option<String>,
+    items: Vec<String>
+  }
+}
+
+struct Entry {
+  comments: Vec<String>,
+  blank_before: bool,
+  statement: Statement
+}
+
+/// Split a quoted chunk from a trailing comment, unquoted chunks are taken verbatim
+fn split_chunk(chunk: &str) -> (String, Option<String>) {
+  let Some(quoted) = chunk.strip_prefix('"') else {
+    return (chunk.into(), None)
+  };
+  let mut escaped = false;
+  for (idx, c) in quoted.char_indices() {
+    match (c, escaped) {
+      ('"', false) => {
+        let comment = quoted[(idx + 1)..].trim();
+        return (chunk[..(idx + 2)].into(), Some(comment.into()).filter(|x: &String| !x.is_empty()))
+      }
+      ('\\', false) => escaped = true,
+      _ => escaped = false
+    }
+  }
+  (chunk.into(), None) // unreachable once validated
+}
+
+fn parse_statement(idx: usize, line: &str) -> Statement {
+  let implicit = |tag: &str| match tag.trim() {
+    "" => idx.to_string(),
+    x => x.into()
+  };
+
+  if line.starts_with('.') {
+    return Statement::Directive(line.split_whitespace().map(String::from).collect())
+  }
+
+  if let Some(memory_idx) = line.find('#') {
+    let (chunk, comment) = split_chunk(&line[(memory_idx + 1)..]);
+    return Statement::Memory { tag: implicit(&line[..memory_idx]), chunk, comment }
+  }
+
+  let (tag, content) = match line.find(':') {
+    Some(tag_idx) => (Some(implicit(&line[..tag_idx])), &line[(tag_idx + 1)..]),
+    None => (None, line)
+  };
+  Statement::Instruction { tag, items: content.split_whitespace().map(String::from).collect() }
+}
+
+/// Rename the tags where they are defined and where they are operands
+fn rename(entries: &mut [Entry], renames: &HashMap<String, String>) {
+  for entry in entries.iter_mut() {
+    match &mut entry.statement {
+      Statement::Memory { tag, .. } => if let Some(renamed) = renames.get(tag) {
+        *tag = renamed.clone()
+      }
+      Statement::Instruction { tag, items } => {
+        if let Some(renamed) = tag.as_ref().and_then(|tag| renames.get(tag)) {
+          *tag = Some(renamed.clone())
+        }
+        for item in items.iter_mut().skip(1).filter(|x| x.starts_with(['$', '@', '^'])) {
+          if let Some(renamed) = renames.get(&item[1..]) {
+            *item = format!("{}{}", &item[..1], renamed)
+          }
+        }
+      }
+      Statement::Directive(items) if items[0] == ".export" => for item in items.iter_mut().skip(1) {
+        if let Some(renamed) = renames.get(item) {
+          *item = renamed.clone()
+        }
+      }
+      Statement::Directive(_) => ()
+    }
+  }
+}
+
+/// Format a v2 source, fails if the source can not be parsed
+pub fn format(source: impl AsRef<str>) -> Result<String, SimpleParserErrors> {
+  let source = source.as_ref();
+  v2::Simple::parse_module("", source)?;
+
+  let mut entries = vec![];
+  let mut comments = vec![];
+  let mut blank_before = false;
+  let mut previous_blank = false;
+
+  for (idx, line) in source.lines().enumerate() {
+    let line = line.trim_start();
+    match line.trim_end() {
+      "" => previous_blank = true,
+      comment if comment.starts_with(';') => {
+        blank_before |= comments.is_empty() && previous_blank;
+        comments.push(comment.to_string());
+        previous_blank = false;
+      }
+      _ => {
+        blank_before |= comments.is_empty() && previous_blank;
+        entries.push(Entry {
+          comments: std::mem::take(&mut comments),
+          blank_before,
+          statement: parse_statement(idx, line)
+        });
+        blank_before = false;
+        previous_blank = false;
+      }
+    }
+  }
+
+  // the memory chunks written without a tag take the index of their new line, the numeric tags kept move away from them
+  let output = render(&entries, &comments);
+  let implicit: HashSet<_> = output.lines().enumerate()
+    .filter(|(_, line)| line.trim_start().starts_with('#'))
+    .map(|(idx, _)| idx.to_string())
+    .collect();
+  let tags: HashSet<_> = entries.iter().filter_map(|entry| match &entry.statement {
+    Statement::Memory { tag, .. } | Statement::Instruction { tag: Some(tag), .. } => Some(tag.clone()),
+    _ => None
+  }).collect();
+  let referenced = referenced(&entries);
+  let mut free = (0..).map(|idx: usize| idx.to_string()).filter(|tag| !implicit.contains(tag) && !tags.contains(tag));
+  let renames: HashMap<_, _> = tags.iter()
+    .filter(|tag| implicit.contains(*tag) && referenced.contains(*tag))
+    .map(|tag| (tag.clone(), free.next().unwrap()))
+    .collect();
+  if renames.is_empty() {
+    return Ok(output)
+  }
+  rename(&mut entries, &renames);
+  Ok(render(&entries, &comments))
+}
+
+/// The tags used as operands or exported
+fn referenced(entries: &[Entry]) -> HashSet<String> {
+  entries.iter().flat_map(|entry| match &entry.statement {
+    Statement::Instruction { items, .. } => items.iter().skip(1)
+      .filter(|x| x.starts_with(['$', '@', '^']))
+      .map(|x| x[1..].to_string())
+      .collect(),
+    Statement::Directive(items) if items[0] == ".export" => items[1..].to_vec(),
+    _ => vec![]
+  }).collect()
+}
+
+/// Write the entries and the comments after the last one
+fn render(entries: &[Entry], comments: &[String]) -> String {
+  // only the implicit tags used as operands are kept
+  let referenced = referenced(entries);
+  let explicit = |tag: &String| !tag.chars().all(|x| x.is_ascii_digit()) || referenced.contains(tag);
+  let explicit_tag = |statement: &Statement| match statement {
+    Statement::Memory { tag, .. } | Statement::Instruction { tag: Some(tag), .. } if explicit(tag) => Some(tag.clone()),
+    _ => None
+  };
+
+  let directives: Vec<_> = entries.iter().filter(|x| matches!(x.statement, Statement::Directive(_))).collect();
+  let memory: Vec<_> = entries.iter().filter(|x| matches!(x.statement, Statement::Memory { .. })).collect();
+  let instructions: Vec<_> = entries.iter().filter(|x| matches!(x.statement, Statement::Instruction { .. })).collect();
+
+  let tag_width = |group: &[&Entry], suffix: usize| group.iter()
+    .filter_map(|entry| explicit_tag(&entry.statement))
+    .map(|tag| tag.chars().count() + suffix)
+    .max().unwrap_or(0);
+  let memory_width = tag_width(&memory, 0);
+  let instruction_width = tag_width(&instructions, 1);
+
+  let memory_lines: Vec<_> = memory.iter().map(|entry| match (&entry.statement, memory_width) {
+    (Statement::Memory { chunk, .. }, 0) => format!("#{}", chunk),
+    (Statement::Memory { chunk, .. }, width) =>
+      format!("{:width$} #{}", explicit_tag(&entry.statement).unwrap_or_default(), chunk, width = width),
+    _ => unreachable!()
+  }).collect();
+  let comment_column = memory.iter().zip(memory_lines.iter())
+    .filter(|(entry, _)| matches!(entry.statement, Statement::Memory { comment: Some(_), .. }))
+    .map(|(_, line)| line.chars().count())
+    .max().unwrap_or(0);
+
+  let mut sections = vec![];
+
+  sections.push(directives.iter().map(|entry| match &entry.statement {
+    Statement::Directive(items) => (*entry, items.join(" ")),
+    _ => unreachable!()
+  }).collect::<Vec<_>>());
+
+  sections.push(memory.iter().zip(memory_lines).map(|(entry, line)| match &entry.statement {
+    Statement::Memory { comment: Some(comment), .. } => (*entry, format!("{:width$} {}", line, comment, width = comment_column)),
+    _ => (*entry, line)
+  }).collect());
+
+  let indent = match instruction_width {
+    0 => String::new(),
+    width => " ".repeat(width + 1)
+  };
+  sections.push(instructions.iter().map(|entry| match (&entry.statement, explicit_tag(&entry.statement)) {
+    (Statement::Instruction { items, .. }, Some(tag)) =>
+      (*entry, format!("{:width$} {}", format!("{}:", tag), items.join(" "), width = instruction_width)),
+    (Statement::Instruction { items, .. }, None) => (*entry, format!("{}{}", indent, items.join(" "))),
+    _ => unreachable!()
+  }).collect());
+
+  let mut output = String::new();
+  for (section_idx, section) in sections.iter().filter(|x| !x.is_empty()).enumerate() {
+    if section_idx > 0 {
+      output.push('\n');
+    }
+    let code = matches!(section[0].0.statement, Statement::Instruction { .. });
+    for (idx, (entry, line)) in section.iter().enumerate() {
+      if entry.blank_before && idx > 0 {
+        output.push('\n');
+      }
+      for comment in entry.comments.iter() {
+        if code {
+          output.push_str(&indent);
+        }
+        output.push_str(comment);
+        output.push('\n');
+      }
+      output.push_str(line);
+      output.push('\n');
+    }
+  }
+
+  if !comments.is_empty() {
+    if !output.is_empty() {
+      output.push('\n');
+    }
+    for comment in comments {
+      output.push_str(comment);
+      output.push('\n');
+    }
+  }
+
+  output
+}
@@ -0,0 +1,640 @@
+//! A Forth front-end, the data stack is the stack of the process
+//!
+//! ```text
+//! VARIABLE total
+//! : square ( n -- n*n ) DUP * ;
+//! : sum-squares ( n -- ) 0 DO I square total +! LOOP ;
+//! 4 sum-squares total @ . CR
+//! ```
+//!
+//! - words are case insensitive, a definition is visible after its `;`, use `RECURSE` inside it
+//! - the code outside definitions runs in order from the start of the program
+//! - flags are `-1` (true) and `0` (false), numbers with a `.` are floats
+//! - control words: `IF ELSE THEN`, `BEGIN UNTIL`, `BEGIN AGAIN`, `BEGIN WHILE REPEAT`, `DO LOOP`, `DO +LOOP`, `I`, `J`
+//! - `VARIABLE name` reserves a cell in the process memory and `n CONSTANT name` takes a literal number
+//! - `.`, `EMIT`, `CR`, `SPACE` and `." text"` print through the std ffi library
+//!
+//! The return stack (calls, loops and `>R`) lives in the process memory after the static data,
+//! the register 14 points to its next free cell and holds only ints
+
+use std::{borrow::BorrowMut, collections::HashMap};
+
+use thiserror::Error;
+
+use crate::vm::program::{Instruction, InstructionParam, Opcode, Program};
+
+use super::{diagnostic::{self, Span}, Parser};
+
+pub struct Forth;
+
+/// Register holding the address of the next free cell of the return stack
+const RETURN_STACK_POINTER: i64 = 14;
+const FLAG_SHARE_MEMORY: i64 = 10;
+const CELL: i64 = 8;
+/// Memory reserved for the return stack after the static data
+pub const RETURN_STACK_SIZE: usize = 8 * 1024;
+
+const BUILTINS: [&str; 62] = [
+  "+", "-", "*", "/", "MOD", "NEGATE", "1+", "1-", "2*",
+  "=", "<>", "<", ">", "<=", ">=", "0=", "0<", "0>", "TRUE", "FALSE",
+  "DUP", "DROP", "SWAP", "OVER", "NIP", "TUCK", "ROT", "-ROT", "2DUP", "2DROP",
+  ">R", "R>", "R@", "I", "J",
+  "@", "!", "+!", "C@", "C!", "F@", "F!", "CELLS", "S>F", "F>S",
+  ".", "EMIT", "CR", "SPACE", ".\"",
+  "IF", "ELSE", "THEN", "BEGIN", "UNTIL", "AGAIN", "WHILE", "REPEAT", "DO", "LOOP", "+LOOP", "RECURSE"
+];
+
+#[derive(Error, Debug)]
+pub enum InternalForthError {
+  #[error("unknown word: {0}{}", did_you_mean(.1))]
+  UnknownWord(String, Vec<String>),
+  #[error("{0} without a matching {1}")]
+  Unbalanced(String, String),
+  #[error("{0} can only be used inside a definition")]
+  CompileOnly(String),
+  #[error("definitions can not be nested")]
+  NestedDefinition,
+  #[error("definition without ;")]
+  UnterminatedDefinition,
+  #[error("{0} expects a name")]
+  MissingName(String),
+  #[error("CONSTANT expects a number before it")]
+  ConstantValue,
+  #[error("missing closing {0}")]
+  Unterminated(char)
+}
+
+fn did_you_mean(suggestions: &[String]) -> String {
+  match suggestions {
+    [] => String::new(),
+    _ => format!(", did you mean {}?", suggestions.join(", "))
+  }
+}
+
+#[derive(Error, Debug)]
+#[error("Error [LINE: {}, COLUMN: {}] :: {}", .span.line + 1, .span.column + 1, .error)]
+pub struct ForthError {
+  pub span: Span,
+  pub error: InternalForthError
+}
+
+impl ForthError {
+  /// Render the error with the source it refers to
+  pub fn render(&self, name: &str, source: &str) -> String {
+    diagnostic::render(name, source, self.span, &self.error)
+  }
+}
+
+struct Scanner<'a> {
+  source: &'a str,
+  position: usize,
+  line: usize,
+  line_start: usize
+}
+
+impl<'a> Scanner<'a> {
+  fn new(source: &'a str) -> Self {
+    Scanner { source, position: 0, line: 0, line_start: 0 }
+  }
+
+  fn span(&self, start: usize) -> Span {
+    Span::new(self.line, start - self.line_start, self.position - start)
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let c = self.source[self.position..].chars().next()?;
+    self.position += c.len_utf8();
+    if c == '\n' {
+      self.line += 1;
+      self.line_start = self.position;
+    }
+    Some(c)
+  }
+
+  fn word(&mut self) -> Option<(String, Span)> {
+    while self.source[self.position..].starts_with(char::is_whitespace) {
+      self.bump();
+    }
+    let start = self.position;
+    while !self.source[self.position..].starts_with(char::is_whitespace) && self.bump().is_some() {}
+    match self.position > start {
+      true => Some((self.source[start..self.position].into(), self.span(start))),
+      false => None
+    }
+  }
+
+  /// The text until the delimiter, skipping the space after the previous word
+  fn until(&mut self, delimiter: char, span: Span) -> Result<String, ForthError> {
+    self.bump();
+    let start = self.position;
+    loop {
+      match self.bump() {
+        Some(c) if c == delimiter => return Ok(self.source[start..(self.position - c.len_utf8())].into()),
+        Some(_) => (),
+        None => return Err(ForthError { span, error: InternalForthError::Unterminated(delimiter) })
+      }
+    }
+  }
+}
+
+#[derive(Clone, Copy)]
+enum Word {
+  Colon(usize),
+  Variable(i64),
+  Constant(InstructionParam)
+}
+
+enum Control {
+  If(usize),
+  Else(usize),
+  Begin(usize),
+  While(usize, usize),
+  Do(usize)
+}
+
+impl Control {
+  fn name(&self) -> &'static str {
+    match self {
+      Control::If(_) => "IF",
+      Control::Else(_) => "ELSE",
+      Control::Begin(_) => "BEGIN",
+      Control::While(..) => "WHILE",
+      Control::Do(_) => "DO"
+    }
+  }
+
+  fn closing(&self) -> &'static str {
+    match self {
+      Control::If(_) | Control::Else(_) => "THEN",
+      Control::Begin(_) => "UNTIL",
+      Control::While(..) => "REPEAT",
+      Control::Do(_) => "LOOP"
+    }
+  }
+}
+
+struct Definition {
+  name: String,
+  label: usize,
+  skip: usize,
+  controls: usize,
+  span: Span
+}
+
+struct ForthCompiler<'a> {
+  program: &'a mut Program,
+  words: HashMap<String, Word>,
+  strings: HashMap<Vec<u8>, (i64, i64)>,
+  labels: Vec<Option<usize>>,
+  fixups: Vec<(usize, usize, Option<usize>)>, // instruction, operand, label or the return stack when none
+  controls: Vec<(Control, Span)>,
+  definition: Option<Definition>,
+  emit_buffer: Option<i64>,
+  printing: bool
+}
+
+fn int(x: i64) -> Option<InstructionParam> {
+  Some(InstructionParam::Int(x))
+}
+
+fn number(word: &str) -> Option<InstructionParam> {
+  match word.contains('.') {
+    true => word.parse().ok().map(InstructionParam::Float),
+    false => word.parse().ok().map(InstructionParam::Int)
+  }
+}
+
+impl<'a> ForthCompiler<'a> {
+  fn new(program: &'a mut Program) -> Self {
+    ForthCompiler {
+      program,
+      words: HashMap::new(),
+      strings: HashMap::new(),
+      labels: vec![],
+      fixups: vec![],
+      controls: vec![],
+      definition: None,
+      emit_buffer: None,
+      printing: false
+    }
+  }
+
+  fn emit(&mut self, opcode: Opcode, first: Option<InstructionParam>, second: Option<InstructionParam>) -> usize {
+    self.program.instructions.push(Instruction::with_args(opcode, first, second));
+    self.program.instructions.len() - 1
+  }
+
+  fn ops(&mut self, opcodes: &[Opcode]) {
+    for opcode in opcodes.iter() {
+      self.emit(*opcode, None, None);
+    }
+  }
+
+  fn label(&mut self) -> usize {
+    self.labels.push(None);
+    self.labels.len() - 1
+  }
+
+  fn place(&mut self, label: usize) {
+    self.labels[label] = Some(self.program.instructions.len());
+  }
+
+  fn placed_label(&mut self) -> usize {
+    let label = self.label();
+    self.place(label);
+    label
+  }
+
+  fn jump(&mut self, label: usize, cond: Option<InstructionParam>) {
+    let idx = self.emit(Opcode::Jump, int(0), cond);
+    self.fixups.push((idx, 0, Some(label)));
+  }
+
+  /// Jump when the flag on top of the stack is false
+  fn jump_false(&mut self, label: usize) {
+    self.emit(Opcode::Eq, None, int(0));
+    self.jump(label, None);
+  }
+
+  fn string(&mut self, data: &[u8]) -> (i64, i64) {
+    if let Some(location) = self.strings.get(data) {
+      return *location
+    }
+    let address = self.program.static_data.len();
+    self.program.static_data.extend_from_slice(data);
+    self.program.static_data_meta.push((address, data.len()));
+    let location = (address as i64, data.len() as i64);
+    self.strings.insert(data.into(), location);
+    location
+  }
+
+  fn push_return_stack(&mut self) {
+    self.emit(Opcode::Reg, int(RETURN_STACK_POINTER), None);
+    self.ops(&[Opcode::WriteInt64]);
+    self.emit(Opcode::Reg, int(RETURN_STACK_POINTER), None);
+    self.emit(Opcode::Add, None, int(CELL));
+    self.emit(Opcode::SetReg, int(RETURN_STACK_POINTER), None);
+  }
+
+  fn pop_return_stack(&mut self) {
+    self.emit(Opcode::Reg, int(RETURN_STACK_POINTER), None);
+    self.emit(Opcode::Sub, None, int(CELL));
+    self.ops(&[Opcode::Clone]);
+    self.emit(Opcode::SetReg, int(RETURN_STACK_POINTER), None);
+    self.ops(&[Opcode::ReadInt64]);
+  }
+
+  /// Push the cell of the return stack at the depth, 1 being the top
+  fn peek_return_stack(&mut self, depth: i64) {
+    self.emit(Opcode::Reg, int(RETURN_STACK_POINTER), None);
+    self.emit(Opcode::Sub, None, int(depth * CELL));
+    self.ops(&[Opcode::ReadInt64]);
+  }
+
+  fn drop_return_stack(&mut self, cells: i64) {
+    self.emit(Opcode::Reg, int(RETURN_STACK_POINTER), None);
+    self.emit(Opcode::Sub, None, int(cells * CELL));
+    self.emit(Opcode::SetReg, int(RETURN_STACK_POINTER), None);
+  }
+
+  fn call(&mut self, label: usize) {
+    let back = self.label();
+    let idx = self.emit(Opcode::Push, int(0), None);
+    self.fixups.push((idx, 0, Some(back)));
+    self.program.code_references.push((idx, 0));
+    self.push_return_stack();
+    self.jump(label, int(1));
+    self.place(back);
+  }
+
+  fn ret(&mut self) {
+    self.pop_return_stack();
+    self.emit(Opcode::Jump, None, int(1));
+  }
+
+  fn invoke(&mut self, function: &str, share_memory: bool) {
+    let (address, size) = self.string(function.as_bytes());
+    self.emit(Opcode::SetReg, int(FLAG_SHARE_MEMORY), int(share_memory.into()));
+    self.emit(Opcode::FastInvoke, int(address), int(size));
+    self.printing = true;
+  }
+
+  fn print(&mut self, text: &[u8]) {
+    let (address, size) = self.string(text);
+    self.emit(Opcode::SetReg, int(0), int(address));
+    self.emit(Opcode::SetReg, int(1), int(size));
+    self.invoke("std_print", true);
+  }
+
+  /// A byte of the static data where EMIT writes the char to print
+  fn emit_buffer(&mut self) -> i64 {
+    match self.emit_buffer {
+      Some(address) => address,
+      None => {
+        let address = self.program.static_data.len() as i64;
+        self.program.static_data.push(0);
+        self.emit_buffer = Some(address);
+        address
+      }
+    }
+  }
+
+  fn flag(&mut self, opcode: Opcode, operand: Option<InstructionParam>) {
+    self.emit(opcode, None, operand);
+    self.emit(Opcode::Mul, None, int(-1));
+  }
+
+  fn open(&mut self, control: Control, span: Span) {
+    self.controls.push((control, span));
+  }
+
+  /// Pop the innermost control structure if it is one of the expected
+  fn close(&mut self, word: &str, expected: &[&str], span: Span) -> Result<Control, ForthError> {
+    let open = self.definition.as_ref().map(|x| x.controls).unwrap_or(0);
+    match self.controls.last() {
+      Some((control, _)) if self.controls.len() > open && expected.contains(&control.name()) =>
+        Ok(self.controls.pop().unwrap().0),
+      _ => Err(ForthError { span, error: InternalForthError::Unbalanced(word.into(), expected.join(" or ")) })
+    }
+  }
+
+  fn control(&mut self, word: &str, span: Span) -> Result<bool, ForthError> {
+    match word {
+      "IF" => {
+        let other = self.label();
+        self.jump_false(other);
+        self.open(Control::If(other), span);
+      }
+      "ELSE" => {
+        let Control::If(other) = self.close(word, &["IF"], span)? else { unreachable!() };
+        let end = self.label();
+        self.jump(end, int(1));
+        self.place(other);
+        self.open(Control::Else(end), span);
+      }
+      "THEN" => match self.close(word, &["IF", "ELSE"], span)? {
+        Control::If(label) | Control::Else(label) => self.place(label),
+        _ => unreachable!()
+      }
+      "BEGIN" => {
+        let start = self.placed_label();
+        self.open(Control::Begin(start), span);
+      }
+      "UNTIL" => {
+        let Control::Begin(start) = self.close(word, &["BEGIN"], span)? else { unreachable!() };
+        self.jump_false(start);
+      }
+      "AGAIN" => {
+        let Control::Begin(start) = self.close(word, &["BEGIN"], span)? else { unreachable!() };
+        self.jump(start, int(1));
+      }
+      "WHILE" => {
+        let Control::Begin(start) = self.close(word, &["BEGIN"], span)? else { unreachable!() };
+        let end = self.label();
+        self.jump_false(end);
+        self.open(Control::While(start, end), span);
+      }
+      "REPEAT" => {
+        let Control::While(start, end) = self.close(word, &["WHILE"], span)? else { unreachable!() };
+        self.jump(start, int(1));
+        self.place(end);
+      }
+      "DO" => {
+        // the limit and then the index go to the return stack
+        self.ops(&[Opcode::Swap]);
+        self.push_return_stack();
+        self.push_return_stack();
+        let body = self.placed_label();
+        self.open(Control::Do(body), span);
+      }
+      "LOOP" | "+LOOP" => {
+        let Control::Do(body) = self.close(word, &["DO"], span)? else { unreachable!() };
+        self.pop_return_stack();
+        match word {
+          "LOOP" => { self.emit(Opcode::Add, None, int(1)); }
+          _ => self.ops(&[Opcode::Add])
+        }
+        self.ops(&[Opcode::Clone]);
+        self.peek_return_stack(1);
+        self.ops(&[Opcode::Gt, Opcode::Swap]);
+        self.push_return_stack();
+        self.jump(body, None);
+        self.drop_return_stack(2);
+      }
+      _ => return Ok(false)
+    }
+    Ok(true)
+  }
+
+  fn builtin(&mut self, word: &str, scanner: &mut Scanner, span: Span) -> Result<bool, ForthError> {
+    match word {
+      "+" => self.ops(&[Opcode::Add]),
+      "-" => self.ops(&[Opcode::Swap, Opcode::Sub]),
+      "*" => self.ops(&[Opcode::Mul]),
+      "/" => self.ops(&[Opcode::Swap, Opcode::Div]),
+      "MOD" => self.ops(&[Opcode::Over, Opcode::Over, Opcode::Swap, Opcode::Div, Opcode::Mul, Opcode::Swap, Opcode::Sub]),
+      "NEGATE" => { self.emit(Opcode::Mul, None, int(-1)); }
+      "1+" => { self.emit(Opcode::Add, None, int(1)); }
+      "1-" => { self.emit(Opcode::Sub, None, int(1)); }
+      "2*" => { self.emit(Opcode::Mul, None, int(2)); }
+
+      // the vm compares the top of the stack with the second item
+      "=" => self.flag(Opcode::Eq, None),
+      "<>" => self.flag(Opcode::Noteq, None),
+      "<" => self.flag(Opcode::Gt, None),
+      ">" => self.flag(Opcode::Ls, None),
+      "<=" => self.flag(Opcode::Gteq, None),
+      ">=" => self.flag(Opcode::Lseq, None),
+      "0=" => self.flag(Opcode::Eq, int(0)),
+      "0<" => self.flag(Opcode::Ls, int(0)),
+      "0>" => self.flag(Opcode::Gt, int(0)),
+      "TRUE" => { self.emit(Opcode::Push, int(-1), None); }
+      "FALSE" => { self.emit(Opcode::Push, int(0), None); }
+
+      "DUP" => self.ops(&[Opcode::Clone]),
+      "DROP" => self.ops(&[Opcode::Discard]),
+      "SWAP" => self.ops(&[Opcode::Swap]),
+      "OVER" => self.ops(&[Opcode::Over]),
+      "NIP" => self.ops(&[Opcode::Swap, Opcode::Discard]),
+      "TUCK" => self.ops(&[Opcode::Swap, Opcode::Over]),
+      "ROT" | "-ROT" => for _ in 0..(if word == "ROT" { 1 } else { 2 }) {
+        self.push_return_stack();
+        self.ops(&[Opcode::Swap]);
+        self.pop_return_stack();
+        self.ops(&[Opcode::Swap]);
+      }
+      "2DUP" => self.ops(&[Opcode::Over, Opcode::Over]),
+      "2DROP" => self.ops(&[Opcode::Discard, Opcode::Discard]),
+
+      ">R" => self.push_return_stack(),
+      "R>" => self.pop_return_stack(),
+      "R@" | "I" => self.peek_return_stack(1),
+      "J" => self.peek_return_stack(3),
+
+      "@" => self.ops(&[Opcode::ReadInt64]),
+      "!" => self.ops(&[Opcode::WriteInt64]),
+      "C@" => self.ops(&[Opcode::ReadInt8]),
+      "C!" => self.ops(&[Opcode::WriteInt8]),
+      "F@" => self.ops(&[Opcode::ReadFloat64]),
+      "F!" => self.ops(&[Opcode::WriteFloat64]),
+      "+!" => {
+        self.ops(&[Opcode::Clone]);
+        self.push_return_stack();
+        self.ops(&[Opcode::ReadInt64, Opcode::Add]);
+        self.pop_return_stack();
+        self.ops(&[Opcode::WriteInt64]);
+      }
+      "CELLS" => { self.emit(Opcode::Mul, None, int(CELL)); }
+      "S>F" => self.ops(&[Opcode::Float]),
+      "F>S" => self.ops(&[Opcode::Int]),
+
+      "." => {
+        self.emit(Opcode::SetReg, int(0), None);
+        self.invoke("std_reg_print", false);
+        self.print(b" ");
+      }
+      "EMIT" => {
+        let address = self.emit_buffer();
+        self.emit(Opcode::Push, int(address), None);
+        self.ops(&[Opcode::WriteInt8]);
+        self.emit(Opcode::SetReg, int(0), int(address));
+        self.emit(Opcode::SetReg, int(1), int(1));
+        self.invoke("std_print", true);
+      }
+      "CR" => self.print(b"\n"),
+      "SPACE" => self.print(b" "),
+      ".\"" => {
+        let text = scanner.until('"', span)?;
+        self.print(text.as_bytes());
+      }
+      _ => return Ok(false)
+    }
+    Ok(true)
+  }
+
+  fn name(&mut self, word: &str, scanner: &mut Scanner, span: Span) -> Result<(String, Span), ForthError> {
+    match scanner.word() {
+      Some((name, span)) => Ok((name.to_uppercase(), span)),
+      None => Err(ForthError { span, error: InternalForthError::MissingName(word.into()) })
+    }
+  }
+
+  fn compile(mut self, source: &str) -> Result<(), ForthError> {
+    let entry = self.emit(Opcode::SetReg, int(RETURN_STACK_POINTER), int(0));
+    self.fixups.push((entry, 1, None));
+
+    let mut scanner = Scanner::new(source);
+    let mut previous: Option<InstructionParam> = None;
+    while let Some((text, span)) = scanner.word() {
+      let word = text.to_uppercase();
+      let literal = previous.take();
+
+      match word.as_str() {
+        "\\" => { while !matches!(scanner.bump(), Some('\n') | None) {} }
+        "(" => { scanner.until(')', span)?; }
+        ":" => {
+          if self.definition.is_some() {
+            return Err(ForthError { span, error: InternalForthError::NestedDefinition })
+          }
+          let (name, _) = self.name(&word, &mut scanner, span)?;
+          let skip = self.label();
+          self.jump(skip, int(1));
+          let label = self.placed_label();
+          self.definition = Some(Definition { name, label, skip, controls: self.controls.len(), span });
+        }
+        ";" => {
+          let Some(definition) = self.definition.take() else {
+            return Err(ForthError { span, error: InternalForthError::Unbalanced(word, ":".into()) })
+          };
+          if self.controls.len() > definition.controls {
+            let (control, span) = self.controls.pop().unwrap();
+            return Err(ForthError { span, error: InternalForthError::Unbalanced(control.name().into(), control.closing().into()) })
+          }
+          self.ret();
+          self.place(definition.skip);
+          self.words.insert(definition.name, Word::Colon(definition.label));
+        }
+        "EXIT" | "RECURSE" => match &self.definition {
+          Some(definition) if word == "RECURSE" => {
+            let label = definition.label;
+            self.call(label);
+          }
+          Some(_) => self.ret(),
+          None => return Err(ForthError { span, error: InternalForthError::CompileOnly(word) })
+        }
+        "VARIABLE" => {
+          let (name, _) = self.name(&word, &mut scanner, span)?;
+          let address = self.program.static_data.len();
+          self.program.static_data.extend_from_slice(&[0; CELL as usize]);
+          self.words.insert(name, Word::Variable(address as i64));
+        }
+        "CONSTANT" => {
+          let (name, _) = self.name(&word, &mut scanner, span)?;
+          let Some(value) = literal else {
+            return Err(ForthError { span, error: InternalForthError::ConstantValue })
+          };
+          self.program.instructions.pop(); // the literal is not pushed at runtime
+          self.words.insert(name, Word::Constant(value));
+        }
+        "BYE" => {
+          self.flush();
+          self.ops(&[Opcode::Exit]);
+        }
+        _ => match self.words.get(&word).copied() {
+          Some(Word::Colon(label)) => self.call(label),
+          Some(Word::Variable(address)) => { self.emit(Opcode::Push, int(address), None); }
+          Some(Word::Constant(value)) => { self.emit(Opcode::Push, Some(value), None); }
+          None => if !self.control(&word, span)? && !self.builtin(&word, &mut scanner, span)? {
+            match number(&word) {
+              Some(value) => {
+                self.emit(Opcode::Push, Some(value), None);
+                previous = Some(value);
+              }
+              None => {
+                let words = self.words.keys().map(String::as_str).chain(BUILTINS);
+                let suggestions = diagnostic::suggestions(&word, words);
+                return Err(ForthError { span, error: InternalForthError::UnknownWord(text, suggestions) })
+              }
+            }
+          }
+        }
+      }
+    }
+
+    if let Some(definition) = self.definition {
+      return Err(ForthError { span: definition.span, error: InternalForthError::UnterminatedDefinition })
+    }
+    if let Some((control, span)) = self.controls.pop() {
+      return Err(ForthError { span, error: InternalForthError::Unbalanced(control.name().into(), control.closing().into()) })
+    }
+    self.flush();
+    self.ops(&[Opcode::Exit]);
+
+    let return_stack = self.program.static_data.len().next_multiple_of(CELL as usize);
+    for (idx, operand, label) in self.fixups.iter() {
+      let value = match label {
+        Some(label) => self.labels[*label].expect("labels are placed once compiled"),
+        None => return_stack
+      };
+      let instruction = &mut self.program.instructions[*idx];
+      *(if *operand == 0 { &mut instruction.1 } else { &mut instruction.2 }) = int(value as i64);
+    }
+    self.program.required_memory = self.program.required_memory.max(return_stack + RETURN_STACK_SIZE);
+    Ok(())
+  }
+
+  /// The std library buffers what is printed without a line end
+  fn flush(&mut self) {
+    if self.printing {
+      self.invoke("std_flush", false);
+    }
+  }
+}
+
+impl Parser for Forth {
+  type Err = ForthError;
+
+  fn parse(mut target: impl BorrowMut<Program>, source: impl AsRef<str>) -> Result<(), Self::Err> {
+    ForthCompiler::new(target.borrow_mut()).compile(source.as_ref())
+  }
+}
@@ -0,0 +1,197 @@
+//! Combine many parsed modules into a single program
+//!
+//! Every module keeps track of the operands pointing to its own instructions or memory (relocations),
+//! so they can be moved once the instructions and static data of all the modules are put together
+//!
+//! Modules share tags using `.export tag` and `.import tag`, the first module is the entry point
+//! and an `Exit` is placed after every module so the execution never falls into the next one
+
+use std::collections::HashMap;
+
+use thiserror::Error;
+
+use crate::vm::program::{Instruction, InstructionParam, Opcode, Program};
+
+#[derive(Clone, Copy, Debug)]
+pub enum Symbol {
+  Memory {
+    address: usize,
+    size: usize,
+  },
+  Instruction {
+    line: usize
+  }
+}
+
+/// How the symbol is used as operand: `$` address, `@` size or `^` end
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SymbolAccess {
+  Address,
+  Size,
+  End
+}
+
+#[derive(Clone, Debug)]
+pub enum RelocationKind {
+  Instruction,
+  Memory,
+  Import(String, SymbolAccess)
+}
+
+#[derive(Clone, Debug)]
+pub struct Relocation {
+  pub instruction: usize,
+  pub operand: usize, // 0 or 1
+  pub kind: RelocationKind
+}
+
+#[derive(Clone, Debug)]
+pub struct Module {
+  pub program: Program,
+  pub exports: HashMap<String, Symbol>,
+  pub relocations: Vec<Relocation>
+}
+
+impl Module {
+  pub fn new(program: Program) -> Self {
+    Module {
+      program,
+      exports: HashMap::new(),
+      relocations: vec![]
+    }
+  }
+}
+
+#[derive(Error, Debug)]
+pub enum SymbolError {
+  #[error("duplicate symbol: {symbol}, exported by {first} and {second}")]
+  Duplicate {
+    symbol: String,
+    first: String,
+    second: String
+  },
+
+  #[error("unresolved symbol: {symbol}, imported by {module}")]
+  Unresolved {
+    symbol: String,
+    module: String
+  },
+
+  #[error("bad symbol: {symbol}, only $ is valid for instruction symbol, imported by {module}")]
+  InstructionBadAccess {
+    symbol: String,
+    module: String
+  }
+}
+
+#[derive(Error, Debug)]
+#[error("{}", .0.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n"))]
+pub struct LinkerError(pub Vec<SymbolError>);
+
+pub struct Linker(Vec<Module>);
+
+impl Default for Linker {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Linker {
+  pub fn new() -> Self {
+    Linker(vec![])
+  }
+
+  pub fn add_module(mut self, module: Module) -> Self {
+    self.0.push(module);
+    self
+  }
+
+  fn offsets(&self) -> Vec<(usize, usize)> {
+    let mut offsets = vec![];
+    let (mut code, mut data) = (0, 0);
+    for module in self.0.iter() {
+      offsets.push((code, data));
+      code += module.program.instructions.len() + 1; // the module Exit
+      data += module.program.static_data.len();
+    }
+    offsets
+  }
+
+  fn symbols(&self, offsets: &[(usize, usize)], errors: &mut Vec<SymbolError>) -> HashMap<String, (Symbol, usize)> {
+    let mut symbols: HashMap<String, (Symbol, usize)> = HashMap::new(); // symbol => (relocated, module)
+    for (idx, (module, &(code, data))) in self.0.iter().zip(offsets).enumerate() {
+      for (name, symbol) in module.exports.iter() {
+        if let Some(&(_, first)) = symbols.get(name) {
+          errors.push(SymbolError::Duplicate {
+            symbol: name.clone(),
+            first: self.0[first].program.name.clone(),
+            second: module.program.name.clone()
+          });
+          continue
+        }
+        let relocated = match *symbol {
+          Symbol::Memory { address, size } => Symbol::Memory { address: address + data, size },
+          Symbol::Instruction { line } => Symbol::Instruction { line: line + code }
+        };
+        symbols.insert(name.clone(), (relocated, idx));
+      }
+    }
+    symbols
+  }
+
+  pub fn link(self, name: impl AsRef<str>) -> Result<Program, LinkerError> {
+    let mut program = Program::with_name(name);
+    let mut errors = vec![];
+    let offsets = self.offsets();
+    let symbols = self.symbols(&offsets, &mut errors);
+
+    program.required_memory = 0;
+    for (module, &(code, data)) in self.0.into_iter().zip(offsets.iter()) {
+      let mut instructions = module.program.instructions;
+
+      for relocation in module.relocations.iter() {
+        let value = match &relocation.kind {
+          RelocationKind::Instruction => code as i64,
+          RelocationKind::Memory => data as i64,
+          RelocationKind::Import(symbol, access) => match (symbols.get(symbol), access) {
+            (Some(&(Symbol::Memory { address, .. }, _)), SymbolAccess::Address) => address as i64,
+            (Some(&(Symbol::Memory { size, .. }, _)), SymbolAccess::Size) => size as i64,
+            (Some(&(Symbol::Memory { address, size }, _)), SymbolAccess::End) => (address + size) as i64,
+            (Some(&(Symbol::Instruction { line }, _)), SymbolAccess::Address) => {
+              program.code_references.push((relocation.instruction + code, relocation.operand));
+              line as i64
+            }
+            (Some(&(Symbol::Instruction { .. }, _)), _) => {
+              errors.push(SymbolError::InstructionBadAccess { symbol: symbol.clone(), module: module.program.name.clone() });
+              continue
+            }
+            (None, _) => {
+              errors.push(SymbolError::Unresolved { symbol: symbol.clone(), module: module.program.name.clone() });
+              continue
+            }
+          }
+        };
+
+        let Instruction(_, first, second) = &mut instructions[relocation.instruction];
+        let operand = if relocation.operand == 0 { first } else { second };
+        if let Some(InstructionParam::Int(x)) = operand {
+          *x += value; // imports are parsed as 0
+        }
+      }
+
+      program.code_references.extend(module.program.code_references.iter().map(|&(idx, operand)| (idx + code, operand)));
+      program.unit_references.extend(module.program.unit_references.into_iter().map(|(idx, operand, name)| (idx + code, operand, name)));
+      program.instructions.extend(instructions);
+      program.instructions.push(Instruction::new(Opcode::Exit));
+      program.static_data.extend(module.program.static_data);
+      program.static_data_meta.extend(module.program.static_data_meta.iter().map(|&(address, size)| (address + data, size)));
+      program.required_memory = program.required_memory.max(module.program.required_memory);
+    }
+
+    if errors.is_empty() {
+      Ok(program)
+    } else {
+      Err(LinkerError(errors))
+    }
+  }
+}
@@ -0,0 +1,76 @@
+//! Conversion of sources written for the deprecated v1 parser into v2 sources
+//!
+//! In v1 memory chunks are referenced by their index (`$0`, `@0`, `^0`) while in v2 the same syntax is a tag lookup,
+//! so every memory chunk gets a tag (`mem0`, `mem1`...) and the references are rewritten to use it
+
+#![allow(deprecated)]
+
+use thiserror::Error;
+
+use crate::vm::program::Program;
+
+use super::{v1, Parser};
+
+#[derive(Debug, Error)]
+pub enum MigrationError {
+  #[error("not a valid v1 source: {0}")]
+  Parse(#[from] v1::SimpleParserError)
+}
+
+fn chunk_tag(idx: usize) -> String {
+  format!("mem{}", idx)
+}
+
+/// A v1 chunk is the verbatim content of the line, quote it when v2 would read it differently
+fn chunk_content(content: &str) -> String {
+  if !content.starts_with('"') {
+    return content.into()
+  }
+  let mut quoted = String::from("\"");
+  for c in content.chars() {
+    match c {
+      '"' | '\\' => { quoted.push('\\'); quoted.push(c) },
+      _ => quoted.push(c)
+    }
+  }
+  quoted.push('"');
+  quoted
+}
+
+fn migrate_operand(item: &str) -> String {
+  match item.chars().next() {
+    Some(access @ ('$' | '@' | '^')) => match item[1..].parse() {
+      Ok(idx) => format!("{}{}", access, chunk_tag(idx)),
+      Err(_) => item.into()
+    },
+    _ => item.into()
+  }
+}
+
+/// Write the equivalent v2 source of a v1 source
+pub fn v1_to_v2(source: impl AsRef<str>) -> Result<String, MigrationError> {
+  let source = source.as_ref();
+  v1::Simple::parse(Program::new(), source)?; // v1 validates references to memory chunks
+
+  let mut output = String::new();
+  let mut chunks = 0;
+
+  for line in source.lines().map(|l| l.trim()) {
+    if line.is_empty() || line.starts_with(";") {
+      output.push_str(line);
+    } else if let Some(content) = line.strip_prefix("#") {
+      output.push_str(&format!("{} #{}", chunk_tag(chunks), chunk_content(content)));
+      chunks += 1;
+    } else {
+      let items: Vec<_> = line.split(' ').filter(|x| !x.is_empty()).collect();
+      output.push_str(items[0]);
+      for item in items[1..].iter() {
+        output.push(' ');
+        output.push_str(&migrate_operand(item));
+      }
+    }
+    output.push('\n');
+  }
+
+  Ok(output)
+}
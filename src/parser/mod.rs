@@ -9,6 +9,20 @@ pub mod v1;
 #[path = "simple_v2.rs"]
 pub mod v2;
 
+pub mod linker;
+
+pub mod diagnostic;
+
+pub mod migrate;
+
+pub mod format;
+
+pub mod structured;
+
+pub mod forth;
+
+pub mod wat;
+
 pub trait Parser {
   type Err;
 
@@ -52,10 +52,10 @@ fn parse_operand(item: &str, program: &Program) -> Result<Option<InstructionPara
 
 fn parse_instruction(line: &str, program: &Program) -> Result<Instruction, InternalSimpleParserError> {
   let items: Vec<_> = line.split(' ').filter(|x| !x.is_empty()).collect();
-  Ok(match items.as_slice() {
-    &[a] => Instruction::new(Opcode::from_str(a)?),
-    &[a, b] => Instruction::with_args(Opcode::from_str(a)?, parse_operand(b, program)?, None),
-    &[a, b, c] => Instruction::with_args(
+  Ok(match *items.as_slice() {
+    [a] => Instruction::new(Opcode::from_str(a)?),
+    [a, b] => Instruction::with_args(Opcode::from_str(a)?, parse_operand(b, program)?, None),
+    [a, b, c] => Instruction::with_args(
       Opcode::from_str(a)?, parse_operand(b, program)?, parse_operand(c, program)?
     ),
     _ => return Err(InternalSimpleParserError::BadLineSyntax(line.to_owned()))
@@ -85,7 +85,7 @@ impl Parser for Simple {
         continue
       }
 
-      program.instructions.push(parse_instruction(line, &program).map_err(|err| SimpleParserError(idx + 1, err))?);
+      program.instructions.push(parse_instruction(line, program).map_err(|err| SimpleParserError(idx + 1, err))?);
     }
     Ok(())
   }
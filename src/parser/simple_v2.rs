@@ -1,27 +1,43 @@
 //! This is the version 2 of the simple parser.
-//! 
+//!
 //! It's designed to provide tags in order to write memory access and jumps easier,
-//! 
+//!
 //! A line must contain only either: a comment, a chunk to be written in memory or a instruction
-//! 
+//!
 //! If no tag is provided, for example "#memory" ":push 0", the index of the line would be used as tag (starting in 0)
-//! 
+//!
+//! A memory chunk takes the rest of the line verbatim unless it starts with a quote, for example `msg #"hello\n\0"`,
+//! then it ends at the closing quote (only a comment may follow) and supports the escapes
+//! `\n`, `\r`, `\t`, `\0`, `\\`, `\"`, `\xNN` (a raw byte) and `\u{NNNN}` (an utf-8 encoded char)
+//!
+//! Tags can be shared between files with the directives `.export tag` and `.import tag`, see [`super::linker`]
+//!
 //! Numeric tags are not compatible with v1, so both parsers are not interchangeable
+//!
+//! The parser does not stop at the first error, every error found is reported with its location
 
 use std::{borrow::BorrowMut, collections::HashMap, str::FromStr};
 
+use strum::VariantNames;
 use thiserror::Error;
 
 use crate::vm::program::{Instruction, InstructionParam, Opcode, Program};
 
-use super::Parser;
+use super::{diagnostic::{self, Span}, linker::{Module, Relocation, RelocationKind, Symbol, SymbolAccess}, Parser};
 
 pub struct Simple;
 
+fn did_you_mean(suggestions: &[String]) -> String {
+  match suggestions {
+    [] => String::new(),
+    _ => format!(", did you mean: {}?", suggestions.join(", "))
+  }
+}
+
 #[derive(Error, Debug)]
 pub enum InternalSimpleParserError {
-  #[error("operand invalid syntax")]
-  OperandInvalidSyntax,
+  #[error("operand invalid syntax: {0}")]
+  OperandInvalidSyntax(String),
 
   #[error("bad tag: {0}, not found")]
   BadTagNotFound(String),
@@ -32,112 +48,384 @@ pub enum InternalSimpleParserError {
   #[error("bad line syntax: {0}")]
   BadLineSyntax(String),
 
-  #[error("opcode not found")]
-  OpcodeNotFound(#[from] <Opcode as FromStr>::Err)
+  #[error("unterminated string literal")]
+  UnterminatedString,
+
+  #[error("bad escape sequence: {0}")]
+  BadEscape(String),
+
+  #[error("unexpected content after string literal: {0}")]
+  TrailingContent(String),
+
+  #[error("bad directive: {0}")]
+  BadDirective(String),
+
+  #[error("bad tag: {0}, imported and also defined")]
+  ImportedTagDefined(String),
+
+  #[error("bad tag: {0}, imported but the program is not being linked")]
+  UnresolvedImport(String),
+
+  #[error("opcode not found: {opcode}{}", did_you_mean(.suggestions))]
+  OpcodeNotFound {
+    opcode: String,
+    suggestions: Vec<String>
+  }
 }
 
 #[derive(Error, Debug)]
-#[error("Error [LINE: {0}] :: {1}")]
-pub struct SimpleParserError(usize, InternalSimpleParserError);
+#[error("Error [LINE: {}, COLUMN: {}] :: {}", .span.line + 1, .span.column + 1, .error)]
+pub struct SimpleParserError {
+  pub span: Span,
+  pub error: InternalSimpleParserError
+}
 
-enum Tag {
+/// Every error found in a source, sorted by location
+#[derive(Error, Debug)]
+#[error("{}", .0.iter().map(|err| err.to_string()).collect::<Vec<_>>().join("\n"))]
+pub struct SimpleParserErrors(pub Vec<SimpleParserError>);
+
+impl SimpleParserErrors {
+  /// Render every error with the source excerpt it refers to
+  pub fn render(&self, name: &str, source: &str) -> String {
+    self.0.iter().map(|err| diagnostic::render(name, source, err.span, &err.error)).collect::<Vec<_>>().join("\n")
+  }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Tag {
   Memory {
     address: usize,
     size: usize,
   },
   Instruction {
     line: usize
+  },
+  Import
+}
+
+#[derive(Clone, Debug)]
+pub struct TagDefinition {
+  pub name: String,
+  pub span: Span, // empty when the tag is implicit
+  pub tag: Tag
+}
+
+#[derive(Clone, Debug)]
+pub struct TagReference {
+  pub name: String,
+  pub span: Span // without the $, @ or ^
+}
+
+/// The tags of a source and where they are used, along with the errors found, intended for tooling
+#[derive(Debug)]
+pub struct SourceIndex {
+  pub definitions: Vec<TagDefinition>,
+  pub references: Vec<TagReference>,
+  pub errors: Vec<SimpleParserError>
+}
+
+/// A line of the source, its content might start after the beginning of the original line
+struct SourceLine {
+  line: usize,
+  column: usize,
+  content: String
+}
+
+impl SourceLine {
+  fn span(&self, start: usize, length: usize) -> Span {
+    Span::new(self.line, self.column + start, length)
+  }
+
+  fn advance(&mut self, start: usize) {
+    self.content = self.content[start..].into();
+    self.column += start;
+  }
+
+  /// Items separated by spaces along with their position in the content
+  fn items(&self) -> Vec<(usize, &str)> {
+    let mut items = vec![];
+    let mut start = None;
+    for (idx, c) in self.content.char_indices().chain([(self.content.len(), ' ')]) {
+      match (c.is_whitespace(), start) {
+        (true, Some(begin)) => {
+          items.push((begin, &self.content[begin..idx]));
+          start = None;
+        }
+        (false, None) => start = Some(idx),
+        _ => ()
+      }
+    }
+    items
+  }
+}
+
+fn parse_escape(chars: &mut std::str::Chars, target: &mut Vec<u8>) -> Result<(), InternalSimpleParserError> {
+  let escape = chars.next().ok_or(InternalSimpleParserError::UnterminatedString)?;
+  match escape {
+    'n' => target.push(b'\n'),
+    'r' => target.push(b'\r'),
+    't' => target.push(b'\t'),
+    '0' => target.push(0),
+    '\\' | '"' => target.push(escape as u8),
+    'x' => {
+      let digits: String = chars.by_ref().take(2).collect();
+      match u8::from_str_radix(&digits, 16) {
+        // from_str_radix also takes a sign
+        Ok(byte) if digits.len() == 2 && digits.chars().all(|x| x.is_ascii_hexdigit()) => target.push(byte),
+        _ => return Err(InternalSimpleParserError::BadEscape(format!("\\x{}", digits)))
+      }
+    }
+    'u' => {
+      let digits: String = chars.by_ref().take_while(|x| *x != '}').collect();
+      let code = digits.strip_prefix('{')
+        .filter(|x| !x.is_empty() && x.len() <= 6 && x.chars().all(|x| x.is_ascii_hexdigit()))
+        .and_then(|x| u32::from_str_radix(x, 16).ok())
+        .and_then(char::from_u32)
+        .ok_or_else(|| InternalSimpleParserError::BadEscape(format!("\\u{}}}", digits)))?;
+      target.extend_from_slice(code.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    _ => return Err(InternalSimpleParserError::BadEscape(format!("\\{}", escape)))
+  }
+  Ok(())
+}
+
+/// Get the bytes of a memory chunk, the content after the #
+///
+/// On error the position and length of the wrong content inside the chunk is also returned
+pub(crate) fn parse_memory_chunk(chunk: &str) -> Result<Vec<u8>, (usize, usize, InternalSimpleParserError)> {
+  let Some(quoted) = chunk.strip_prefix('"') else {
+    return Ok(chunk.as_bytes().into()) // unquoted chunks are taken verbatim
+  };
+
+  let mut data = vec![];
+  let mut chars = quoted.chars();
+  loop {
+    let offset = chunk.len() - chars.as_str().len();
+    match chars.next() {
+      Some('"') => break,
+      Some('\\') => if let Err(err) = parse_escape(&mut chars, &mut data) {
+        let length = chunk.len() - chars.as_str().len() - offset;
+        return Err((offset, length, err))
+      },
+      Some(c) => data.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+      None => return Err((0, chunk.len(), InternalSimpleParserError::UnterminatedString))
+    }
+  }
+
+  let rest = chars.as_str();
+  match rest.trim() {
+    trimmed if trimmed.is_empty() || trimmed.starts_with(';') => Ok(data),
+    trimmed => Err((
+      chunk.len() - rest.trim_start().len(), trimmed.len(), InternalSimpleParserError::TrailingContent(trimmed.into())
+    ))
   }
 }
 
 struct ParserV2<'a> {
   program: &'a mut Program,
   tags: HashMap<String, Tag>, // tag => command
+  exports: Vec<(Span, String)>,
+  relocations: Vec<Relocation>,
+  errors: Vec<SimpleParserError>,
+  definitions: Vec<TagDefinition>,
+  references: Vec<TagReference>,
+  linking: bool
 }
 
 impl<'a> ParserV2<'a> {
-  pub fn consume_tags_and_memory(&mut self, source: &mut Vec<(usize, String)>) -> Result<(), SimpleParserError> {
-    source.retain(|(_, x)| match x.trim().chars().next() {
-      Some(';') | None => false,
-      _ => true
-    }); // remove empty lines and comments
+  fn new(program: &'a mut Program, linking: bool) -> Self {
+    ParserV2 {
+      program,
+      tags: HashMap::new(),
+      exports: vec![],
+      relocations: vec![],
+      errors: vec![],
+      definitions: vec![],
+      references: vec![],
+      linking
+    }
+  }
+
+  fn error(&mut self, span: Span, error: InternalSimpleParserError) {
+    self.errors.push(SimpleParserError { span, error })
+  }
+
+  fn define_tag(&mut self, tag: String, value: Tag, span: Span) {
+    match (self.tags.get(&tag), &value) {
+      (Some(Tag::Import), _) | (Some(_), Tag::Import) => self.error(span, InternalSimpleParserError::ImportedTagDefined(tag)),
+      _ => {
+        self.definitions.push(TagDefinition { name: tag.clone(), span, tag: value });
+        self.tags.insert(tag, value);
+      }
+    }
+  }
+
+  fn consume_directive(&mut self, line: &SourceLine) {
+    let items = line.items();
+    match items.first() {
+      Some((_, ".export")) => for &(start, tag) in items[1..].iter() {
+        let span = line.span(start, tag.len());
+        self.exports.push((span, tag.into()));
+        self.references.push(TagReference { name: tag.into(), span });
+      },
+      Some((_, ".import")) => for &(start, tag) in items[1..].iter() {
+        self.define_tag(tag.into(), Tag::Import, line.span(start, tag.len()))
+      },
+      _ => self.error(
+        line.span(0, line.content.len()), InternalSimpleParserError::BadDirective(line.content.clone())
+      )
+    }
+  }
+
+  fn line_tag(line: &SourceLine, end: usize) -> (String, Span) {
+    let prefix = &line.content[..end];
+    match prefix.trim() {
+      "" => (line.line.to_string(), line.span(0, 0)),
+      x => (x.into(), line.span(prefix.len() - prefix.trim_start().len(), x.len()))
+    }
+  }
+
+  pub fn consume_tags_and_memory(&mut self, source: &mut Vec<SourceLine>) {
+    source.retain(|x| !matches!(x.content.trim().chars().next(), Some(';') | None)); // remove empty lines and comments
 
     let mut instruction_counter = 0;
 
-    for (idx, line) in source.iter_mut() {
-      *line = line.trim_start().into();
+    for line in source.iter_mut() {
+      line.advance(line.content.len() - line.content.trim_start().len());
 
-      if let Some(memory_idx) = line.find("#") {
-        let tag: String = match line[..memory_idx].trim() {
-          x if x.len() > 0 => x.into(),
-          _ => idx.to_string()
+      if line.content.starts_with('.') {
+        self.consume_directive(line);
+        line.content = String::new();
+        continue
+      }
+
+      if let Some(memory_idx) = line.content.find("#") {
+        let (tag, tag_span) = Self::line_tag(line, memory_idx);
+
+        let chunk = match parse_memory_chunk(&line.content[(memory_idx + 1)..]) {
+          Ok(chunk) => chunk,
+          Err((start, length, err)) => {
+            self.error(line.span(memory_idx + 1 + start, length), err);
+            vec![]
+          }
         };
 
         let begin = self.program.static_data.len();
-        self.program.static_data.extend_from_slice(&line[(memory_idx + 1)..].as_bytes());
+        self.program.static_data.extend_from_slice(&chunk);
         let end = self.program.static_data.len();
         self.program.static_data_meta.push((begin, end - begin)); // TODO?: remove static_data_meta
 
-        self.tags.insert(tag, Tag::Memory { address: begin, size: end - begin });
-        
-        *line = String::new();
+        self.define_tag(tag, Tag::Memory { address: begin, size: end - begin }, tag_span);
+
+        line.content = String::new();
 
         continue // skip so the instruction counter does not increment
       }
 
-      if let Some(line_tag_idx) = line.find(":") {
-        let tag: String = match line[..line_tag_idx].trim() {
-          x if x.len() > 0 => x.into(),
-          _ => idx.to_string()
-        };
+      if let Some(line_tag_idx) = line.content.find(":") {
+        let (tag, tag_span) = Self::line_tag(line, line_tag_idx);
 
-        self.tags.insert(tag, Tag::Instruction { line: instruction_counter });
+        self.define_tag(tag, Tag::Instruction { line: instruction_counter }, tag_span);
 
-        *line = line[(line_tag_idx+1)..].into();
+        line.advance(line_tag_idx + 1);
       }
 
       // here we are assume this is a instruction, otherwise consume_instructions will return an error
-      instruction_counter += 1; 
+      instruction_counter += 1;
     }
 
-    source.retain(|(_, x)| !x.trim().is_empty()); // remove empty lines
-    Ok(())
+    source.retain(|x| !x.content.trim().is_empty()); // remove empty lines
   }
 
-  pub fn consume_instructions(&mut self, source: &mut Vec<(usize, String)>) -> Result<(), SimpleParserError> {
-    for (idx, line) in source.iter() {
-      self.consume_instruction(line).map_err(|err| SimpleParserError(*idx, err))?;
+  pub fn exported_symbols(&mut self) -> HashMap<String, Symbol> {
+    let mut symbols = HashMap::new();
+    for (span, tag) in std::mem::take(&mut self.exports) {
+      match self.tags.get(&tag) {
+        Some(&Tag::Memory { address, size }) => { symbols.insert(tag, Symbol::Memory { address, size }); },
+        Some(&Tag::Instruction { line }) => { symbols.insert(tag, Symbol::Instruction { line }); },
+        _ => self.error(span, InternalSimpleParserError::BadTagNotFound(tag))
+      }
     }
-    Ok(())
+    symbols
   }
 
-  pub fn consume_instruction(&mut self, line: &String) -> Result<(), InternalSimpleParserError> {
-    let items: Vec<_> = line.split(' ').filter(|x| !x.is_empty()).collect();
-    let instruction = match items.as_slice() {
-      &[a] => Instruction::new(Opcode::from_str(a)?),
-      &[a, b] => Instruction::with_args(Opcode::from_str(a)?, self.parse_operand(b)?, None),
-      &[a, b, c] => Instruction::with_args(
-        Opcode::from_str(a)?, self.parse_operand(b)?, self.parse_operand(c)?
-      ),
-      _ => return Err(InternalSimpleParserError::BadLineSyntax(line.to_owned()))
+  pub fn consume_instructions(&mut self, source: &[SourceLine]) {
+    for line in source.iter() {
+      if let Err((span, err)) = self.consume_instruction(line) {
+        self.error(span, err);
+        self.program.instructions.push(Instruction::new(Opcode::Noop)); // keep the instruction indices
+      }
+    }
+  }
+
+  pub fn consume_instruction(&mut self, line: &SourceLine) -> Result<(), (Span, InternalSimpleParserError)> {
+    let items = line.items();
+    let opcode = |(start, item): (usize, &str)| Opcode::from_str(item).map_err(|_| (
+      line.span(start, item.len()),
+      InternalSimpleParserError::OpcodeNotFound {
+        opcode: item.into(),
+        suggestions: diagnostic::suggestions(item, Opcode::VARIANTS.iter().copied())
+      }
+    ));
+    let mut operand = |(start, item): (usize, &str), idx| {
+      if item.starts_with(['$', '@', '^']) {
+        self.references.push(TagReference { name: item[1..].into(), span: line.span(start + 1, item.len() - 1) });
+      }
+      self.parse_operand(item, idx).map_err(|err| (line.span(start, item.len()), err))
+    };
+
+    let instruction = match *items.as_slice() {
+      [a] => Instruction::new(opcode(a)?),
+      [a, b] => Instruction::with_args(opcode(a)?, operand(b, 0)?, None),
+      [a, b, c] => Instruction::with_args(opcode(a)?, operand(b, 0)?, operand(c, 1)?),
+      _ => {
+        let start = line.content.len() - line.content.trim_start().len();
+        return Err((
+          line.span(start, line.content.trim().len()), InternalSimpleParserError::BadLineSyntax(line.content.trim().into())
+        ))
+      }
     };
     self.program.instructions.push(instruction);
     Ok(())
   }
 
-  pub fn parse_operand(&mut self, item: &str) -> Result<Option<InstructionParam>, InternalSimpleParserError> {
+  fn relocate(&mut self, operand: usize, kind: RelocationKind) {
+    self.relocations.push(Relocation { instruction: self.program.instructions.len(), operand, kind })
+  }
+
+  pub fn parse_operand(&mut self, item: &str, operand: usize) -> Result<Option<InstructionParam>, InternalSimpleParserError> {
     // handle tag
     if item.starts_with("$") || item.starts_with("@") || item.starts_with("^") {
       let tag = &item[1..];
-      return match (item.chars().next().unwrap(), self.tags.get(tag)) {
-        ('$', Some(&Tag::Memory { address, .. })) => Ok(Some(InstructionParam::Int(address as i64))),
-        ('@', Some(&Tag::Memory { size, .. })) => Ok(Some(InstructionParam::Int(size as i64))),
-        ('^', Some(&Tag::Memory { address, size })) => Ok(Some(InstructionParam::Int((address + size) as i64))),
-        ('$', Some(&Tag::Instruction { line })) => Ok(Some(InstructionParam::Int(line as i64))),
-        ('@' | '^', Some(&Tag::Instruction { .. })) => Err(InternalSimpleParserError::InstructionBadTag(tag.into())),
-        (_, None) => Err(InternalSimpleParserError::BadTagNotFound(tag.into())),
-        _ => Err(InternalSimpleParserError::BadTagNotFound(tag.into())) // should never trigger, char not in $, @, ^
+      let access = match item.chars().next().unwrap() {
+        '$' => SymbolAccess::Address,
+        '@' => SymbolAccess::Size,
+        _ => SymbolAccess::End
+      };
+      let (value, relocation) = match (access, self.tags.get(tag)) {
+        (SymbolAccess::Address, Some(&Tag::Memory { address, .. })) => (address, Some(RelocationKind::Memory)),
+        (SymbolAccess::Size, Some(&Tag::Memory { size, .. })) => (size, None),
+        (SymbolAccess::End, Some(&Tag::Memory { address, size })) => (address + size, Some(RelocationKind::Memory)),
+        (SymbolAccess::Address, Some(&Tag::Instruction { line })) => (line, Some(RelocationKind::Instruction)),
+        (_, Some(&Tag::Instruction { .. })) => return Err(InternalSimpleParserError::InstructionBadTag(tag.into())),
+        (_, Some(Tag::Import)) if self.linking => (0, Some(RelocationKind::Import(tag.into(), access))),
+        (_, Some(Tag::Import)) => return Err(InternalSimpleParserError::UnresolvedImport(tag.into())),
+        (_, None) => return Err(InternalSimpleParserError::BadTagNotFound(tag.into()))
+      };
+      if let Some(RelocationKind::Instruction) = relocation {
+        self.program.code_references.push((self.program.instructions.len(), operand));
+      }
+      if let Some(kind) = relocation {
+        self.relocate(operand, kind);
       }
+      return Ok(Some(InstructionParam::Int(value as i64)))
+    }
+
+    // shared memory unit, resolved by the machine
+    if let Some(name) = item.strip_prefix('%') {
+      self.program.unit_references.push((self.program.instructions.len(), operand, name.into()));
+      return Ok(Some(InstructionParam::Int(-1)))
     }
 
     // default behaviour
@@ -148,26 +436,74 @@ impl<'a> ParserV2<'a> {
     } else if let Ok(float) = item.parse() {
       Ok(Some(InstructionParam::Float(float)))
     } else {
-      Err(InternalSimpleParserError::OperandInvalidSyntax)
+      Err(InternalSimpleParserError::OperandInvalidSyntax(item.into()))
+    }
+  }
+
+  fn finish(mut self) -> Result<Vec<Relocation>, SimpleParserErrors> {
+    if self.errors.is_empty() {
+      Ok(self.relocations)
+    } else {
+      self.errors.sort_by_key(|err| err.span);
+      Err(SimpleParserErrors(self.errors))
     }
   }
 }
 
+fn source_lines(source: &str) -> Vec<SourceLine> {
+  source.lines().enumerate().map(|(line, content)| SourceLine { line, column: 0, content: content.into() }).collect()
+}
+
 impl Parser for Simple {
-  type Err = SimpleParserError;
+  type Err = SimpleParserErrors;
 
   fn parse(mut target: impl BorrowMut<Program>, source: impl AsRef<str>) -> Result<(), Self::Err> {
-    let mut parser = ParserV2{
-      program: target.borrow_mut(),
-      tags: HashMap::new()
-    };
+    let mut parser = ParserV2::new(target.borrow_mut(), false);
+
+    let mut source = source_lines(source.as_ref());
 
-    let mut source: Vec<_> = source.as_ref().lines().enumerate().map(|(idx, line)| (idx, line.to_string())).collect();
+    parser.consume_tags_and_memory(&mut source);
 
-    parser.consume_tags_and_memory(&mut source)?;
+    parser.consume_instructions(&source);
 
-    parser.consume_instructions(&mut source)?;
+    parser.finish()?;
 
     Ok(())
   }
-}
\ No newline at end of file
+}
+
+impl Simple {
+  /// Index the tags of a source, imported tags are allowed
+  pub fn index(source: impl AsRef<str>) -> SourceIndex {
+    let mut program = Program::new();
+    let mut parser = ParserV2::new(&mut program, true);
+
+    let mut source = source_lines(source.as_ref());
+
+    parser.consume_tags_and_memory(&mut source);
+
+    parser.consume_instructions(&source);
+
+    parser.exported_symbols();
+    parser.errors.sort_by_key(|err| err.span);
+
+    SourceIndex { definitions: parser.definitions, references: parser.references, errors: parser.errors }
+  }
+
+  /// Parse the source as a module to be linked with others, where imported tags are allowed
+  pub fn parse_module(name: impl AsRef<str>, source: impl AsRef<str>) -> Result<Module, SimpleParserErrors> {
+    let mut program = Program::with_name(name);
+    let mut parser = ParserV2::new(&mut program, true);
+
+    let mut source = source_lines(source.as_ref());
+
+    parser.consume_tags_and_memory(&mut source);
+
+    parser.consume_instructions(&source);
+
+    let exports = parser.exported_symbols();
+    let relocations = parser.finish()?;
+
+    Ok(Module { program, exports, relocations })
+  }
+}
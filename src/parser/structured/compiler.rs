@@ -0,0 +1,528 @@
+use std::collections::HashMap;
+
+use crate::{parser::diagnostic::Span, vm::program::{Instruction, InstructionParam, Opcode, Program}};
+
+use super::{
+  syntax::{BinaryOp, Expr, ExprKind, Extern, Function, Module, Stmt, StmtKind, Type, UnaryOp},
+  InternalStructuredError, StructuredError
+};
+
+/// Register holding the address of the current frame
+const FRAME_POINTER: i64 = 14;
+const FLAG_SHARE_MEMORY: i64 = 10;
+/// A frame starts with the caller frame pointer and the return address, then the parameters and locals
+const FRAME_HEADER: i64 = 16;
+const SLOT_SIZE: i64 = 8;
+/// Memory reserved for the frames after the static data
+pub const CALL_STACK_SIZE: usize = 64 * 1024;
+
+enum Fixup {
+  Label(usize),
+  /// Adds the frame size of the function being compiled
+  FrameSize,
+  /// The first address after the static data
+  CallStack
+}
+
+struct Signature {
+  params: Vec<Type>,
+  ret: Option<Type>,
+  label: usize
+}
+
+struct ExternSignature {
+  params: Vec<Type>,
+  ret: Option<Type>,
+  symbol: (i64, i64)
+}
+
+type CompileResult<T> = Result<T, StructuredError>;
+
+fn error<T>(span: Span, error: InternalStructuredError) -> CompileResult<T> {
+  Err(StructuredError { span, error })
+}
+
+fn int(x: i64) -> Option<InstructionParam> {
+  Some(InstructionParam::Int(x))
+}
+
+fn mismatched<T>(span: Span, expected: Type, found: Type) -> CompileResult<T> {
+  error(span, InternalStructuredError::MismatchedTypes { expected: expected.to_string(), found: found.to_string() })
+}
+
+/// Every path ends with a return
+fn returns(body: &[Stmt]) -> bool {
+  body.iter().any(|stmt| match &stmt.kind {
+    StmtKind::Return(_) => true,
+    StmtKind::If(_, then, otherwise) => returns(then) && returns(otherwise),
+    _ => false
+  })
+}
+
+pub struct Compiler<'a> {
+  program: &'a mut Program,
+  functions: HashMap<String, Signature>,
+  externs: HashMap<String, ExternSignature>,
+  strings: HashMap<Vec<u8>, (i64, i64)>,
+  labels: Vec<Option<usize>>,
+  fixups: Vec<(usize, usize, Fixup)>, // instruction, operand, fixup
+  scopes: Vec<HashMap<String, (i64, Type)>>, // name => offset in the frame, type
+  frame_size: i64,
+  ret: Option<Type>
+}
+
+impl<'a> Compiler<'a> {
+  pub fn new(program: &'a mut Program) -> Self {
+    Compiler {
+      program,
+      functions: HashMap::new(),
+      externs: HashMap::new(),
+      strings: HashMap::new(),
+      labels: vec![],
+      fixups: vec![],
+      scopes: vec![],
+      frame_size: 0,
+      ret: None
+    }
+  }
+
+  fn emit(&mut self, opcode: Opcode, first: Option<InstructionParam>, second: Option<InstructionParam>) -> usize {
+    self.program.instructions.push(Instruction::with_args(opcode, first, second));
+    self.program.instructions.len() - 1
+  }
+
+  fn op(&mut self, opcode: Opcode) {
+    self.emit(opcode, None, None);
+  }
+
+  fn label(&mut self) -> usize {
+    self.labels.push(None);
+    self.labels.len() - 1
+  }
+
+  fn place(&mut self, label: usize) {
+    self.labels[label] = Some(self.program.instructions.len());
+  }
+
+  fn jump(&mut self, label: usize, cond: Option<InstructionParam>) {
+    let idx = self.emit(Opcode::Jump, int(0), cond);
+    self.fixups.push((idx, 0, Fixup::Label(label)));
+  }
+
+  /// Push the address of a slot in the current frame, or in the next one for the calls
+  fn address(&mut self, offset: i64, next_frame: bool) {
+    self.emit(Opcode::Reg, int(FRAME_POINTER), None);
+    if next_frame {
+      let idx = self.emit(Opcode::Add, None, int(offset));
+      self.fixups.push((idx, 1, Fixup::FrameSize));
+    } else if offset != 0 {
+      self.emit(Opcode::Add, None, int(offset));
+    }
+  }
+
+  fn string(&mut self, data: &[u8]) -> (i64, i64) {
+    if let Some(location) = self.strings.get(data) {
+      return *location
+    }
+    let address = self.program.static_data.len();
+    self.program.static_data.extend_from_slice(data);
+    self.program.static_data_meta.push((address, data.len()));
+    let location = (address as i64, data.len() as i64);
+    self.strings.insert(data.into(), location);
+    location
+  }
+
+  fn read(&mut self, ty: Type, offset: i64) {
+    for slot in 0..(ty.width() as i64) {
+      self.address(offset + slot * SLOT_SIZE, false);
+      self.op(if ty == Type::Float { Opcode::ReadFloat64 } else { Opcode::ReadInt64 });
+    }
+  }
+
+  /// Pop the value into the frame, the last slot is on top of the stack
+  fn write(&mut self, ty: Type, offset: i64, next_frame: bool) {
+    for slot in (0..(ty.width() as i64)).rev() {
+      self.address(offset + slot * SLOT_SIZE, next_frame);
+      self.op(if ty == Type::Float { Opcode::WriteFloat64 } else { Opcode::WriteInt64 });
+    }
+  }
+
+  fn variable(&self, name: &str, span: Span) -> CompileResult<(i64, Type)> {
+    match self.scopes.iter().rev().find_map(|scope| scope.get(name)) {
+      Some(variable) => Ok(*variable),
+      None => error(span, InternalStructuredError::VariableNotFound(name.into()))
+    }
+  }
+
+  fn allocate(&mut self, name: &str, ty: Type) -> i64 {
+    let offset = self.frame_size;
+    self.frame_size += ty.width() as i64 * SLOT_SIZE;
+    self.scopes.last_mut().unwrap().insert(name.into(), (offset, ty));
+    offset
+  }
+
+  /// Patch the frame size into the pending instructions
+  fn close_frame(&mut self) {
+    let frame_size = self.frame_size;
+    for (idx, operand, fixup) in self.fixups.iter() {
+      if let Fixup::FrameSize = fixup {
+        let instruction = &mut self.program.instructions[*idx];
+        let param = if *operand == 0 { &mut instruction.1 } else { &mut instruction.2 };
+        if let Some(InstructionParam::Int(x)) = param {
+          *x += frame_size;
+        }
+      }
+    }
+    self.fixups.retain(|(_, _, fixup)| !matches!(fixup, Fixup::FrameSize));
+  }
+
+  pub fn compile(mut self, module: &Module) -> CompileResult<()> {
+    for Extern { name, params, ret, span } in module.externs.iter() {
+      if self.functions.contains_key(name) || self.externs.contains_key(name) {
+        return error(*span, InternalStructuredError::DuplicateFunction(name.clone()))
+      }
+      let registers: usize = params.iter().map(Type::width).sum();
+      if registers > 10 {
+        return error(*span, InternalStructuredError::TooManyRegisters(name.clone(), registers))
+      }
+      let symbol = self.string(name.as_bytes());
+      self.externs.insert(name.clone(), ExternSignature { params: params.clone(), ret: *ret, symbol });
+    }
+    for Function { name, params, ret, span, .. } in module.functions.iter() {
+      if self.functions.contains_key(name) || self.externs.contains_key(name) {
+        return error(*span, InternalStructuredError::DuplicateFunction(name.clone()))
+      }
+      let label = self.label();
+      let params = params.iter().map(|(_, ty)| *ty).collect();
+      self.functions.insert(name.clone(), Signature { params, ret: *ret, label });
+    }
+
+    match self.functions.get("main") {
+      Some(Signature { params, .. }) if params.is_empty() => (),
+      _ => return error(Span::default(), InternalStructuredError::MainNotFound)
+    }
+    let entry = self.emit(Opcode::SetReg, int(FRAME_POINTER), int(0));
+    self.fixups.push((entry, 1, Fixup::CallStack));
+    if let Some(ty) = self.call("main", &[])? {
+      for _ in 0..ty.width() {
+        self.op(Opcode::Discard);
+      }
+    }
+    self.close_frame(); // the entry has no frame
+    self.op(Opcode::Exit);
+
+    for function in module.functions.iter() {
+      self.function(function)?;
+    }
+
+    let call_stack = self.program.static_data.len().next_multiple_of(SLOT_SIZE as usize);
+    for (idx, operand, fixup) in self.fixups.iter() {
+      let value = match fixup {
+        Fixup::Label(label) => self.labels[*label].expect("labels are placed once compiled"),
+        Fixup::CallStack => call_stack,
+        Fixup::FrameSize => unreachable!()
+      };
+      let instruction = &mut self.program.instructions[*idx];
+      *(if *operand == 0 { &mut instruction.1 } else { &mut instruction.2 }) = int(value as i64);
+    }
+    self.program.required_memory = self.program.required_memory.max(call_stack + CALL_STACK_SIZE);
+    Ok(())
+  }
+
+  fn function(&mut self, function: &Function) -> CompileResult<()> {
+    let label = self.functions[&function.name].label;
+    self.place(label);
+    self.scopes = vec![HashMap::new()];
+    self.frame_size = FRAME_HEADER;
+    self.ret = function.ret;
+    for (name, ty) in function.params.iter() {
+      self.allocate(name, *ty);
+    }
+
+    self.block(&function.body)?;
+
+    if !returns(&function.body) {
+      if function.ret.is_some() {
+        return error(function.span, InternalStructuredError::MissingReturn(function.name.clone()))
+      }
+      self.ret();
+    }
+    self.close_frame();
+    Ok(())
+  }
+
+  /// Restore the caller frame and jump back, the returned value stays on the stack
+  fn ret(&mut self) {
+    self.address(SLOT_SIZE, false);
+    self.op(Opcode::ReadInt64);
+    self.address(0, false);
+    self.op(Opcode::ReadInt64);
+    self.emit(Opcode::SetReg, int(FRAME_POINTER), None);
+    self.emit(Opcode::Jump, None, int(1));
+  }
+
+  fn block(&mut self, body: &[Stmt]) -> CompileResult<()> {
+    self.scopes.push(HashMap::new());
+    for stmt in body.iter() {
+      self.statement(stmt)?;
+    }
+    self.scopes.pop();
+    Ok(())
+  }
+
+  fn condition(&mut self, cond: &Expr, label: usize) -> CompileResult<()> {
+    match self.value(cond)? {
+      Type::Int => (),
+      ty => return mismatched(cond.span, Type::Int, ty)
+    }
+    self.emit(Opcode::Eq, None, int(0));
+    self.jump(label, None);
+    Ok(())
+  }
+
+  fn statement(&mut self, stmt: &Stmt) -> CompileResult<()> {
+    match &stmt.kind {
+      StmtKind::Let(name, declared, value) => {
+        let ty = self.value(value)?;
+        match declared {
+          Some(declared) if *declared != ty => return mismatched(value.span, *declared, ty),
+          _ => ()
+        }
+        let offset = self.allocate(name, ty);
+        self.write(ty, offset, false);
+      }
+      StmtKind::Assign(name, value) => {
+        let (offset, expected) = self.variable(name, stmt.span)?;
+        let ty = self.value(value)?;
+        if ty != expected {
+          return mismatched(value.span, expected, ty)
+        }
+        self.write(ty, offset, false);
+      }
+      StmtKind::If(cond, then, otherwise) => {
+        let (other, end) = (self.label(), self.label());
+        self.condition(cond, other)?;
+        self.block(then)?;
+        if !otherwise.is_empty() {
+          self.jump(end, int(1));
+        }
+        self.place(other);
+        self.block(otherwise)?;
+        self.place(end);
+      }
+      StmtKind::While(cond, body) => {
+        let (start, end) = (self.label(), self.label());
+        self.place(start);
+        self.condition(cond, end)?;
+        self.block(body)?;
+        self.jump(start, int(1));
+        self.place(end);
+      }
+      StmtKind::Return(value) => {
+        let ty = match value {
+          Some(value) => Some(self.value(value)?),
+          None => None
+        };
+        match (self.ret, ty) {
+          (Some(expected), Some(ty)) if expected != ty => return mismatched(stmt.span, expected, ty),
+          (Some(expected), None) => return error(stmt.span, InternalStructuredError::NoValue(expected.to_string())),
+          (None, Some(_)) => return error(stmt.span, InternalStructuredError::UnexpectedValue),
+          _ => ()
+        }
+        self.ret();
+      }
+      StmtKind::Expr(value) => {
+        if let Some(ty) = self.expression(value)? {
+          for _ in 0..ty.width() {
+            self.op(Opcode::Discard);
+          }
+        }
+      }
+    }
+    Ok(())
+  }
+
+  /// An expression that must produce a value
+  fn value(&mut self, expr: &Expr) -> CompileResult<Type> {
+    match self.expression(expr)? {
+      Some(ty) => Ok(ty),
+      None => error(expr.span, InternalStructuredError::NoValue("a value".into()))
+    }
+  }
+
+  fn expression(&mut self, expr: &Expr) -> CompileResult<Option<Type>> {
+    let ty = match &expr.kind {
+      ExprKind::Int(x) => {
+        self.emit(Opcode::Push, int(*x), None);
+        Type::Int
+      }
+      ExprKind::Float(x) => {
+        self.emit(Opcode::Push, Some(InstructionParam::Float(*x)), None);
+        Type::Float
+      }
+      ExprKind::Str(data) => {
+        let (address, size) = self.string(data);
+        self.emit(Opcode::Push, int(address), int(size));
+        Type::Str
+      }
+      ExprKind::Var(name) => {
+        let (offset, ty) = self.variable(name, expr.span)?;
+        self.read(ty, offset);
+        ty
+      }
+      ExprKind::Unary(UnaryOp::Neg, value) => match &value.kind {
+        ExprKind::Int(x) => { self.emit(Opcode::Push, int(-x), None); Type::Int }
+        ExprKind::Float(x) => { self.emit(Opcode::Push, Some(InstructionParam::Float(-x)), None); Type::Float }
+        _ => match self.value(value)? {
+          Type::Int => { self.emit(Opcode::Mul, None, int(-1)); Type::Int }
+          Type::Float => { self.emit(Opcode::Mul, None, Some(InstructionParam::Float(-1.0))); Type::Float }
+          Type::Str => return mismatched(value.span, Type::Int, Type::Str)
+        }
+      }
+      ExprKind::Unary(UnaryOp::Not, value) => {
+        self.logical(value)?;
+        self.emit(Opcode::Eq, None, int(0));
+        Type::Int
+      }
+      ExprKind::Binary(op @ (BinaryOp::And | BinaryOp::Or), left, right) => {
+        // short circuit leaving the normalized left value when it decides the result
+        let end = self.label();
+        self.logical(left)?;
+        self.emit(Opcode::Noteq, None, int(0));
+        self.op(Opcode::Clone);
+        if *op == BinaryOp::And {
+          self.emit(Opcode::Eq, None, int(0));
+        }
+        self.jump(end, None);
+        self.op(Opcode::Discard);
+        self.logical(right)?;
+        self.emit(Opcode::Noteq, None, int(0));
+        self.place(end);
+        Type::Int
+      }
+      ExprKind::Binary(op, left, right) => {
+        let ty = self.value(left)?;
+        if ty == Type::Str {
+          return error(left.span, InternalStructuredError::BadOperand(op.to_string(), ty.to_string()))
+        }
+        let found = self.value(right)?;
+        if found != ty {
+          return mismatched(right.span, ty, found)
+        }
+        // the vm operates the top of the stack with the second item
+        let (opcode, swap) = match op {
+          BinaryOp::Add => (Opcode::Add, false),
+          BinaryOp::Sub => (Opcode::Sub, true),
+          BinaryOp::Mul => (Opcode::Mul, false),
+          BinaryOp::Div => (Opcode::Div, true),
+          BinaryOp::Eq => (Opcode::Eq, false),
+          BinaryOp::Noteq => (Opcode::Noteq, false),
+          BinaryOp::Ls => (Opcode::Ls, true),
+          BinaryOp::Lseq => (Opcode::Lseq, true),
+          BinaryOp::Gt => (Opcode::Gt, true),
+          BinaryOp::Gteq => (Opcode::Gteq, true),
+          BinaryOp::And | BinaryOp::Or => unreachable!()
+        };
+        if swap {
+          self.op(Opcode::Swap);
+        }
+        self.op(opcode);
+        match op {
+          BinaryOp::Add | BinaryOp::Sub | BinaryOp::Mul | BinaryOp::Div => ty,
+          _ => Type::Int
+        }
+      }
+      ExprKind::Call(name, args) => {
+        let mut types = vec![];
+        for arg in args.iter() {
+          types.push((self.value(arg)?, arg.span));
+        }
+        return self.call_by_name(name, &types, expr.span)
+      }
+    };
+    Ok(Some(ty))
+  }
+
+  fn logical(&mut self, value: &Expr) -> CompileResult<()> {
+    match self.value(value)? {
+      Type::Int => Ok(()),
+      ty => mismatched(value.span, Type::Int, ty)
+    }
+  }
+
+  fn check_arguments(name: &str, params: &[Type], args: &[(Type, Span)], span: Span) -> CompileResult<()> {
+    if params.len() != args.len() {
+      return error(span, InternalStructuredError::BadArgumentCount(name.into(), params.len(), args.len()))
+    }
+    for (expected, (ty, span)) in params.iter().zip(args.iter()) {
+      if expected != ty {
+        return mismatched(*span, *expected, *ty)
+      }
+    }
+    Ok(())
+  }
+
+  /// The arguments are already on the stack
+  fn call_by_name(&mut self, name: &str, args: &[(Type, Span)], span: Span) -> CompileResult<Option<Type>> {
+    match name {
+      "int" | "float" => {
+        match args {
+          [(Type::Int | Type::Float, _)] => (),
+          [(ty, span)] => return mismatched(*span, Type::Int, *ty),
+          _ => return error(span, InternalStructuredError::BadArgumentCount(name.into(), 1, args.len()))
+        }
+        return Ok(Some(match name {
+          "int" => { self.op(Opcode::Int); Type::Int }
+          _ => { self.op(Opcode::Float); Type::Float }
+        }))
+      }
+      _ => ()
+    }
+
+    if let Some(signature) = self.functions.get(name) {
+      Self::check_arguments(name, &signature.params, args, span)?;
+      return self.call(name, &signature.params.clone())
+    }
+
+    if let Some(ExternSignature { params, ret, symbol }) = self.externs.get(name) {
+      Self::check_arguments(name, params, args, span)?;
+      let (params, ret, (address, size)) = (params.clone(), *ret, *symbol);
+      let registers: usize = params.iter().map(Type::width).sum();
+      for register in (0..registers).rev() {
+        self.emit(Opcode::SetReg, int(register as i64), None);
+      }
+      // strings are read by the ffi from the process memory
+      let share_memory = params.contains(&Type::Str);
+      self.emit(Opcode::SetReg, int(FLAG_SHARE_MEMORY), int(share_memory.into()));
+      self.emit(Opcode::FastInvoke, int(address), int(size));
+      return Ok(ret)
+    }
+
+    error(span, InternalStructuredError::FunctionNotFound(name.into()))
+  }
+
+  /// Move the arguments into the next frame, link it with the current one and jump
+  fn call(&mut self, name: &str, params: &[Type]) -> CompileResult<Option<Type>> {
+    let Signature { ret, label, .. } = self.functions[name];
+    let mut offset = FRAME_HEADER + params.iter().map(|ty| ty.width() as i64 * SLOT_SIZE).sum::<i64>();
+    for ty in params.iter().rev() {
+      offset -= ty.width() as i64 * SLOT_SIZE;
+      self.write(*ty, offset, true);
+    }
+
+    let back = self.label();
+    self.emit(Opcode::Reg, int(FRAME_POINTER), None);
+    self.address(0, true);
+    self.op(Opcode::WriteInt64);
+    let idx = self.emit(Opcode::Push, int(0), None);
+    self.fixups.push((idx, 0, Fixup::Label(back)));
+    self.program.code_references.push((idx, 0));
+    self.address(SLOT_SIZE, true);
+    self.op(Opcode::WriteInt64);
+    self.address(0, true);
+    self.emit(Opcode::SetReg, int(FRAME_POINTER), None);
+    self.jump(label, int(1));
+    self.place(back);
+    Ok(ret)
+  }
+}
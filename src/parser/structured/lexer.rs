@@ -0,0 +1,108 @@
+use crate::parser::{diagnostic::Span, v2};
+
+use super::{InternalStructuredError, StructuredError};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Token {
+  Ident(String),
+  Int(i64),
+  Float(f64),
+  Str(Vec<u8>),
+  Symbol(&'static str),
+  End
+}
+
+impl std::fmt::Display for Token {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    match self {
+      Token::Ident(name) => write!(f, "`{}`", name),
+      Token::Int(x) => write!(f, "`{}`", x),
+      Token::Float(x) => write!(f, "`{}`", x),
+      Token::Str(_) => write!(f, "string literal"),
+      Token::Symbol(symbol) => write!(f, "`{}`", symbol),
+      Token::End => write!(f, "end of file")
+    }
+  }
+}
+
+/// Longest symbols first so `==` is not read as two `=`
+const SYMBOLS: [&str; 22] = [
+  "->", "==", "!=", "<=", ">=", "&&", "||",
+  "(", ")", "{", "}", ",", ";", ":", "=", "<", ">", "+", "-", "*", "/", "!"
+];
+
+fn error(line: usize, column: usize, length: usize, error: InternalStructuredError) -> StructuredError {
+  StructuredError { span: Span::new(line, column, length), error }
+}
+
+/// Length in bytes of a string literal starting at the quote, escaped quotes included
+fn string_length(content: &str) -> Option<usize> {
+  let mut escaped = false;
+  for (idx, c) in content.char_indices().skip(1) {
+    match (c, escaped) {
+      ('"', false) => return Some(idx + 1),
+      ('\\', false) => escaped = true,
+      _ => escaped = false
+    }
+  }
+  None
+}
+
+fn tokenize_line(line: usize, content: &str, tokens: &mut Vec<(Token, Span)>) -> Result<(), StructuredError> {
+  let mut column = 0;
+  while let Some(c) = content[column..].chars().next() {
+    let rest = &content[column..];
+    if c.is_whitespace() {
+      column += c.len_utf8();
+      continue
+    }
+    if rest.starts_with("//") {
+      break
+    }
+
+    let (token, length) = if c.is_ascii_alphabetic() || c == '_' {
+      let length = rest.find(|x: char| !x.is_ascii_alphanumeric() && x != '_').unwrap_or(rest.len());
+      (Token::Ident(rest[..length].into()), length)
+    } else if c.is_ascii_digit() {
+      let length = rest.find(|x: char| !x.is_ascii_alphanumeric() && x != '.' && x != '_').unwrap_or(rest.len());
+      let literal = &rest[..length];
+      let token = match literal.contains('.') {
+        true => literal.parse().map(Token::Float).ok(),
+        false => literal.parse().map(Token::Int).ok()
+      };
+      match token {
+        Some(token) => (token, length),
+        None => return Err(error(line, column, length, InternalStructuredError::BadLiteral(literal.into())))
+      }
+    } else if c == '"' {
+      let Some(length) = string_length(rest) else {
+        return Err(error(line, column, rest.len(), InternalStructuredError::UnterminatedString))
+      };
+      match v2::parse_memory_chunk(&rest[..length]) {
+        Ok(data) => (Token::Str(data), length),
+        Err((offset, length, err)) => return Err(error(line, column + offset, length, InternalStructuredError::BadString(err.to_string())))
+      }
+    } else {
+      match SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+        Some(symbol) => (Token::Symbol(symbol), symbol.len()),
+        None => return Err(error(line, column, c.len_utf8(), InternalStructuredError::UnexpectedCharacter(c)))
+      }
+    };
+
+    tokens.push((token, Span::new(line, column, length)));
+    column += length;
+  }
+  Ok(())
+}
+
+/// Split the source in tokens, always ending with [`Token::End`]
+pub fn tokenize(source: &str) -> Result<Vec<(Token, Span)>, StructuredError> {
+  let mut tokens = vec![];
+  let mut last = Span::default();
+  for (line, content) in source.lines().enumerate() {
+    tokenize_line(line, content, &mut tokens)?;
+    last = Span::new(line, content.len(), 0);
+  }
+  tokens.push((Token::End, last));
+  Ok(tokens)
+}
@@ -0,0 +1,105 @@
+//! A small structured language compiled straight into a [`Program`]
+//!
+//! ```text
+//! extern std_println(str);
+//! extern std_reg_println(int);
+//!
+//! fn square(x: float) -> float {
+//!   return x * x;
+//! }
+//!
+//! fn main() {
+//!   let i = 0;
+//!   while i < 3 {
+//!     std_reg_println(i);
+//!     i = i + 1;
+//!   }
+//!   if square(1.5) > 2.0 { std_println("big"); } else { std_println("small"); }
+//! }
+//! ```
+//!
+//! - the types are `int`, `float` and `str` (a string literal, its address and size), there is no implicit conversion,
+//!   `int(x)` and `float(x)` convert between numbers; conditions and `&&`, `||`, `!` work with `int`
+//! - `let` infers the type of the variable unless written, `let x: float = 1.0;`
+//! - the program starts in `fn main()`, functions returning a value must end every path with `return`
+//! - `extern` declares a ffi function, the arguments fill the public registers in order (a string takes two),
+//!   when a string is passed the memory is shared with the function
+//!
+//! Locals live in frames in the process memory after the static data, the register 14 points to the current frame
+//! that starts with the caller frame and the return address; string literals and ffi names are kept in the static data
+
+use std::borrow::BorrowMut;
+
+use thiserror::Error;
+
+use crate::vm::program::Program;
+
+use super::{diagnostic::{self, Span}, Parser};
+
+mod compiler;
+mod lexer;
+mod syntax;
+
+pub use compiler::CALL_STACK_SIZE;
+
+pub struct Structured;
+
+#[derive(Error, Debug)]
+pub enum InternalStructuredError {
+  #[error("unexpected character: {0}")]
+  UnexpectedCharacter(char),
+  #[error("bad literal: {0}")]
+  BadLiteral(String),
+  #[error("unterminated string")]
+  UnterminatedString,
+  #[error("bad string: {0}")]
+  BadString(String),
+  #[error("expected {expected}, found {found}")]
+  Unexpected { expected: String, found: String },
+  #[error("variable not found: {0}")]
+  VariableNotFound(String),
+  #[error("function not found: {0}")]
+  FunctionNotFound(String),
+  #[error("function defined twice: {0}")]
+  DuplicateFunction(String),
+  #[error("main function without parameters not found")]
+  MainNotFound,
+  #[error("mismatched types: expected {expected}, found {found}")]
+  MismatchedTypes { expected: String, found: String },
+  #[error("operator {0} can not be applied to {1}")]
+  BadOperand(String, String),
+  #[error("{0} expects {1} arguments, found {2}")]
+  BadArgumentCount(String, usize, usize),
+  #[error("{0} needs {1} registers, only 10 are public")]
+  TooManyRegisters(String, usize),
+  #[error("missing return in function: {0}")]
+  MissingReturn(String),
+  #[error("expected {0}, found nothing")]
+  NoValue(String),
+  #[error("the function does not return a value")]
+  UnexpectedValue
+}
+
+#[derive(Error, Debug)]
+#[error("Error [LINE: {}, COLUMN: {}] :: {}", .span.line + 1, .span.column + 1, .error)]
+pub struct StructuredError {
+  pub span: Span,
+  pub error: InternalStructuredError
+}
+
+impl StructuredError {
+  /// Render the error with the source it refers to
+  pub fn render(&self, name: &str, source: &str) -> String {
+    diagnostic::render(name, source, self.span, &self.error)
+  }
+}
+
+impl Parser for Structured {
+  type Err = StructuredError;
+
+  fn parse(mut target: impl BorrowMut<Program>, source: impl AsRef<str>) -> Result<(), Self::Err> {
+    let tokens = lexer::tokenize(source.as_ref())?;
+    let module = syntax::SyntaxParser::new(tokens).module()?;
+    compiler::Compiler::new(target.borrow_mut()).compile(&module)
+  }
+}
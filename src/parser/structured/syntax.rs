@@ -0,0 +1,391 @@
+use strum_macros::Display;
+
+use crate::parser::diagnostic::Span;
+
+use super::{lexer::Token, InternalStructuredError, StructuredError};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display)]
+#[strum(serialize_all = "lowercase")]
+pub enum Type {
+  Int,
+  Float,
+  Str
+}
+
+impl Type {
+  /// Values taken in the stack, memory slots or registers, a string is its address and size
+  pub fn width(&self) -> usize {
+    match self {
+      Type::Int | Type::Float => 1,
+      Type::Str => 2
+    }
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UnaryOp {
+  Neg,
+  Not
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Display)]
+pub enum BinaryOp {
+  #[strum(serialize = "+")] Add,
+  #[strum(serialize = "-")] Sub,
+  #[strum(serialize = "*")] Mul,
+  #[strum(serialize = "/")] Div,
+  #[strum(serialize = "==")] Eq,
+  #[strum(serialize = "!=")] Noteq,
+  #[strum(serialize = "<")] Ls,
+  #[strum(serialize = "<=")] Lseq,
+  #[strum(serialize = ">")] Gt,
+  #[strum(serialize = ">=")] Gteq,
+  #[strum(serialize = "&&")] And,
+  #[strum(serialize = "||")] Or
+}
+
+#[derive(Clone, Debug)]
+pub enum ExprKind {
+  Int(i64),
+  Float(f64),
+  Str(Vec<u8>),
+  Var(String),
+  Unary(UnaryOp, Box<Expr>),
+  Binary(BinaryOp, Box<Expr>, Box<Expr>),
+  Call(String, Vec<Expr>)
+}
+
+#[derive(Clone, Debug)]
+pub struct Expr {
+  pub kind: ExprKind,
+  pub span: Span
+}
+
+#[derive(Clone, Debug)]
+pub enum StmtKind {
+  Let(String, Option<Type>, Expr),
+  Assign(String, Expr),
+  If(Expr, Vec<Stmt>, Vec<Stmt>),
+  While(Expr, Vec<Stmt>),
+  Return(Option<Expr>),
+  Expr(Expr)
+}
+
+#[derive(Clone, Debug)]
+pub struct Stmt {
+  pub kind: StmtKind,
+  pub span: Span
+}
+
+#[derive(Clone, Debug)]
+pub struct Function {
+  pub name: String,
+  pub params: Vec<(String, Type)>,
+  pub ret: Option<Type>,
+  pub body: Vec<Stmt>,
+  pub span: Span
+}
+
+#[derive(Clone, Debug)]
+pub struct Extern {
+  pub name: String,
+  pub params: Vec<Type>,
+  pub ret: Option<Type>,
+  pub span: Span
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Module {
+  pub functions: Vec<Function>,
+  pub externs: Vec<Extern>
+}
+
+const KEYWORDS: [&str; 9] = ["fn", "extern", "let", "if", "else", "while", "return", "int", "float"];
+
+/// Recursive descent over the tokens, from the lowest precedence: `||`, `&&`, comparisons, `+ -`, `* /` and unary
+pub struct SyntaxParser {
+  tokens: Vec<(Token, Span)>,
+  current: usize
+}
+
+impl SyntaxParser {
+  pub fn new(tokens: Vec<(Token, Span)>) -> Self {
+    SyntaxParser { tokens, current: 0 }
+  }
+
+  fn peek(&self) -> &Token {
+    &self.tokens[self.current].0
+  }
+
+  fn span(&self) -> Span {
+    self.tokens[self.current].1
+  }
+
+  fn previous_span(&self) -> Span {
+    self.tokens[self.current.saturating_sub(1)].1
+  }
+
+  fn advance(&mut self) -> Token {
+    let token = self.peek().clone();
+    if token != Token::End {
+      self.current += 1;
+    }
+    token
+  }
+
+  fn unexpected(&self, expected: impl Into<String>) -> StructuredError {
+    StructuredError {
+      span: self.span(),
+      error: InternalStructuredError::Unexpected { expected: expected.into(), found: self.peek().to_string() }
+    }
+  }
+
+  fn is_symbol(&self, symbol: &str) -> bool {
+    matches!(self.peek(), Token::Symbol(x) if *x == symbol)
+  }
+
+  fn is_keyword(&self, keyword: &str) -> bool {
+    matches!(self.peek(), Token::Ident(x) if x == keyword)
+  }
+
+  fn accept(&mut self, symbol: &str) -> bool {
+    let found = self.is_symbol(symbol);
+    if found {
+      self.advance();
+    }
+    found
+  }
+
+  fn expect(&mut self, symbol: &str) -> Result<(), StructuredError> {
+    match self.accept(symbol) {
+      true => Ok(()),
+      false => Err(self.unexpected(format!("`{}`", symbol)))
+    }
+  }
+
+  fn ident(&mut self) -> Result<(String, Span), StructuredError> {
+    match self.peek() {
+      Token::Ident(name) if !KEYWORDS.contains(&name.as_str()) => {
+        let name = name.clone();
+        let span = self.span();
+        self.advance();
+        Ok((name, span))
+      }
+      _ => Err(self.unexpected("identifier"))
+    }
+  }
+
+  fn ty(&mut self) -> Result<Type, StructuredError> {
+    let ty = match self.peek() {
+      Token::Ident(x) if x == "int" => Type::Int,
+      Token::Ident(x) if x == "float" => Type::Float,
+      Token::Ident(x) if x == "str" => Type::Str,
+      _ => return Err(self.unexpected("type"))
+    };
+    self.advance();
+    Ok(ty)
+  }
+
+  fn return_type(&mut self) -> Result<Option<Type>, StructuredError> {
+    match self.accept("->") {
+      true => self.ty().map(Some),
+      false => Ok(None)
+    }
+  }
+
+  /// Comma separated items between parenthesis
+  fn list<T>(&mut self, mut item: impl FnMut(&mut Self) -> Result<T, StructuredError>) -> Result<Vec<T>, StructuredError> {
+    self.expect("(")?;
+    let mut items = vec![];
+    while !self.accept(")") {
+      if !items.is_empty() {
+        self.expect(",")?;
+      }
+      items.push(item(self)?);
+    }
+    Ok(items)
+  }
+
+  pub fn module(&mut self) -> Result<Module, StructuredError> {
+    let mut module = Module::default();
+    loop {
+      if self.is_keyword("fn") {
+        module.functions.push(self.function()?);
+      } else if self.is_keyword("extern") {
+        module.externs.push(self.extern_declaration()?);
+      } else if *self.peek() == Token::End {
+        return Ok(module)
+      } else {
+        return Err(self.unexpected("`fn` or `extern`"))
+      }
+    }
+  }
+
+  fn function(&mut self) -> Result<Function, StructuredError> {
+    self.advance();
+    let (name, span) = self.ident()?;
+    let params = self.list(|parser| {
+      let (name, _) = parser.ident()?;
+      parser.expect(":")?;
+      Ok((name, parser.ty()?))
+    })?;
+    let ret = self.return_type()?;
+    let body = self.block()?;
+    Ok(Function { name, params, ret, body, span })
+  }
+
+  fn extern_declaration(&mut self) -> Result<Extern, StructuredError> {
+    self.advance();
+    let (name, span) = self.ident()?;
+    let params = self.list(Self::ty)?;
+    let ret = self.return_type()?;
+    self.expect(";")?;
+    Ok(Extern { name, params, ret, span })
+  }
+
+  fn block(&mut self) -> Result<Vec<Stmt>, StructuredError> {
+    self.expect("{")?;
+    let mut statements = vec![];
+    while !self.accept("}") {
+      statements.push(self.statement()?);
+    }
+    Ok(statements)
+  }
+
+  fn statement(&mut self) -> Result<Stmt, StructuredError> {
+    let span = self.span();
+    let kind = if self.is_keyword("let") {
+      self.advance();
+      let (name, _) = self.ident()?;
+      let ty = match self.accept(":") {
+        true => Some(self.ty()?),
+        false => None
+      };
+      self.expect("=")?;
+      let value = self.expression()?;
+      self.expect(";")?;
+      StmtKind::Let(name, ty, value)
+    } else if self.is_keyword("if") {
+      return self.if_statement()
+    } else if self.is_keyword("while") {
+      self.advance();
+      let cond = self.expression()?;
+      StmtKind::While(cond, self.block()?)
+    } else if self.is_keyword("return") {
+      self.advance();
+      let value = match self.is_symbol(";") {
+        true => None,
+        false => Some(self.expression()?)
+      };
+      self.expect(";")?;
+      StmtKind::Return(value)
+    } else if matches!(self.peek(), Token::Ident(_)) && matches!(self.tokens[self.current + 1].0, Token::Symbol("=")) {
+      let (name, _) = self.ident()?;
+      self.advance();
+      let value = self.expression()?;
+      self.expect(";")?;
+      StmtKind::Assign(name, value)
+    } else {
+      let value = self.expression()?;
+      self.expect(";")?;
+      StmtKind::Expr(value)
+    };
+    Ok(Stmt { kind, span })
+  }
+
+  fn if_statement(&mut self) -> Result<Stmt, StructuredError> {
+    let span = self.span();
+    self.advance();
+    let cond = self.expression()?;
+    let then = self.block()?;
+    let otherwise = match self.is_keyword("else") {
+      true => {
+        self.advance();
+        match self.is_keyword("if") {
+          true => vec![self.if_statement()?],
+          false => self.block()?
+        }
+      }
+      false => vec![]
+    };
+    Ok(Stmt { kind: StmtKind::If(cond, then, otherwise), span })
+  }
+
+  pub fn expression(&mut self) -> Result<Expr, StructuredError> {
+    self.binary(0)
+  }
+
+  fn binary(&mut self, level: usize) -> Result<Expr, StructuredError> {
+    const LEVELS: [&[(&str, BinaryOp)]; 5] = [
+      &[("||", BinaryOp::Or)],
+      &[("&&", BinaryOp::And)],
+      &[
+        ("==", BinaryOp::Eq), ("!=", BinaryOp::Noteq), ("<=", BinaryOp::Lseq),
+        (">=", BinaryOp::Gteq), ("<", BinaryOp::Ls), (">", BinaryOp::Gt)
+      ],
+      &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+      &[("*", BinaryOp::Mul), ("/", BinaryOp::Div)]
+    ];
+    if level == LEVELS.len() {
+      return self.unary()
+    }
+
+    let mut left = self.binary(level + 1)?;
+    while let Some((_, op)) = LEVELS[level].iter().find(|(symbol, _)| self.is_symbol(symbol)) {
+      self.advance();
+      let right = self.binary(level + 1)?;
+      let span = join(left.span, right.span);
+      left = Expr { kind: ExprKind::Binary(*op, Box::new(left), Box::new(right)), span };
+    }
+    Ok(left)
+  }
+
+  fn unary(&mut self) -> Result<Expr, StructuredError> {
+    let span = self.span();
+    let op = match self.peek() {
+      Token::Symbol("-") => UnaryOp::Neg,
+      Token::Symbol("!") => UnaryOp::Not,
+      _ => return self.primary()
+    };
+    self.advance();
+    let value = self.unary()?;
+    let span = join(span, value.span);
+    Ok(Expr { kind: ExprKind::Unary(op, Box::new(value)), span })
+  }
+
+  fn primary(&mut self) -> Result<Expr, StructuredError> {
+    let span = self.span();
+    let kind = match self.peek().clone() {
+      Token::Int(x) => { self.advance(); ExprKind::Int(x) }
+      Token::Float(x) => { self.advance(); ExprKind::Float(x) }
+      Token::Str(x) => { self.advance(); ExprKind::Str(x) }
+      Token::Symbol("(") => {
+        self.advance();
+        let value = self.expression()?;
+        self.expect(")")?;
+        return Ok(Expr { kind: value.kind, span: join(span, self.previous_span()) })
+      }
+      // the casts are calls to builtins
+      Token::Ident(name) if name == "int" || name == "float" || !KEYWORDS.contains(&name.as_str()) => {
+        self.advance();
+        match self.is_symbol("(") {
+          true => {
+            let args = self.list(Self::expression)?;
+            return Ok(Expr { kind: ExprKind::Call(name, args), span: join(span, self.previous_span()) })
+          }
+          false => ExprKind::Var(name)
+        }
+      }
+      _ => return Err(self.unexpected("expression"))
+    };
+    Ok(Expr { kind, span })
+  }
+}
+
+/// Span from the start of a to the end of b when both are in the same line, otherwise a
+fn join(a: Span, b: Span) -> Span {
+  match a.line == b.line && b.column >= a.column {
+    true => Span::new(a.line, a.column, b.column + b.length - a.column),
+    false => a
+  }
+}
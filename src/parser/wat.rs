@@ -0,0 +1,1209 @@
+//! Translation of a subset of the WebAssembly text format
+//!
+//! ```text
+//! (module
+//!   (import "env" "std_reg_println" (func $print (param i64)))
+//!   (func $main (export "main") (local $i i64)
+//!     (block $done
+//!       (loop $next
+//!         (br_if $done (i64.ge_s (local.get $i) (i64.const 3)))
+//!         (call $print (local.get $i))
+//!         (local.set $i (i64.add (local.get $i) (i64.const 1)))
+//!         (br $next)))))
+//! ```
+//!
+//! - values are `i64` and `f64`, `i32` is only accepted for the constant offsets of the data segments
+//! - both the flat and the folded instructions, `block`, `loop`, `if`, `br`, `br_if`, `return`, `call`, `select`
+//! - functions return at most one value
+//! - the linear memory is the start of the process memory, `load` and `store` (8, 16, 32 and 64 bits, signed)
+//!   access it directly, the data segments are part of the static data
+//! - imported functions are ffi functions, the arguments fill the public registers in order; when the import module
+//!   is `"memory"` the process memory is shared with the function, `(import "memory" "std_println" ...)`
+//! - the program starts in the `start` function, otherwise in the one exported as `main` or `_start`
+//!
+//! Globals and the names of the ffi functions follow the linear memory, then the frames of the calls,
+//! the register 14 points to the current frame that starts with the caller frame and the return address
+
+use std::{borrow::BorrowMut, collections::HashMap};
+
+use thiserror::Error;
+
+use crate::vm::program::{Instruction, InstructionParam, Opcode, Program};
+
+use super::{diagnostic::{self, Span}, Parser};
+
+pub struct Wat;
+
+/// Register holding the address of the current frame
+const FRAME_POINTER: i64 = 14;
+const FLAG_SHARE_MEMORY: i64 = 10;
+const FRAME_HEADER: i64 = 16;
+const SLOT_SIZE: i64 = 8;
+const PAGE_SIZE: usize = 64 * 1024;
+/// Memory reserved for the frames after the static data
+pub const CALL_STACK_SIZE: usize = 64 * 1024;
+
+#[derive(Error, Debug)]
+pub enum InternalWatError {
+  #[error("unexpected character: {0}")]
+  UnexpectedCharacter(char),
+  #[error("missing closing {0}")]
+  Unterminated(char),
+  #[error("bad escape sequence: {0}")]
+  BadEscape(String),
+  #[error("expected {0}")]
+  Expected(String),
+  #[error("unsupported: {0}")]
+  Unsupported(String),
+  #[error("not found: {0}")]
+  NotFound(String),
+  #[error("defined twice: {0}")]
+  Duplicate(String),
+  #[error("stack underflow")]
+  StackUnderflow,
+  #[error("the block ends with {found} values, expected {expected}")]
+  BadBlockHeight { expected: usize, found: usize },
+  #[error("{0} needs {1} registers, only 10 are public")]
+  TooManyRegisters(String, usize),
+  #[error("no start function nor export named main or _start")]
+  NoEntry
+}
+
+#[derive(Error, Debug)]
+#[error("Error [LINE: {}, COLUMN: {}] :: {}", .span.line + 1, .span.column + 1, .error)]
+pub struct WatError {
+  pub span: Span,
+  pub error: InternalWatError
+}
+
+impl WatError {
+  /// Render the error with the source it refers to
+  pub fn render(&self, name: &str, source: &str) -> String {
+    diagnostic::render(name, source, self.span, &self.error)
+  }
+}
+
+type WatResult<T> = Result<T, WatError>;
+
+fn error<T>(span: Span, error: InternalWatError) -> WatResult<T> {
+  Err(WatError { span, error })
+}
+
+enum Sexp {
+  Atom(String, Span),
+  Str(Vec<u8>, Span),
+  List(Vec<Sexp>, Span)
+}
+
+impl Sexp {
+  fn span(&self) -> Span {
+    match self {
+      Sexp::Atom(_, span) | Sexp::Str(_, span) | Sexp::List(_, span) => *span
+    }
+  }
+
+  fn atom(&self) -> Option<&str> {
+    match self {
+      Sexp::Atom(atom, _) => Some(atom),
+      _ => None
+    }
+  }
+
+  /// The items of a list starting with the keyword, without it
+  fn form(&self, keyword: &str) -> Option<&[Sexp]> {
+    match self {
+      Sexp::List(items, _) if items.first().and_then(Sexp::atom) == Some(keyword) => Some(&items[1..]),
+      _ => None
+    }
+  }
+}
+
+struct Reader<'a> {
+  source: &'a str,
+  position: usize,
+  line: usize,
+  line_start: usize
+}
+
+impl<'a> Reader<'a> {
+  fn peek(&self) -> Option<char> {
+    self.source[self.position..].chars().next()
+  }
+
+  fn bump(&mut self) -> Option<char> {
+    let c = self.peek()?;
+    self.position += c.len_utf8();
+    if c == '\n' {
+      self.line += 1;
+      self.line_start = self.position;
+    }
+    Some(c)
+  }
+
+  fn here(&self) -> (usize, usize) {
+    (self.line, self.position - self.line_start)
+  }
+
+  fn span(&self, (line, column): (usize, usize)) -> Span {
+    match line == self.line {
+      true => Span::new(line, column, self.position - self.line_start - column),
+      false => Span::new(line, column, 1)
+    }
+  }
+
+  /// Skip whitespace, line comments `;;` and nested block comments `(; ;)`
+  fn skip(&mut self) -> WatResult<()> {
+    loop {
+      let rest = &self.source[self.position..];
+      if rest.starts_with(char::is_whitespace) {
+        self.bump();
+      } else if rest.starts_with(";;") {
+        while !matches!(self.bump(), Some('\n') | None) {}
+      } else if rest.starts_with("(;") {
+        let start = self.here();
+        let mut depth = 0;
+        loop {
+          let rest = &self.source[self.position..];
+          if rest.starts_with("(;") {
+            depth += 1;
+            self.bump();
+          } else if rest.starts_with(";)") {
+            depth -= 1;
+            self.bump();
+            if depth == 0 {
+              self.bump();
+              break
+            }
+          } else if rest.is_empty() {
+            return error(Span::new(start.0, start.1, 2), InternalWatError::Unterminated(')'))
+          }
+          self.bump();
+        }
+      } else {
+        return Ok(())
+      }
+    }
+  }
+
+  fn string(&mut self) -> WatResult<Vec<u8>> {
+    let start = self.here();
+    self.bump();
+    let mut data = vec![];
+    loop {
+      let escape = self.here();
+      match self.bump() {
+        Some('"') => return Ok(data),
+        Some('\\') => {
+          let c = self.bump().unwrap_or_default();
+          match c {
+            'n' => data.push(b'\n'),
+            't' => data.push(b'\t'),
+            'r' => data.push(b'\r'),
+            '\\' | '\'' | '"' => data.push(c as u8),
+            'u' => {
+              let digits = self.source[self.position..].strip_prefix('{').and_then(|x| x.split_once('}')).map(|x| x.0);
+              let code = digits.and_then(|x| u32::from_str_radix(x, 16).ok()).and_then(char::from_u32);
+              let (Some(digits), Some(code)) = (digits, code) else {
+                return error(self.span(escape), InternalWatError::BadEscape("\\u".into()))
+              };
+              for _ in 0..(digits.len() + 2) {
+                self.bump();
+              }
+              data.extend_from_slice(code.encode_utf8(&mut [0; 4]).as_bytes());
+            }
+            _ => {
+              let low = self.bump().unwrap_or_default();
+              match u8::from_str_radix(&format!("{}{}", c, low), 16) {
+                Ok(byte) => data.push(byte),
+                Err(_) => return error(self.span(escape), InternalWatError::BadEscape(format!("\\{}{}", c, low)))
+              }
+            }
+          }
+        }
+        Some(c) => data.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        None => return error(Span::new(start.0, start.1, 1), InternalWatError::Unterminated('"'))
+      }
+    }
+  }
+
+  fn sexp(&mut self) -> WatResult<Sexp> {
+    let start = self.here();
+    match self.peek() {
+      Some('(') => {
+        self.bump();
+        let mut items = vec![];
+        loop {
+          self.skip()?;
+          match self.peek() {
+            Some(')') => {
+              self.bump();
+              return Ok(Sexp::List(items, self.span(start)))
+            }
+            None => return error(Span::new(start.0, start.1, 1), InternalWatError::Unterminated(')')),
+            _ => items.push(self.sexp()?)
+          }
+        }
+      }
+      Some('"') => {
+        let data = self.string()?;
+        Ok(Sexp::Str(data, self.span(start)))
+      }
+      _ => {
+        let begin = self.position;
+        while self.peek().is_some_and(|c| !c.is_whitespace() && !matches!(c, '(' | ')' | '"' | ';')) {
+          self.bump();
+        }
+        if self.position == begin {
+          let c = self.bump().unwrap_or_default();
+          return error(self.span(start), InternalWatError::UnexpectedCharacter(c))
+        }
+        Ok(Sexp::Atom(self.source[begin..self.position].into(), self.span(start)))
+      }
+    }
+  }
+
+  fn read(source: &'a str) -> WatResult<Vec<Sexp>> {
+    let mut reader = Reader { source, position: 0, line: 0, line_start: 0 };
+    let mut items = vec![];
+    loop {
+      reader.skip()?;
+      if reader.peek().is_none() {
+        return Ok(items)
+      }
+      items.push(reader.sexp()?);
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum ValType {
+  I64,
+  F64
+}
+
+impl ValType {
+  fn parse(item: &Sexp) -> WatResult<Self> {
+    match item.atom() {
+      Some("i64") => Ok(ValType::I64),
+      Some("f64") => Ok(ValType::F64),
+      Some(other) => error(item.span(), InternalWatError::Unsupported(format!("type {}", other))),
+      None => error(item.span(), InternalWatError::Expected("a type".into()))
+    }
+  }
+
+  fn read(&self) -> Opcode {
+    match self {
+      ValType::I64 => Opcode::ReadInt64,
+      ValType::F64 => Opcode::ReadFloat64
+    }
+  }
+
+  fn write(&self) -> Opcode {
+    match self {
+      ValType::I64 => Opcode::WriteInt64,
+      ValType::F64 => Opcode::WriteFloat64
+    }
+  }
+
+  fn zero(&self) -> InstructionParam {
+    match self {
+      ValType::I64 => InstructionParam::Int(0),
+      ValType::F64 => InstructionParam::Float(0.0)
+    }
+  }
+}
+
+#[derive(Clone, Default)]
+struct FuncType {
+  params: Vec<ValType>,
+  results: Vec<ValType>
+}
+
+enum Callee {
+  Import { symbol: (i64, i64), share_memory: bool },
+  Defined { label: usize }
+}
+
+struct Func {
+  name: String,
+  ty: FuncType,
+  callee: Callee,
+  span: Span
+}
+
+struct Global {
+  ty: ValType,
+  address: i64
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum BlockKind {
+  Block,
+  Loop,
+  If,
+  Function
+}
+
+struct Block {
+  name: Option<String>,
+  kind: BlockKind,
+  /// Where a branch jumps, the start for loops and the end otherwise
+  target: usize,
+  height: usize,
+  results: usize,
+  /// The start of the else branch of an if, placed at the end when there is none
+  other: Option<usize>
+}
+
+impl Block {
+  /// Values a branch to the block carries
+  fn arity(&self) -> usize {
+    match self.kind {
+      BlockKind::Loop => 0,
+      _ => self.results
+    }
+  }
+}
+
+/// An index (`0`) or an identifier (`$name`) looked up in the names
+fn index(item: Option<&Sexp>, names: &HashMap<String, usize>, span: Span) -> WatResult<usize> {
+  let Some(item) = item else {
+    return error(span, InternalWatError::Expected("an index".into()))
+  };
+  match item.atom() {
+    Some(name) if name.starts_with('$') => match names.get(name) {
+      Some(idx) => Ok(*idx),
+      None => error(item.span(), InternalWatError::NotFound(name.into()))
+    },
+    Some(idx) => idx.parse().or_else(|_| error(item.span(), InternalWatError::Expected("an index".into()))),
+    None => error(item.span(), InternalWatError::Expected("an index".into()))
+  }
+}
+
+/// The name, exports and signature at the start of a function, before its body
+fn is_declaration(item: &Sexp) -> bool {
+  item.atom().is_some_and(|x| x.starts_with('$'))
+    || ["export", "type", "param", "result", "local"].iter().any(|x| item.form(x).is_some())
+}
+
+fn int(x: i64) -> Option<InstructionParam> {
+  Some(InstructionParam::Int(x))
+}
+
+/// Parse a number literal, with `_` separators and hexadecimal integers
+fn number<T: std::str::FromStr>(item: Option<&Sexp>, span: Span) -> WatResult<T> {
+  let Some(atom) = item.and_then(Sexp::atom) else {
+    return error(span, InternalWatError::Expected("a number".into()))
+  };
+  let clean = atom.replace('_', "");
+  let (negative, digits) = match clean.strip_prefix('-') {
+    Some(digits) => (true, digits),
+    None => (false, clean.strip_prefix('+').unwrap_or(&clean))
+  };
+  let parsed = match digits.strip_prefix("0x") {
+    Some(hex) => u64::from_str_radix(hex, 16).ok()
+      .map(|x| if negative { (x as i64).wrapping_neg() } else { x as i64 })
+      .and_then(|x| x.to_string().parse().ok()),
+    None => clean.parse().ok()
+  };
+  match parsed {
+    Some(value) => Ok(value),
+    None => error(item.unwrap().span(), InternalWatError::Expected("a number".into()))
+  }
+}
+
+struct Translator<'a> {
+  program: &'a mut Program,
+  types: Vec<FuncType>,
+  type_names: HashMap<String, usize>,
+  funcs: Vec<Func>,
+  func_names: HashMap<String, usize>,
+  globals: Vec<Global>,
+  global_names: HashMap<String, usize>,
+  labels: Vec<Option<usize>>,
+  fixups: Vec<(usize, usize, Option<usize>)>, // instruction, operand, label or the call stack when none
+  // the function being translated
+  locals: Vec<ValType>,
+  local_names: HashMap<String, usize>,
+  results: usize,
+  blocks: Vec<Block>,
+  height: usize,
+  unreachable: bool
+}
+
+impl<'a> Translator<'a> {
+  fn new(program: &'a mut Program) -> Self {
+    Translator {
+      program,
+      types: vec![],
+      type_names: HashMap::new(),
+      funcs: vec![],
+      func_names: HashMap::new(),
+      globals: vec![],
+      global_names: HashMap::new(),
+      labels: vec![],
+      fixups: vec![],
+      locals: vec![],
+      local_names: HashMap::new(),
+      results: 0,
+      blocks: vec![],
+      height: 0,
+      unreachable: false
+    }
+  }
+
+  fn emit(&mut self, opcode: Opcode, first: Option<InstructionParam>, second: Option<InstructionParam>) {
+    self.program.instructions.push(Instruction::with_args(opcode, first, second));
+  }
+
+  fn op(&mut self, opcode: Opcode) {
+    self.emit(opcode, None, None);
+  }
+
+  fn label(&mut self) -> usize {
+    self.labels.push(None);
+    self.labels.len() - 1
+  }
+
+  fn place(&mut self, label: usize) {
+    self.labels[label] = Some(self.program.instructions.len());
+  }
+
+  fn jump(&mut self, label: usize, cond: Option<InstructionParam>) {
+    self.fixups.push((self.program.instructions.len(), 0, Some(label)));
+    self.emit(Opcode::Jump, int(0), cond);
+  }
+
+  /// Track the values taken and left in the stack by an instruction
+  fn effect(&mut self, pops: usize, pushes: usize, span: Span) -> WatResult<()> {
+    let base = self.blocks.last().map(|x| x.height).unwrap_or(0);
+    match (self.height.checked_sub(pops), self.unreachable) {
+      (Some(height), _) if height >= base => self.height = height,
+      (_, true) => self.height = base,
+      _ => return error(span, InternalWatError::StackUnderflow)
+    }
+    self.height += pushes;
+    Ok(())
+  }
+
+  fn frame_size(&self) -> i64 {
+    FRAME_HEADER + self.locals.len() as i64 * SLOT_SIZE
+  }
+
+  fn frame_address(&mut self, offset: i64) {
+    self.emit(Opcode::Reg, int(FRAME_POINTER), None);
+    self.emit(Opcode::Add, None, int(offset));
+  }
+
+  fn static_string(&mut self, data: &[u8]) -> (i64, i64) {
+    let address = self.static_variable(data);
+    self.program.static_data_meta.push((address as usize, data.len()));
+    (address, data.len() as i64)
+  }
+
+  /// Writable static data, without a meta entry
+  fn static_variable(&mut self, data: &[u8]) -> i64 {
+    let address = self.program.static_data.len();
+    self.program.static_data.extend_from_slice(data);
+    address as i64
+  }
+
+  /// Param and result declarations, from a type use or written inline
+  fn signature(&self, items: &[Sexp], names: Option<&mut HashMap<String, usize>>) -> WatResult<FuncType> {
+    let mut ty = FuncType::default();
+    let mut type_use = None;
+    let mut param_names = HashMap::new();
+    for item in items.iter().take_while(|x| is_declaration(x)) {
+      if let Some(used) = item.form("type") {
+        let idx = index(used.first(), &self.type_names, item.span())?;
+        match self.types.get(idx) {
+          Some(used) => type_use = Some(used.clone()),
+          None => return error(item.span(), InternalWatError::NotFound(format!("type {}", idx)))
+        }
+      } else if let Some(params) = item.form("param") {
+        match params.first().and_then(Sexp::atom) {
+          Some(name) if name.starts_with('$') => {
+            param_names.insert(name.to_string(), ty.params.len());
+            ty.params.push(ValType::parse(params.get(1).unwrap_or(item))?);
+          }
+          _ => for param in params.iter() {
+            ty.params.push(ValType::parse(param)?);
+          }
+        }
+      } else if let Some(results) = item.form("result") {
+        for result in results.iter() {
+          ty.results.push(ValType::parse(result)?);
+        }
+      }
+    }
+    if ty.results.len() > 1 {
+      return error(items[0].span(), InternalWatError::Unsupported("multiple results".into()))
+    }
+    if let Some(names) = names {
+      names.extend(param_names);
+    }
+    Ok(type_use.unwrap_or(ty)) // the inline declarations repeat the type
+  }
+
+  fn name(items: &[Sexp]) -> Option<String> {
+    items.first().and_then(Sexp::atom).filter(|x| x.starts_with('$')).map(String::from)
+  }
+
+  fn declare(names: &mut HashMap<String, usize>, name: Option<String>, idx: usize, span: Span) -> WatResult<()> {
+    if let Some(name) = name {
+      if names.insert(name.clone(), idx).is_some() {
+        return error(span, InternalWatError::Duplicate(name))
+      }
+    }
+    Ok(())
+  }
+
+  fn translate(mut self, source: &str) -> WatResult<()> {
+    let items = Reader::read(source)?;
+    let fields = match items.as_slice() {
+      [module] => match module.form("module") {
+        Some(fields) => fields,
+        None => return error(module.span(), InternalWatError::Expected("(module ...)".into()))
+      },
+      _ => return error(Span::default(), InternalWatError::Expected("a single (module ...)".into()))
+    };
+    let fields = match Self::name(fields) {
+      Some(_) => &fields[1..],
+      None => fields
+    };
+
+    // the index spaces and the memory layout
+    let mut pages = 0;
+    let mut exports = HashMap::new();
+    let mut start = None;
+    for field in fields.iter() {
+      if let Some(items) = field.form("type") {
+        let Some(func) = items.iter().find_map(|x| x.form("func")) else {
+          return error(field.span(), InternalWatError::Expected("(func ...)".into()))
+        };
+        let ty = self.signature(func, None)?;
+        Self::declare(&mut self.type_names, Self::name(items), self.types.len(), field.span())?;
+        self.types.push(ty);
+      } else if let Some(items) = field.form("memory") {
+        let items = match Self::name(items) {
+          Some(_) => &items[1..],
+          None => items
+        };
+        pages = number(items.iter().find(|x| x.atom().is_some()), field.span())?;
+      }
+    }
+    self.program.static_data = vec![0; pages * PAGE_SIZE];
+
+    for field in fields.iter() {
+      if let Some(items) = field.form("import") {
+        let (module, function) = match items {
+          [Sexp::Str(module, _), Sexp::Str(function, _), ..] => (module.clone(), function.clone()),
+          _ => return error(field.span(), InternalWatError::Expected("the module and function names".into()))
+        };
+        let Some(func) = items.get(2).and_then(|x| x.form("func")) else {
+          return error(field.span(), InternalWatError::Unsupported("imports other than functions".into()))
+        };
+        let ty = self.signature(func, None)?;
+        if ty.params.len() > 10 {
+          let name = String::from_utf8_lossy(&function).into();
+          return error(field.span(), InternalWatError::TooManyRegisters(name, ty.params.len()))
+        }
+        Self::declare(&mut self.func_names, Self::name(func), self.funcs.len(), field.span())?;
+        let name = String::from_utf8_lossy(&function).into();
+        let callee = Callee::Import { symbol: (0, 0), share_memory: module == b"memory" };
+        self.funcs.push(Func { name, ty, callee, span: field.span() });
+      }
+    }
+
+    for field in fields.iter() {
+      if let Some(items) = field.form("func") {
+        let ty = self.signature(items, None)?;
+        let name = Self::name(items);
+        for export in items.iter().filter_map(|x| x.form("export")) {
+          if let Some(Sexp::Str(export, _)) = export.first() {
+            exports.insert(export.clone(), self.funcs.len());
+          }
+        }
+        Self::declare(&mut self.func_names, name.clone(), self.funcs.len(), field.span())?;
+        let label = self.label();
+        self.funcs.push(Func { name: name.unwrap_or_default(), ty, callee: Callee::Defined { label }, span: field.span() });
+      } else if let Some(items) = field.form("export") {
+        match items {
+          [Sexp::Str(export, _), target] if target.form("func").is_some() => {
+            let idx = index(target.form("func").unwrap().first(), &self.func_names, target.span())?;
+            exports.insert(export.clone(), idx);
+          }
+          _ => ()
+        }
+      } else if let Some(items) = field.form("start") {
+        start = Some(index(items.first(), &self.func_names, field.span())?);
+      } else if let Some(items) = field.form("data") {
+        let offset = items.iter()
+          .find_map(|x| x.form("i32.const").or(x.form("i64.const")).or(x.form("offset").and_then(|x| x.first()?.form("i32.const"))))
+          .map(|x| number::<usize>(x.first(), field.span()))
+          .unwrap_or(Ok(0))?;
+        for data in items.iter() {
+          if let Sexp::Str(data, span) = data {
+            match self.program.static_data.get_mut(offset..(offset + data.len())) {
+              Some(target) => target.copy_from_slice(data),
+              None => return error(*span, InternalWatError::Expected("data inside the memory".into()))
+            }
+          }
+        }
+      }
+    }
+
+    for field in fields.iter() {
+      if let Some(items) = field.form("global") {
+        let name = Self::name(items);
+        let items = if name.is_some() { &items[1..] } else { items };
+        let (ty, init) = match items {
+          [ty, init] => (ty.form("mut").and_then(|x| x.first()).unwrap_or(ty), init),
+          _ => return error(field.span(), InternalWatError::Expected("the type and initial value".into()))
+        };
+        let ty = ValType::parse(ty)?;
+        let bytes = match (ty, init.form("i64.const"), init.form("f64.const")) {
+          (ValType::I64, Some(value), _) => number::<i64>(value.first(), init.span())?.to_le_bytes(),
+          (ValType::F64, _, Some(value)) => number::<f64>(value.first(), init.span())?.to_le_bytes(),
+          _ => return error(init.span(), InternalWatError::Unsupported("not constant initial value".into()))
+        };
+        let address = self.static_variable(&bytes);
+        Self::declare(&mut self.global_names, name, self.globals.len(), field.span())?;
+        self.globals.push(Global { ty, address });
+      }
+    }
+
+    for idx in 0..self.funcs.len() {
+      if let Callee::Import { share_memory, .. } = self.funcs[idx].callee {
+        let name = self.funcs[idx].name.clone();
+        let symbol = self.static_string(name.as_bytes());
+        self.funcs[idx].callee = Callee::Import { symbol, share_memory };
+      }
+    }
+
+    let entry = start
+      .or_else(|| exports.get(b"main".as_slice()).copied())
+      .or_else(|| exports.get(b"_start".as_slice()).copied());
+    let Some(entry) = entry else {
+      return error(Span::default(), InternalWatError::NoEntry)
+    };
+    self.fixups.push((self.program.instructions.len(), 1, None));
+    self.emit(Opcode::SetReg, int(FRAME_POINTER), int(0));
+    self.call(entry);
+    for _ in 0..self.funcs[entry].ty.results.len() {
+      self.op(Opcode::Discard);
+    }
+    self.op(Opcode::Exit);
+
+    let mut defined = 0;
+    for field in fields.iter() {
+      if let Some(items) = field.form("func") {
+        while !matches!(self.funcs[defined].callee, Callee::Defined { .. }) {
+          defined += 1;
+        }
+        self.function(defined, items)?;
+        defined += 1;
+      }
+    }
+
+    let call_stack = self.program.static_data.len().next_multiple_of(SLOT_SIZE as usize);
+    for (idx, operand, label) in self.fixups.iter() {
+      let value = match label {
+        Some(label) => self.labels[*label].expect("labels are placed once translated"),
+        None => call_stack
+      };
+      let instruction = &mut self.program.instructions[*idx];
+      *(if *operand == 0 { &mut instruction.1 } else { &mut instruction.2 }) = int(value as i64);
+    }
+    self.program.required_memory = self.program.required_memory.max(call_stack + CALL_STACK_SIZE);
+    Ok(())
+  }
+
+  fn function(&mut self, idx: usize, items: &[Sexp]) -> WatResult<()> {
+    let Callee::Defined { label } = self.funcs[idx].callee else { unreachable!() };
+    self.place(label);
+    let mut names = HashMap::new();
+    let ty = self.signature(items, Some(&mut names))?;
+    self.local_names = names;
+    self.locals = ty.params.clone();
+    self.results = ty.results.len();
+
+    let body = items.iter().position(|x| !is_declaration(x)).unwrap_or(items.len());
+    for item in items[..body].iter() {
+      if let Some(locals) = item.form("local") {
+        match locals.first().and_then(Sexp::atom) {
+          Some(name) if name.starts_with('$') => {
+            Self::declare(&mut self.local_names, Some(name.into()), self.locals.len(), item.span())?;
+            self.locals.push(ValType::parse(locals.get(1).unwrap_or(item))?);
+          }
+          _ => for local in locals.iter() {
+            self.locals.push(ValType::parse(local)?);
+          }
+        }
+      }
+    }
+
+    // the locals are zero but the frames are reused
+    for local in ty.params.len()..self.locals.len() {
+      let zero = self.locals[local].zero();
+      let write = self.locals[local].write();
+      self.emit(Opcode::Push, Some(zero), None);
+      self.frame_address(FRAME_HEADER + local as i64 * SLOT_SIZE);
+      self.op(write);
+    }
+
+    let end = self.label();
+    self.height = 0;
+    self.unreachable = false;
+    self.blocks = vec![Block { name: None, kind: BlockKind::Function, target: end, height: 0, results: self.results, other: None }];
+    self.sequence(&items[body..])?;
+    self.end_block(self.funcs[idx].span)?;
+    self.place(end);
+    self.ret(0);
+    Ok(())
+  }
+
+  /// Check the height at the end of the innermost block
+  fn end_block(&mut self, span: Span) -> WatResult<Block> {
+    let block = self.blocks.pop().unwrap();
+    if !self.unreachable && self.height != block.height + block.results {
+      return error(span, InternalWatError::BadBlockHeight { expected: block.results, found: self.height - block.height })
+    }
+    self.height = block.height + block.results;
+    self.unreachable = false;
+    Ok(block)
+  }
+
+  /// Drop the values under the carried ones down to the height
+  fn unwind(&mut self, height: usize, arity: usize) {
+    for _ in 0..(self.height.saturating_sub(height + arity)) {
+      if arity == 1 {
+        self.op(Opcode::Swap);
+      }
+      self.op(Opcode::Discard);
+    }
+  }
+
+  /// Leave the current frame, the values over the height are returned
+  fn ret(&mut self, height: usize) {
+    self.unwind(height, self.results);
+    self.frame_address(SLOT_SIZE);
+    self.op(Opcode::ReadInt64);
+    self.emit(Opcode::Reg, int(FRAME_POINTER), None);
+    self.op(Opcode::ReadInt64);
+    self.emit(Opcode::SetReg, int(FRAME_POINTER), None);
+    self.emit(Opcode::Jump, None, int(1));
+  }
+
+  fn branch(&mut self, depth: usize) {
+    let block = &self.blocks[self.blocks.len() - 1 - depth];
+    let (kind, target, height, arity) = (block.kind, block.target, block.height, block.arity());
+    match kind {
+      BlockKind::Function => self.ret(height),
+      _ => {
+        self.unwind(height, arity);
+        self.jump(target, int(1));
+      }
+    }
+  }
+
+  fn depth(&self, item: Option<&Sexp>, span: Span) -> WatResult<usize> {
+    let names = self.blocks.iter().rev().enumerate()
+      .filter_map(|(depth, block)| block.name.clone().map(|name| (name, depth)))
+      .rev()
+      .collect();
+    let depth = index(item, &names, span)?;
+    match depth < self.blocks.len() {
+      true => Ok(depth),
+      false => error(span, InternalWatError::NotFound(format!("label {}", depth)))
+    }
+  }
+
+  /// A label and the block type at the start of the items, returns where the body starts
+  fn block_header(&mut self, items: &[Sexp]) -> WatResult<(Option<String>, usize, usize)> {
+    let name = Self::name(items);
+    let mut start = usize::from(name.is_some());
+    let mut results = 0;
+    while let Some(item) = items.get(start) {
+      if let Some(types) = item.form("result") {
+        results += types.len();
+      } else if item.form("param").is_some() || item.form("type").is_some() {
+        return error(item.span(), InternalWatError::Unsupported("block parameters and types".into()))
+      } else {
+        break
+      }
+      start += 1;
+    }
+    if results > 1 {
+      return error(items[0].span(), InternalWatError::Unsupported("multiple results".into()))
+    }
+    Ok((name, results, start))
+  }
+
+  fn open(&mut self, name: Option<String>, kind: BlockKind, results: usize) -> usize {
+    let target = self.label();
+    if kind == BlockKind::Loop {
+      self.place(target);
+    }
+    self.blocks.push(Block { name, kind, target, height: self.height, results, other: None });
+    target
+  }
+
+  fn sequence(&mut self, items: &[Sexp]) -> WatResult<()> {
+    let mut position = 0;
+    while position < items.len() {
+      self.item(items, &mut position)?;
+    }
+    Ok(())
+  }
+
+  /// Translate the items until one of the keywords, returning it
+  fn until(&mut self, items: &[Sexp], position: &mut usize, keywords: &[&str], span: Span) -> WatResult<String> {
+    loop {
+      match items.get(*position) {
+        Some(Sexp::Atom(atom, _)) if keywords.contains(&atom.as_str()) => {
+          *position += 1;
+          // an optional label repeating the block name
+          if items.get(*position).and_then(Sexp::atom).is_some_and(|x| x.starts_with('$')) {
+            *position += 1;
+          }
+          return Ok(atom.clone())
+        }
+        Some(_) => self.item(items, position)?,
+        None => return error(span, InternalWatError::Expected(keywords.join(" or ")))
+      }
+    }
+  }
+
+  fn item(&mut self, items: &[Sexp], position: &mut usize) -> WatResult<()> {
+    let item = &items[*position];
+    *position += 1;
+    match item {
+      Sexp::List(children, span) => self.folded(children, *span),
+      Sexp::Atom(op, span) => match op.as_str() {
+        "block" | "loop" => {
+          let (name, results, start) = self.block_header(&items[*position..])?;
+          *position += start;
+          let kind = if op == "block" { BlockKind::Block } else { BlockKind::Loop };
+          self.open(name, kind, results);
+          self.until(items, position, &["end"], *span)?;
+          self.close(*span)
+        }
+        "if" => {
+          let (name, results, start) = self.block_header(&items[*position..])?;
+          *position += start;
+          self.effect(1, 0, *span)?;
+          self.open_if(name, results);
+          if self.until(items, position, &["else", "end"], *span)? == "else" {
+            self.otherwise(*span)?;
+            self.until(items, position, &["end"], *span)?;
+          }
+          self.close(*span)
+        }
+        _ => {
+          let immediates = &items[*position..(*position + Self::immediates(op, &items[*position..]))];
+          *position += immediates.len();
+          self.instruction(op, immediates, *span)
+        }
+      },
+      Sexp::Str(_, span) => error(*span, InternalWatError::Expected("an instruction".into()))
+    }
+  }
+
+  /// How many of the items are immediates of the instruction in the flat syntax
+  fn immediates(op: &str, items: &[Sexp]) -> usize {
+    let atoms = items.iter().take_while(|x| x.atom().is_some());
+    match op {
+      "i64.const" | "f64.const" | "local.get" | "local.set" | "local.tee" | "global.get" | "global.set" |
+      "br" | "br_if" | "call" => atoms.take(1).count(),
+      _ => atoms.take_while(|x| x.atom().is_some_and(|x| x.starts_with("offset=") || x.starts_with("align="))).count()
+    }
+  }
+
+  fn open_if(&mut self, name: Option<String>, results: usize) {
+    let other = self.label();
+    self.emit(Opcode::Eq, None, int(0));
+    self.jump(other, None);
+    self.open(name, BlockKind::If, results);
+    self.blocks.last_mut().unwrap().other = Some(other);
+  }
+
+  fn otherwise(&mut self, span: Span) -> WatResult<()> {
+    let block = self.blocks.last().unwrap();
+    if !self.unreachable && self.height != block.height + block.results {
+      return error(span, InternalWatError::BadBlockHeight { expected: block.results, found: self.height - block.height })
+    }
+    let (target, height, other) = (block.target, block.height, block.other);
+    self.jump(target, int(1));
+    if let Some(other) = other {
+      self.place(other);
+    }
+    self.blocks.last_mut().unwrap().other = None;
+    self.height = height;
+    self.unreachable = false;
+    Ok(())
+  }
+
+  fn close(&mut self, span: Span) -> WatResult<()> {
+    let block = self.end_block(span)?;
+    if let Some(other) = block.other {
+      self.place(other);
+    }
+    if block.kind != BlockKind::Loop {
+      self.place(block.target);
+    }
+    Ok(())
+  }
+
+  fn folded(&mut self, children: &[Sexp], span: Span) -> WatResult<()> {
+    let Some(op) = children.first().and_then(Sexp::atom) else {
+      return error(span, InternalWatError::Expected("an instruction".into()))
+    };
+    let rest = &children[1..];
+    match op {
+      "block" | "loop" => {
+        let (name, results, start) = self.block_header(rest)?;
+        let kind = if op == "block" { BlockKind::Block } else { BlockKind::Loop };
+        self.open(name, kind, results);
+        self.sequence(&rest[start..])?;
+        self.close(span)
+      }
+      "if" => {
+        let (name, results, start) = self.block_header(rest)?;
+        let branches = rest[start..].iter().position(|x| x.form("then").is_some()).map(|x| start + x);
+        let Some(then) = branches else {
+          return error(span, InternalWatError::Expected("(then ...)".into()))
+        };
+        self.sequence(&rest[start..then])?;
+        self.effect(1, 0, span)?;
+        self.open_if(name, results);
+        self.sequence(rest[then].form("then").unwrap())?;
+        if let Some(otherwise) = rest.get(then + 1).and_then(|x| x.form("else")) {
+          self.otherwise(span)?;
+          self.sequence(otherwise)?;
+        }
+        self.close(span)
+      }
+      _ => {
+        // the immediates and then the operands
+        let immediates = rest.iter().take_while(|x| x.atom().is_some()).count();
+        self.sequence(&rest[immediates..])?;
+        self.instruction(op, &rest[..immediates], span)
+      }
+    }
+  }
+
+  fn local(&self, immediates: &[Sexp], span: Span) -> WatResult<(i64, ValType)> {
+    let idx = index(immediates.first(), &self.local_names, span)?;
+    match self.locals.get(idx) {
+      Some(ty) => Ok((FRAME_HEADER + idx as i64 * SLOT_SIZE, *ty)),
+      None => error(span, InternalWatError::NotFound(format!("local {}", idx)))
+    }
+  }
+
+  fn global(&self, immediates: &[Sexp], span: Span) -> WatResult<(i64, ValType)> {
+    let idx = index(immediates.first(), &self.global_names, span)?;
+    match self.globals.get(idx) {
+      Some(global) => Ok((global.address, global.ty)),
+      None => error(span, InternalWatError::NotFound(format!("global {}", idx)))
+    }
+  }
+
+  fn memory_offset(immediates: &[Sexp]) -> i64 {
+    immediates.iter()
+      .filter_map(|x| x.atom()?.strip_prefix("offset="))
+      .find_map(|x| x.parse().ok())
+      .unwrap_or(0)
+  }
+
+  /// Move the arguments into the next frame, link it with the current one and jump
+  fn call(&mut self, idx: usize) {
+    let ty = self.funcs[idx].ty.clone();
+    let frame = if self.blocks.is_empty() { 0 } else { self.frame_size() };
+    match self.funcs[idx].callee {
+      Callee::Import { symbol: (address, size), share_memory } => {
+        for register in (0..ty.params.len()).rev() {
+          self.emit(Opcode::SetReg, int(register as i64), None);
+        }
+        self.emit(Opcode::SetReg, int(FLAG_SHARE_MEMORY), int(share_memory.into()));
+        self.emit(Opcode::FastInvoke, int(address), int(size));
+      }
+      Callee::Defined { label } => {
+        for (param, ty) in ty.params.iter().enumerate().rev() {
+          self.frame_address(frame + FRAME_HEADER + param as i64 * SLOT_SIZE);
+          self.op(ty.write());
+        }
+        let back = self.label();
+        self.emit(Opcode::Reg, int(FRAME_POINTER), None);
+        self.frame_address(frame);
+        self.op(Opcode::WriteInt64);
+        self.fixups.push((self.program.instructions.len(), 0, Some(back)));
+        self.program.code_references.push((self.program.instructions.len(), 0));
+        self.emit(Opcode::Push, int(0), None);
+        self.frame_address(frame + SLOT_SIZE);
+        self.op(Opcode::WriteInt64);
+        self.frame_address(frame);
+        self.emit(Opcode::SetReg, int(FRAME_POINTER), None);
+        self.jump(label, int(1));
+        self.place(back);
+      }
+    }
+  }
+
+  fn instruction(&mut self, op: &str, immediates: &[Sexp], span: Span) -> WatResult<()> {
+    // the vm operates the top of the stack with the second item, so the order is swapped or the comparison reversed
+    let binary = |opcode: Opcode, swap: bool| move |translator: &mut Self| {
+      if swap {
+        translator.op(Opcode::Swap);
+      }
+      translator.op(opcode);
+    };
+    let (pops, pushes): (usize, usize) = match op {
+      "nop" => (0, 0),
+      "unreachable" => {
+        self.op(Opcode::Exit);
+        self.unreachable = true;
+        (0, 0)
+      }
+      "drop" => { self.op(Opcode::Discard); (1, 0) }
+      "select" => {
+        let (other, end) = (self.label(), self.label());
+        self.emit(Opcode::Eq, None, int(0));
+        self.jump(other, None);
+        self.op(Opcode::Discard);
+        self.jump(end, int(1));
+        self.place(other);
+        self.op(Opcode::Swap);
+        self.op(Opcode::Discard);
+        self.place(end);
+        (3, 1)
+      }
+
+      "i64.const" => { let value = number::<i64>(immediates.first(), span)?; self.emit(Opcode::Push, int(value), None); (0, 1) }
+      "f64.const" => {
+        let value = number::<f64>(immediates.first(), span)?;
+        self.emit(Opcode::Push, Some(InstructionParam::Float(value)), None);
+        (0, 1)
+      }
+
+      "local.get" => {
+        let (offset, ty) = self.local(immediates, span)?;
+        self.frame_address(offset);
+        self.op(ty.read());
+        (0, 1)
+      }
+      "local.set" | "local.tee" => {
+        let (offset, ty) = self.local(immediates, span)?;
+        if op == "local.tee" {
+          self.op(Opcode::Clone);
+        }
+        self.frame_address(offset);
+        self.op(ty.write());
+        (1, usize::from(op == "local.tee"))
+      }
+      "global.get" => {
+        let (address, ty) = self.global(immediates, span)?;
+        self.emit(ty.read(), int(address), None);
+        (0, 1)
+      }
+      "global.set" => {
+        let (address, ty) = self.global(immediates, span)?;
+        self.emit(ty.write(), int(address), None);
+        (1, 0)
+      }
+
+      "i64.add" | "f64.add" => { binary(Opcode::Add, false)(self); (2, 1) }
+      "i64.sub" | "f64.sub" => { binary(Opcode::Sub, true)(self); (2, 1) }
+      "i64.mul" | "f64.mul" => { binary(Opcode::Mul, false)(self); (2, 1) }
+      "i64.div_s" | "f64.div" => { binary(Opcode::Div, true)(self); (2, 1) }
+      "i64.rem_s" => {
+        for opcode in [Opcode::Over, Opcode::Over, Opcode::Swap, Opcode::Div, Opcode::Mul, Opcode::Swap, Opcode::Sub] {
+          self.op(opcode);
+        }
+        (2, 1)
+      }
+      "f64.neg" => { self.emit(Opcode::Mul, None, Some(InstructionParam::Float(-1.0))); (1, 1) }
+      "i64.eq" | "f64.eq" => { binary(Opcode::Eq, false)(self); (2, 1) }
+      "i64.ne" | "f64.ne" => { binary(Opcode::Noteq, false)(self); (2, 1) }
+      "i64.lt_s" | "f64.lt" => { binary(Opcode::Gt, false)(self); (2, 1) }
+      "i64.gt_s" | "f64.gt" => { binary(Opcode::Ls, false)(self); (2, 1) }
+      "i64.le_s" | "f64.le" => { binary(Opcode::Gteq, false)(self); (2, 1) }
+      "i64.ge_s" | "f64.ge" => { binary(Opcode::Lseq, false)(self); (2, 1) }
+      "i64.eqz" => { self.emit(Opcode::Eq, None, int(0)); (1, 1) }
+      "f64.convert_i64_s" => { self.op(Opcode::Float); (1, 1) }
+      "i64.trunc_f64_s" => { self.op(Opcode::Int); (1, 1) }
+
+      "i64.load" | "f64.load" | "i64.load8_s" | "i64.load16_s" | "i64.load32_s" => {
+        let offset = Self::memory_offset(immediates);
+        if offset != 0 {
+          self.emit(Opcode::Add, None, int(offset));
+        }
+        self.op(match op {
+          "i64.load" => Opcode::ReadInt64,
+          "f64.load" => Opcode::ReadFloat64,
+          "i64.load8_s" => Opcode::ReadInt8,
+          "i64.load16_s" => Opcode::ReadInt16,
+          _ => Opcode::ReadInt32
+        });
+        (1, 1)
+      }
+      "i64.store" | "f64.store" | "i64.store8" | "i64.store16" | "i64.store32" => {
+        self.op(Opcode::Swap);
+        let offset = Self::memory_offset(immediates);
+        if offset != 0 {
+          self.emit(Opcode::Add, None, int(offset));
+        }
+        self.op(match op {
+          "i64.store" => Opcode::WriteInt64,
+          "f64.store" => Opcode::WriteFloat64,
+          "i64.store8" => Opcode::WriteInt8,
+          "i64.store16" => Opcode::WriteInt16,
+          _ => Opcode::WriteInt32
+        });
+        (2, 0)
+      }
+
+      "br" => {
+        let depth = self.depth(immediates.first(), span)?;
+        self.branch(depth);
+        self.unreachable = true;
+        (0, 0)
+      }
+      "br_if" => {
+        let depth = self.depth(immediates.first(), span)?;
+        self.effect(1, 0, span)?;
+        let skip = self.label();
+        self.emit(Opcode::Eq, None, int(0));
+        self.jump(skip, None);
+        self.branch(depth);
+        self.place(skip);
+        (0, 0)
+      }
+      "return" => {
+        let depth = self.blocks.len() - 1;
+        self.branch(depth);
+        self.unreachable = true;
+        (0, 0)
+      }
+      "call" => {
+        let idx = index(immediates.first(), &self.func_names, span)?;
+        let Some(func) = self.funcs.get(idx) else {
+          return error(span, InternalWatError::NotFound(format!("function {}", idx)))
+        };
+        let (params, results) = (func.ty.params.len(), func.ty.results.len());
+        self.call(idx);
+        (params, results)
+      }
+      _ => return error(span, InternalWatError::Unsupported(format!("instruction {}", op)))
+    };
+    self.effect(pops, pushes, span)
+  }
+}
+
+impl Parser for Wat {
+  type Err = WatError;
+
+  fn parse(mut target: impl BorrowMut<Program>, source: impl AsRef<str>) -> Result<(), Self::Err> {
+    Translator::new(target.borrow_mut()).translate(source.as_ref())
+  }
+}
@@ -0,0 +1,298 @@
+//! Saving a process to a file and resuming it later, in this machine or another
+//!
+//! A checkpoint holds the id of the process, the program, the pc, the stack, the registers, the invoke target and the
+//! process memory with its heap. The shared memory units are not saved, a process with a unit mounted mounts the unit with the same index when
+//! resumed, so the machine resuming it needs the same units. The values are little endian and the opcodes are saved by
+//! name, a checkpoint survives new opcodes but not renamed ones
+
+use std::{collections::BTreeMap, fs, io, path::Path, str::FromStr};
+
+use thiserror::Error;
+
+use super::{
+  memory::{Access, ProcessMemory},
+  process::{Process, PROCESS_REGISTERS_COUNT},
+  program::{Instruction, InstructionParam, Opcode, Program},
+  stack::{StackValue, STACK_SIZE}
+};
+
+const MAGIC: &[u8; 8] = b"AVMIRCKP";
+const VERSION: u32 = 2;
+
+#[derive(Debug, Error)]
+pub enum CheckpointError {
+  #[error("{path}: {source}")]
+  Io { path: String, source: io::Error },
+
+  #[error("{0} is not a checkpoint")]
+  NotACheckpoint(String),
+
+  #[error("{path} is a checkpoint of version {version}, expecting {VERSION}")]
+  Version { path: String, version: u32 },
+
+  #[error("{path} is truncated or corrupted")]
+  Corrupted { path: String },
+
+  #[error("{path} uses the opcode {opcode}, it does not exist")]
+  UnknownOpcode { path: String, opcode: String },
+
+  #[error("the process of {path} has the memory unit {unit} mounted, it is not configured")]
+  UnknownUnit { path: String, unit: usize }
+}
+
+/// A process saved with its memory
+pub struct Checkpoint {
+  /// The pid of the process that saved the first checkpoint, it keeps it when resumed to save the next ones
+  pub id: usize,
+  pub process: Process,
+  pub memory: ProcessMemory,
+  /// The unit mounted and the access to it, `None` when the process memory is active
+  pub mount: Option<(usize, Access)>,
+  /// The file it was read from
+  pub path: String
+}
+
+/// The id, the process, its memory and the unit mounted
+type Restored = (usize, Process, ProcessMemory, Option<(usize, Access)>);
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+  fn u8(&mut self, value: u8) {
+    self.0.push(value)
+  }
+
+  fn u64(&mut self, value: u64) {
+    self.0.extend_from_slice(&value.to_le_bytes())
+  }
+
+  fn usize(&mut self, value: usize) {
+    self.u64(value as u64)
+  }
+
+  fn bytes(&mut self, bytes: &[u8]) {
+    self.usize(bytes.len());
+    self.0.extend_from_slice(bytes)
+  }
+
+  fn pairs<'a>(&mut self, pairs: impl ExactSizeIterator<Item = (&'a usize, &'a usize)>) {
+    self.usize(pairs.len());
+    for (a, b) in pairs {
+      self.usize(*a);
+      self.usize(*b);
+    }
+  }
+
+  fn value(&mut self, value: StackValue) {
+    match value {
+      StackValue::Int(x) => { self.u8(0); self.u64(x as u64) }
+      StackValue::Float(x) => { self.u8(1); self.u64(x.to_bits()) }
+    }
+  }
+
+  fn param(&mut self, param: Option<InstructionParam>) {
+    match param {
+      None => self.u8(0),
+      Some(param) => { self.u8(1); self.value(param.into()) }
+    }
+  }
+}
+
+/// Reading a checkpoint, `None` once past the end or on an invalid value
+struct Reader<'a>(&'a [u8]);
+
+impl Reader<'_> {
+  fn take(&mut self, len: usize) -> Option<&[u8]> {
+    let (taken, rest) = self.0.split_at_checked(len)?;
+    self.0 = rest;
+    Some(taken)
+  }
+
+  fn u8(&mut self) -> Option<u8> {
+    self.take(1).map(|bytes| bytes[0])
+  }
+
+  fn u32(&mut self) -> Option<u32> {
+    self.take(4).map(|bytes| u32::from_le_bytes(bytes.try_into().unwrap()))
+  }
+
+  fn u64(&mut self) -> Option<u64> {
+    self.take(8).map(|bytes| u64::from_le_bytes(bytes.try_into().unwrap()))
+  }
+
+  fn usize(&mut self) -> Option<usize> {
+    self.u64().and_then(|value| value.try_into().ok())
+  }
+
+  fn bytes(&mut self) -> Option<Vec<u8>> {
+    let len = self.usize()?;
+    self.take(len).map(<[u8]>::to_vec)
+  }
+
+  fn string(&mut self) -> Option<String> {
+    String::from_utf8(self.bytes()?).ok()
+  }
+
+  /// A count of items, each at least `size` bytes, bounded by what is left to avoid huge allocations
+  fn count(&mut self, size: usize) -> Option<usize> {
+    self.usize().filter(|&count| count.checked_mul(size).is_some_and(|len| len <= self.0.len()))
+  }
+
+  fn pairs(&mut self) -> Option<Vec<(usize, usize)>> {
+    (0..self.count(16)?).map(|_| Some((self.usize()?, self.usize()?))).collect()
+  }
+
+  fn value(&mut self) -> Option<StackValue> {
+    match self.u8()? {
+      0 => Some(StackValue::Int(self.u64()? as i64)),
+      1 => Some(StackValue::Float(f64::from_bits(self.u64()?))),
+      _ => None
+    }
+  }
+
+  fn param(&mut self) -> Option<Option<InstructionParam>> {
+    match self.u8()? {
+      0 => Some(None),
+      1 => Some(Some(match self.value()? {
+        StackValue::Int(x) => InstructionParam::Int(x),
+        StackValue::Float(x) => InstructionParam::Float(x)
+      })),
+      _ => None
+    }
+  }
+}
+
+/// Save the process and its memory into the file, replacing it at once so a crash never leaves half a checkpoint
+pub fn save(
+  path: impl AsRef<Path>, id: usize, process: &Process, memory: &ProcessMemory, mount: Option<(usize, Access)>
+) -> Result<(), CheckpointError> {
+  let path = path.as_ref();
+  let program = &process.program;
+  let mut writer = Writer(MAGIC.to_vec());
+  writer.0.extend_from_slice(&VERSION.to_le_bytes());
+  writer.usize(id);
+
+  writer.bytes(program.name.as_bytes());
+  writer.usize(program.instructions.len());
+  for Instruction(opcode, first, second) in program.instructions.iter() {
+    writer.bytes(opcode.to_string().as_bytes());
+    writer.param(*first);
+    writer.param(*second);
+  }
+  writer.bytes(&program.static_data);
+  writer.pairs(program.static_data_meta.iter().map(|(a, b)| (a, b)));
+  writer.pairs(program.code_references.iter().map(|(a, b)| (a, b)));
+  writer.usize(program.unit_references.len());
+  for (idx, operand, name) in program.unit_references.iter() {
+    writer.usize(*idx);
+    writer.usize(*operand);
+    writer.bytes(name.as_bytes());
+  }
+  writer.usize(program.required_memory);
+
+  writer.usize(process.pc);
+  writer.usize(process.stack.as_slice().len());
+  process.stack.as_slice().iter().for_each(|&value| writer.value(value));
+  process.registers.iter().for_each(|&value| writer.value(value));
+  writer.bytes(&process.invoke_target);
+
+  let (top, allocated, free) = memory.heap();
+  writer.bytes(memory.bytes());
+  writer.usize(top);
+  writer.pairs(allocated.iter());
+  writer.pairs(free.iter());
+
+  match mount {
+    None => writer.u8(0),
+    Some((unit, access)) => {
+      writer.u8(1);
+      writer.usize(unit);
+      writer.u8(access.read as u8 | (access.write as u8) << 1);
+    }
+  }
+
+  let io = |source| CheckpointError::Io { path: path.display().to_string(), source };
+  let mut temporary = path.as_os_str().to_owned();
+  temporary.push(".tmp");
+  fs::write(&temporary, &writer.0).map_err(io)?;
+  fs::rename(&temporary, path).map_err(io)
+}
+
+impl Checkpoint {
+  pub fn load(path: impl AsRef<Path>) -> Result<Checkpoint, CheckpointError> {
+    let path = path.as_ref().display().to_string();
+    let content = fs::read(&path).map_err(|source| CheckpointError::Io { path: path.clone(), source })?;
+    let mut reader = Reader(&content);
+    if reader.take(MAGIC.len()) != Some(MAGIC) {
+      return Err(CheckpointError::NotACheckpoint(path))
+    }
+    match reader.u32() {
+      Some(VERSION) => (),
+      Some(version) => return Err(CheckpointError::Version { path, version }),
+      None => return Err(CheckpointError::Corrupted { path })
+    }
+    match Self::read(&mut reader) {
+      Ok(Some((id, process, memory, mount))) if reader.0.is_empty() => Ok(Checkpoint { id, process, memory, mount, path }),
+      Ok(_) => Err(CheckpointError::Corrupted { path }),
+      Err(opcode) => Err(CheckpointError::UnknownOpcode { path, opcode })
+    }
+  }
+
+  /// The content after the version, `Err` with the name of an unknown opcode
+  fn read(reader: &mut Reader) -> Result<Option<Restored>, String> {
+    macro_rules! read {
+      ($value: expr) => {
+        match $value {
+          Some(value) => value,
+          None => return Ok(None)
+        }
+      };
+    }
+
+    let id = read!(reader.usize());
+    let mut program = Program::with_name(read!(reader.string()));
+    for _ in 0..read!(reader.count(10)) {
+      let name = read!(reader.string());
+      let opcode = Opcode::from_str(&name).map_err(|_| name)?;
+      program.instructions.push(Instruction(opcode, read!(reader.param()), read!(reader.param())));
+    }
+    program.static_data = read!(reader.bytes());
+    program.static_data_meta = read!(reader.pairs());
+    program.code_references = read!(reader.pairs());
+    for _ in 0..read!(reader.count(24)) {
+      program.unit_references.push((read!(reader.usize()), read!(reader.usize()), read!(reader.string())));
+    }
+    program.required_memory = read!(reader.usize());
+
+    let mut process = Process::new(program);
+    process.pc = read!(reader.usize());
+    let depth = read!(reader.usize().filter(|&depth| depth <= STACK_SIZE));
+    for _ in 0..depth {
+      process.stack.push(read!(reader.value()));
+    }
+    for idx in 0..PROCESS_REGISTERS_COUNT {
+      process.registers[idx] = read!(reader.value());
+    }
+    process.invoke_target = read!(reader.bytes());
+
+    let bytes = read!(reader.bytes());
+    let top = read!(reader.usize().filter(|&top| top <= bytes.len()));
+    // the ranges of the heap stay below its top, the allocator trusts them
+    let in_heap = |ranges: &Vec<(usize, usize)>| ranges.iter()
+      .all(|&(address, size)| address.checked_add(size).is_some_and(|end| end <= top));
+    let allocated: BTreeMap<_, _> = read!(reader.pairs().filter(in_heap)).into_iter().collect();
+    let free: BTreeMap<_, _> = read!(reader.pairs().filter(in_heap)).into_iter().collect();
+    let memory = ProcessMemory::restore(&process.program, bytes, (top, allocated, free));
+
+    let mount = match read!(reader.u8()) {
+      0 => None,
+      1 => {
+        let unit = read!(reader.usize());
+        let access = read!(reader.u8());
+        Some((unit, Access { read: access & 1 != 0, write: access & 2 != 0 }))
+      }
+      _ => return Ok(None)
+    };
+    Ok(Some((id, process, memory, mount)))
+  }
+}
@@ -1,7 +1,7 @@
 use libloading::{library_filename, Symbol};
 use thiserror::Error;
 
-use super::{memory::Memory, process::{ProcesSupervisor, Process, PublicRegisters}, stack::StackValue};
+use super::{memory::Memory, process::{ProcesSupervisor, Process, ProcessFault, PublicRegisters}, stack::StackValue};
 
 #[derive(Debug, Error)]
 pub enum FFIError {
@@ -11,36 +11,87 @@ pub enum FFIError {
   SymbolError(#[from] libloading::Error)
 }
 
+pub type FFIFunction = fn (&mut PublicRegisters) -> Option<StackValue>;
+pub type FFIMemoryFunction = fn (&mut PublicRegisters, &mut dyn Memory) -> Option<StackValue>;
+/// A trap may run instructions of the process, returning their fault ends the process
+pub type FFITrapFunction = fn (&mut Process, &mut dyn ProcesSupervisor) -> Result<Option<StackValue>, ProcessFault>;
+
+type HostFunction = dyn Fn(&mut PublicRegisters) -> Option<StackValue> + Send + Sync;
+type HostMemoryFunction = dyn Fn(&mut PublicRegisters, &mut dyn Memory) -> Option<StackValue> + Send + Sync;
+type HostTrapFunction = dyn Fn(&mut Process, &mut dyn ProcesSupervisor) -> Result<Option<StackValue>, ProcessFault> + Send + Sync;
+
+/// A function of the embedder invoked by name like the ffi functions of the libraries, looked up before them so a
+/// library function with the same name is never called
+///
+/// It is called with the arguments of its kind whatever the flags of the process, with the active memory for the
+/// memory functions
+pub enum HostFn {
+  Plain(Box<HostFunction>),
+  Memory(Box<HostMemoryFunction>),
+  Trap(Box<HostTrapFunction>)
+}
+
+impl HostFn {
+  /// A function of the public registers, like [`FFIFunction`]
+  pub fn plain(function: impl Fn(&mut PublicRegisters) -> Option<StackValue> + Send + Sync + 'static) -> Self {
+    HostFn::Plain(Box::new(function))
+  }
+
+  /// A function of the public registers and the active memory, like [`FFIMemoryFunction`]
+  pub fn memory(
+    function: impl Fn(&mut PublicRegisters, &mut dyn Memory) -> Option<StackValue> + Send + Sync + 'static
+  ) -> Self {
+    HostFn::Memory(Box::new(function))
+  }
+
+  /// A function of the process and its supervisor, like [`FFITrapFunction`]
+  pub fn trap(
+    function: impl Fn(&mut Process, &mut dyn ProcesSupervisor) -> Result<Option<StackValue>, ProcessFault> + Send + Sync + 'static
+  ) -> Self {
+    HostFn::Trap(Box::new(function))
+  }
+}
+
 #[derive(Debug)]
 pub struct FFILoader(libloading::Library);
 
 impl FFILoader {
+  /// # Safety
+  /// Loading a library runs its initialization routines, see [`libloading::Library::new`]
   pub unsafe fn new(path: impl AsRef<str>) -> Result<Self, FFIError> {
     Ok(Self(libloading::Library::new(library_filename(path.as_ref()))?))
   }
 
+  /// # Safety
+  /// The symbol must be a function with the [`FFIFunction`] signature
   pub unsafe fn invoke_ffi(
     &self, symbol: &[u8], registers: &mut PublicRegisters
   ) -> Result<Option<StackValue>, FFIError> {
-    let symbol: Symbol<fn (&mut PublicRegisters) -> Option<StackValue>> = self.0.get(symbol)?;
+    let symbol: Symbol<FFIFunction> = self.0.get(symbol)?;
     Ok(symbol(registers))
   }
 
+  /// # Safety
+  /// The symbol must be a function with the [`FFIMemoryFunction`] signature
   pub unsafe fn invoke_ffi_memory(
     &self, symbol: &[u8], registers: &mut PublicRegisters, memory: &mut dyn Memory
   ) -> Result<Option<StackValue>, FFIError> {
-    let symbol: Symbol<fn (&mut PublicRegisters, &mut dyn Memory) -> Option<StackValue>> = self.0.get(symbol)?;
+    let symbol: Symbol<FFIMemoryFunction> = self.0.get(symbol)?;
     Ok(symbol(registers, memory))
   }
 
+  /// # Safety
+  /// The symbol must be a function with the [`FFITrapFunction`] signature
   pub unsafe fn invoke_ffi_trap(
     &self, symbol: &[u8], process: &mut Process, supervisor: &mut dyn ProcesSupervisor
-  ) -> Result<Option<StackValue>, FFIError> {
-    let symbol: Symbol<fn (&mut Process, &mut dyn ProcesSupervisor) -> Option<StackValue>> = self.0.get(symbol)?;
+  ) -> Result<Result<Option<StackValue>, ProcessFault>, FFIError> {
+    let symbol: Symbol<FFITrapFunction> = self.0.get(symbol)?;
     Ok(symbol(process, supervisor))
   }
 }
 
+/// # Safety
+/// See [`FFILoader::invoke_ffi`]
 pub unsafe fn invoke_ffi(
   many: &[FFILoader], symbol: &[u8], registers: &mut PublicRegisters
 ) -> Result<Option<StackValue>, FFIError>{
@@ -49,9 +100,11 @@ pub unsafe fn invoke_ffi(
       return Ok(output)
     }
   }
-  return Err(FFIError::NotFound)
+  Err(FFIError::NotFound)
 }
 
+/// # Safety
+/// See [`FFILoader::invoke_ffi_memory`]
 pub unsafe fn invoke_ffi_memory(
   many: &[FFILoader], symbol: &[u8], registers: &mut PublicRegisters, memory: &mut dyn Memory
 ) -> Result<Option<StackValue>, FFIError>{
@@ -60,16 +113,18 @@ pub unsafe fn invoke_ffi_memory(
       return Ok(output)
     }
   }
-  return Err(FFIError::NotFound)
+  Err(FFIError::NotFound)
 }
 
+/// # Safety
+/// See [`FFILoader::invoke_ffi_trap`]
 pub unsafe fn invoke_ffi_trap(
   many: &[FFILoader], symbol: &[u8], process: &mut Process, supervisor: &mut dyn ProcesSupervisor
-) -> Result<Option<StackValue>, FFIError>{
+) -> Result<Result<Option<StackValue>, ProcessFault>, FFIError>{
   for loader in many.iter() {
     if let Ok(output) = loader.invoke_ffi_trap(symbol, process, supervisor) {
       return Ok(output)
     }
   }
-  return Err(FFIError::NotFound)
+  Err(FFIError::NotFound)
 }
\ No newline at end of file
@@ -1,6 +1,6 @@
-use super::{program::{Instruction, Opcode}, stack::StackValue};
+use super::{process::PROCESS_REGISTERS_COUNT, program::{Instruction, InstructionParam, Opcode}, stack::StackValue};
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub struct ProcessInstruction {
   pub opcode: Opcode,
   pub operands: (Option<StackValue>, Option<StackValue>),
@@ -13,4 +13,168 @@ impl From<Instruction> for ProcessInstruction {
       operands: (value.1.map(|x| x.into()), value.2.map(|x| x.into()))
     }
   }
-}
\ No newline at end of file
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BinaryOp {
+  Add,
+  Sub,
+  Mul,
+  Div,
+  Gt,
+  Ls,
+  Gteq,
+  Lseq,
+  Eq,
+  Noteq
+}
+
+impl BinaryOp {
+  pub fn of(opcode: Opcode) -> Option<BinaryOp> {
+    Some(match opcode {
+      Opcode::Add => BinaryOp::Add,
+      Opcode::Sub => BinaryOp::Sub,
+      Opcode::Mul => BinaryOp::Mul,
+      Opcode::Div => BinaryOp::Div,
+      Opcode::Gt => BinaryOp::Gt,
+      Opcode::Ls => BinaryOp::Ls,
+      Opcode::Gteq => BinaryOp::Gteq,
+      Opcode::Lseq => BinaryOp::Lseq,
+      Opcode::Eq => BinaryOp::Eq,
+      Opcode::Noteq => BinaryOp::Noteq,
+      _ => return None
+    })
+  }
+}
+
+/// An instruction prepared for the interpreter loop, operands known ahead of time select a specialized variant
+/// and common sequences become a single superinstruction
+///
+/// A superinstruction replaces only the first instruction of its sequence and skips the rest when it runs, the
+/// following instructions are decoded on their own so jumping into the middle of a sequence still works. A sequence
+/// stops before the target of a static jump or fork, so the loops entered in the middle of one are fused from there
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DecodedInstruction {
+  /// Any other instruction, run matching its opcode and operands
+  Generic(ProcessInstruction),
+  Noop,
+  Push(StackValue),
+  Push2(StackValue, StackValue),
+  Discard,
+  Clone,
+  Swap,
+  Over,
+  /// Both operands from the stack
+  Binary(BinaryOp),
+  /// The first operand from the stack, `Add _ b`
+  BinaryRight(BinaryOp, StackValue),
+  Reg(usize),
+  /// The value from the stack, `SetReg r`
+  SetReg(usize),
+  ReadInt64,
+  WriteInt64,
+  /// `Jump pc 1`
+  Goto(usize),
+  /// The condition from the stack, `Jump pc`
+  JumpIf(usize),
+  Exit,
+
+  /// `Reg r; Add _ k`, also `Reg r; Sub _ k` with k negated
+  RegOffset(usize, i64),
+  /// `Reg r; Add _ k; ReadInt64`
+  ReadOffset(usize, i64),
+  /// `Reg r; Add _ k; WriteInt64`
+  WriteOffset(usize, i64),
+  /// `Eq _ b; Jump pc`
+  JumpIfEqual(StackValue, usize)
+}
+
+/// Instructions of the longest superinstruction
+const LONGEST_SEQUENCE: usize = 3;
+
+/// Decode every instruction, trying first a superinstruction starting on it
+pub fn decode(instructions: &[Instruction]) -> Vec<DecodedInstruction> {
+  let targets = targets(instructions);
+  (0..instructions.len())
+    .map(|idx| {
+      // the sequence stops before a target, jumping there runs the instructions decoded on their own
+      let limit = instructions.len().min(idx + LONGEST_SEQUENCE);
+      let end = ((idx + 1)..limit).find(|&pc| targets[pc]).unwrap_or(limit);
+      superinstruction(&instructions[idx..end]).unwrap_or_else(|| single(instructions[idx]))
+    })
+    .collect()
+}
+
+/// The instructions a `Jump` or `Fork` with a known pc goes to
+fn targets(instructions: &[Instruction]) -> Vec<bool> {
+  let mut targets = vec![false; instructions.len()];
+  for instruction in instructions {
+    if let Instruction(Opcode::Jump | Opcode::Fork, pc, _) = instruction {
+      if let Some(target) = target(*pc).and_then(|pc| targets.get_mut(pc)) {
+        *target = true
+      }
+    }
+  }
+  targets
+}
+
+fn register(param: Option<InstructionParam>) -> Option<usize> {
+  match param {
+    Some(InstructionParam::Int(reg)) if (0..PROCESS_REGISTERS_COUNT as i64).contains(&reg) => Some(reg as usize),
+    _ => None
+  }
+}
+
+fn target(param: Option<InstructionParam>) -> Option<usize> {
+  match param {
+    Some(InstructionParam::Int(pc)) => Some(pc as usize),
+    _ => None
+  }
+}
+
+fn superinstruction(instructions: &[Instruction]) -> Option<DecodedInstruction> {
+  use DecodedInstruction::*;
+  use InstructionParam::Int;
+  let decoded = match instructions {
+    [Instruction(Opcode::Reg, reg, None), Instruction(Opcode::Add, None, Some(Int(k))), rest @ ..] => {
+      let reg = register(*reg)?;
+      match rest.first() {
+        Some(Instruction(Opcode::ReadInt64, None, None)) => ReadOffset(reg, *k),
+        Some(Instruction(Opcode::WriteInt64, None, None)) => WriteOffset(reg, *k),
+        _ => RegOffset(reg, *k)
+      }
+    }
+    [Instruction(Opcode::Reg, reg, None), Instruction(Opcode::Sub, None, Some(Int(k))), ..] =>
+      RegOffset(register(*reg)?, k.checked_neg()?),
+    [Instruction(Opcode::Eq, None, Some(b)), Instruction(Opcode::Jump, pc @ Some(_), None), ..] =>
+      JumpIfEqual((*b).into(), target(*pc)?),
+    _ => return None
+  };
+  Some(decoded)
+}
+
+fn single(instruction: Instruction) -> DecodedInstruction {
+  use DecodedInstruction::*;
+  match instruction {
+    Instruction(Opcode::Noop, _, _) => Noop,
+    Instruction(Opcode::Push, Some(a), None) | Instruction(Opcode::Push, None, Some(a)) => Push(a.into()),
+    Instruction(Opcode::Push, Some(a), Some(b)) => Push2(a.into(), b.into()),
+    Instruction(Opcode::Discard, _, _) => Discard,
+    Instruction(Opcode::Clone, _, _) => Clone,
+    Instruction(Opcode::Swap, None, None) => Swap,
+    Instruction(Opcode::Over, None, None) => Over,
+    Instruction(opcode, None, None) if BinaryOp::of(opcode).is_some() => Binary(BinaryOp::of(opcode).unwrap()),
+    Instruction(opcode, None, Some(b)) if BinaryOp::of(opcode).is_some() => BinaryRight(BinaryOp::of(opcode).unwrap(), b.into()),
+    Instruction(Opcode::Reg, reg @ Some(_), _) if register(reg).is_some() => Reg(register(reg).unwrap()),
+    Instruction(Opcode::SetReg, reg @ Some(_), None) if register(reg).is_some() => SetReg(register(reg).unwrap()),
+    Instruction(Opcode::ReadInt64, None, _) => ReadInt64,
+    Instruction(Opcode::WriteInt64, None, None) => WriteInt64,
+    Instruction(Opcode::Jump, pc @ Some(InstructionParam::Int(_)), Some(InstructionParam::Int(cond))) => match cond {
+      0 => Noop,
+      _ => Goto(target(pc).unwrap())
+    }
+    Instruction(Opcode::Jump, pc @ Some(InstructionParam::Int(_)), None) => JumpIf(target(pc).unwrap()),
+    Instruction(Opcode::Exit, _, _) => Exit,
+    instruction => Generic(instruction.into())
+  }
+}
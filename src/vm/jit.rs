@@ -0,0 +1,728 @@
+//! Compilation of programs to x86-64 machine code, enabled with the `jit` feature
+//!
+//! A process runs in the interpreter until it has executed [`JIT_THRESHOLD`] instructions, then its program is
+//! compiled as a whole: every instruction gets a native block and the blocks follow the program order, so straight
+//! line code falls through and loops are plain jumps. Integer arithmetic, comparisons, stack and register operations,
+//! jumps and the access to the process memory run natively.
+//!
+//! The native code leaves before any instruction it can not run, with the state of the process untouched by it:
+//! ffi, fork, shared memory and the rest of the opcodes, an overflow, a write among the read only chunks of the
+//! static data or any case that would make the interpreter panic. That instruction then runs in the interpreter,
+//! which calls the supervisor as usual, and the native code resumes after it through a table with the address of
+//! every instruction
+//!
+//! The supervisor is polled after every return from the native code, which also leaves once it has taken
+//! [`POLL_JUMPS`] jumps so the loops running natively are killed and checkpointed like the interpreted ones
+
+#[cfg(not(target_arch = "x86_64"))]
+compile_error!("the jit feature only supports x86-64");
+
+use std::{io, mem::{self, offset_of}};
+
+use dynasmrt::{dynasm, x64::Assembler, AssemblyOffset, DynamicLabel, DynasmApi, DynasmLabelApi, ExecutableBuffer};
+
+use super::{
+  process::{ProcesSupervisor, Process, ProcessFault, PROCESS_REGISTERS_COUNT},
+  program::{Instruction, InstructionParam, Opcode, Program},
+  stack::{StackValue, STACK_SIZE}
+};
+
+/// Instructions interpreted before compiling the program
+pub const JIT_THRESHOLD: usize = 10_000;
+
+/// Jumps taken by the native code before leaving to poll the supervisor
+pub const POLL_JUMPS: u64 = 1 << 16;
+
+const INT: i32 = 0;
+const FLOAT: i32 = 1;
+
+const SLOT: i32 = mem::size_of::<Slot>() as i32;
+const REGISTERS: i32 = offset_of!(JitState, registers) as i32;
+const SP: i32 = offset_of!(JitState, sp) as i32;
+const PC: i32 = offset_of!(JitState, pc) as i32;
+const MEMORY: i32 = offset_of!(JitState, memory) as i32;
+const MEMORY_LEN: i32 = offset_of!(JitState, memory_len) as i32;
+const READ_ONLY_START: i32 = offset_of!(JitState, read_only_start) as i32;
+const READ_ONLY_END: i32 = offset_of!(JitState, read_only_end) as i32;
+const FUEL: i32 = offset_of!(JitState, fuel) as i32;
+const TABLE: i32 = offset_of!(JitState, table) as i32;
+
+// registers holding the operands
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct Slot {
+  tag: u64,
+  value: u64
+}
+
+impl From<StackValue> for Slot {
+  fn from(value: StackValue) -> Self {
+    match value {
+      StackValue::Int(x) => Slot { tag: INT as u64, value: x as u64 },
+      StackValue::Float(x) => Slot { tag: FLOAT as u64, value: x.to_bits() }
+    }
+  }
+}
+
+impl From<Slot> for StackValue {
+  fn from(slot: Slot) -> Self {
+    match slot.tag as i32 {
+      INT => StackValue::Int(slot.value as i64),
+      _ => StackValue::Float(f64::from_bits(slot.value))
+    }
+  }
+}
+
+/// The process as seen by the native code, its address is in `rdi`
+#[repr(C)]
+struct JitState {
+  stack: [Slot; STACK_SIZE],
+  registers: [Slot; PROCESS_REGISTERS_COUNT],
+  sp: u64,
+  pc: u64,
+  memory: *mut u8,
+  memory_len: u64,
+  /// Span of the read only chunks of the memory, writes to it go to the interpreter
+  read_only_start: u64,
+  read_only_end: u64,
+  /// Jumps left before leaving
+  fuel: u64,
+  table: *const usize
+}
+
+impl JitState {
+  fn load<S: ProcesSupervisor + ?Sized>(&mut self, process: &Process, supervisor: &mut S) {
+    let items = process.stack.as_slice();
+    for (slot, item) in self.stack.iter_mut().zip(items) {
+      *slot = (*item).into();
+    }
+    self.sp = items.len() as u64;
+    for (slot, register) in self.registers.iter_mut().zip(process.registers) {
+      *slot = register.into();
+    }
+    (self.memory, self.memory_len, self.read_only_start, self.read_only_end) = match supervisor.local_memory() {
+      Some(memory) => {
+        let (start, end) = memory.read_only_span();
+        let bytes = memory.bytes_mut();
+        (bytes.as_mut_ptr(), bytes.len() as u64, start as u64, end as u64)
+      }
+      None => (std::ptr::null_mut(), 0, 0, 0)
+    };
+  }
+
+  fn store(&self, process: &mut Process) {
+    while process.stack.pop().is_some() {}
+    for slot in self.stack.iter().take(self.sp as usize) {
+      process.stack.push((*slot).into());
+    }
+    for (register, slot) in process.registers.iter_mut().zip(self.registers) {
+      *register = slot.into();
+    }
+    process.pc = self.pc as usize;
+  }
+}
+
+/// Run the process until it finishes, compiling its program once it is hot, false when the supervisor stops it
+pub fn run<S: ProcesSupervisor + ?Sized>(process: &mut Process, supervisor: &mut S) -> Result<bool, ProcessFault> {
+  let mut compiled = false;
+  loop {
+    for _ in 0..JIT_THRESHOLD {
+      if !process.run_next(supervisor)? {
+        return Ok(true)
+      }
+    }
+    if !supervisor.poll(process) {
+      return Ok(false)
+    }
+    // a program the compiler rejects stays in the interpreter
+    if !compiled {
+      compiled = true;
+      if let Ok(jit) = JitProgram::compile(&process.program) {
+        return jit.run(process, supervisor)
+      }
+    }
+  }
+}
+
+pub struct JitProgram {
+  buffer: ExecutableBuffer,
+  entry: AssemblyOffset,
+  /// Address of the native code of every instruction
+  table: Vec<usize>,
+  /// Instructions with native code, the rest always go to the interpreter
+  native: Vec<bool>
+}
+
+impl JitProgram {
+  pub fn compile(program: &Program) -> io::Result<JitProgram> {
+    Compiler::new(&program.instructions)?.compile()
+  }
+
+  /// Run the process from its pc until it finishes or faults, false when the supervisor stops it
+  pub fn run<S: ProcesSupervisor + ?Sized>(&self, process: &mut Process, supervisor: &mut S) -> Result<bool, ProcessFault> {
+    let len = self.native.len();
+    // SAFETY: the entry is the start of the code generated by the compiler for this signature
+    let entry: extern "sysv64" fn(*mut JitState, u64) = unsafe { mem::transmute(self.buffer.ptr(self.entry)) };
+    let mut state = JitState {
+      stack: [Slot::default(); STACK_SIZE],
+      registers: [Slot::default(); PROCESS_REGISTERS_COUNT],
+      sp: 0,
+      pc: 0,
+      memory: std::ptr::null_mut(),
+      memory_len: 0,
+      read_only_start: 0,
+      read_only_end: 0,
+      fuel: 0,
+      table: self.table.as_ptr()
+    };
+
+    while process.pc < len {
+      if self.native[process.pc] {
+        state.load(process, supervisor);
+        state.fuel = POLL_JUMPS;
+        entry(&mut state, process.pc as u64);
+        state.store(process);
+      }
+      if !supervisor.poll(process) {
+        return Ok(false)
+      }
+      // the native code stops before an instruction it can not run or once out of fuel
+      process.run_next(supervisor)?;
+    }
+    Ok(true)
+  }
+}
+
+#[derive(Debug)]
+struct Unsupported;
+
+type Emit = Result<(), Unsupported>;
+
+#[derive(Clone, Copy)]
+enum Operand {
+  Immediate(InstructionParam),
+  /// Depth in the stack, 1 is the top
+  Stack(i32)
+}
+
+/// The operands of an instruction taking both, those missing come from the stack starting at the top
+fn both(instruction: &Instruction) -> ([Operand; 2], i32) {
+  use Operand::*;
+  match (instruction.1, instruction.2) {
+    (Some(a), Some(b)) => ([Immediate(a), Immediate(b)], 0),
+    (Some(a), None) => ([Immediate(a), Stack(1)], 1),
+    (None, Some(b)) => ([Stack(1), Immediate(b)], 1),
+    (None, None) => ([Stack(1), Stack(2)], 2)
+  }
+}
+
+fn first(instruction: &Instruction) -> (Operand, i32) {
+  match instruction.1 {
+    Some(a) => (Operand::Immediate(a), 0),
+    None => (Operand::Stack(1), 1)
+  }
+}
+
+fn register(operand: Operand) -> Result<i32, Unsupported> {
+  match operand {
+    Operand::Immediate(InstructionParam::Int(reg)) if (0..PROCESS_REGISTERS_COUNT as i64).contains(&reg) =>
+      Ok(REGISTERS + reg as i32 * SLOT),
+    _ => Err(Unsupported)
+  }
+}
+
+struct Compiler<'a> {
+  ops: Assembler,
+  instructions: &'a [Instruction],
+  labels: Vec<DynamicLabel>,
+  /// Exits before every instruction
+  sides: Vec<DynamicLabel>,
+  pc: usize
+}
+
+impl<'a> Compiler<'a> {
+  fn new(instructions: &'a [Instruction]) -> io::Result<Self> {
+    let mut ops = Assembler::new()?;
+    let labels = instructions.iter().map(|_| ops.new_dynamic_label()).collect();
+    let sides = instructions.iter().map(|_| ops.new_dynamic_label()).collect();
+    Ok(Compiler { ops, instructions, labels, sides, pc: 0 })
+  }
+
+  fn compile(mut self) -> io::Result<JitProgram> {
+    let len = self.instructions.len();
+    let mut offsets = Vec::with_capacity(len);
+    let mut native = Vec::with_capacity(len);
+    for pc in 0..len {
+      self.pc = pc;
+      offsets.push(self.ops.offset());
+      dynasm!(self.ops ; .arch x64 ; =>self.labels[pc]);
+      let supported = self.instruction(self.instructions[pc]).is_ok();
+      if !supported {
+        let side = self.side();
+        dynasm!(self.ops ; .arch x64 ; jmp =>side);
+      }
+      native.push(supported);
+    }
+    self.leave(len as i64);
+
+    for pc in 0..len {
+      dynasm!(self.ops
+        ; .arch x64
+        ; =>self.sides[pc]
+        ; mov QWORD [rdi + PC], pc as i32
+        ; jmp ->exit
+      );
+    }
+    dynasm!(self.ops
+      ; .arch x64
+      ; ->exit:
+      ; shr r9, 4
+      ; mov [rdi + SP], r9
+      ; ret
+    );
+
+    // r9 is the size of the stack in bytes, r10 and r11 the address and size of the process memory
+    let entry = self.ops.offset();
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov r9, [rdi + SP]
+      ; shl r9, 4
+      ; mov r10, [rdi + MEMORY]
+      ; mov r11, [rdi + MEMORY_LEN]
+      ; mov rax, [rdi + TABLE]
+      ; jmp QWORD [rax + rsi * 8]
+    );
+
+    let buffer = self.ops.finalize().map_err(|_| io::Error::other("unable to finalize the native code"))?;
+    let table = offsets.into_iter().map(|offset| buffer.ptr(offset) as usize).collect();
+    Ok(JitProgram { buffer, entry, table, native })
+  }
+
+  fn side(&self) -> DynamicLabel {
+    self.sides[self.pc]
+  }
+
+  /// Leave the native code with the given pc
+  fn leave(&mut self, pc: i64) {
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov rax, QWORD pc
+      ; mov [rdi + PC], rax
+      ; jmp ->exit
+    );
+  }
+
+  /// Jump to the instruction, leaving at it once out of fuel
+  fn goto(&mut self, pc: i64) {
+    if let Some(&label) = self.labels.get(pc as usize).filter(|_| pc >= 0) {
+      dynasm!(self.ops ; .arch x64 ; sub QWORD [rdi + FUEL], 1 ; jnz =>label);
+    }
+    self.leave(pc)
+  }
+
+  /// Leave if the stack has not enough items to pop or no room for the pushed ones
+  fn check_stack(&mut self, pops: i32, pushes: i32) {
+    let side = self.side();
+    if pops > 0 {
+      dynasm!(self.ops ; .arch x64 ; cmp r9, pops * SLOT ; jb =>side);
+    }
+    if pushes > pops {
+      dynasm!(self.ops ; .arch x64 ; cmp r9, (STACK_SIZE as i32 - pushes + pops) * SLOT ; ja =>side);
+    }
+  }
+
+  /// Load the value of an operand into a register, leaving if it is not of the expected type
+  fn load(&mut self, reg: u8, operand: Operand, tag: i32) -> Emit {
+    let side = self.side();
+    match operand {
+      Operand::Immediate(InstructionParam::Int(x)) if tag == INT => dynasm!(self.ops ; .arch x64 ; mov Rq(reg), QWORD x),
+      Operand::Immediate(InstructionParam::Float(x)) if tag == FLOAT =>
+        dynasm!(self.ops ; .arch x64 ; mov Rq(reg), QWORD x.to_bits() as i64),
+      Operand::Immediate(_) => return Err(Unsupported),
+      Operand::Stack(depth) => dynasm!(self.ops
+        ; .arch x64
+        ; cmp QWORD [rdi + r9 - depth * SLOT], tag
+        ; jne =>side
+        ; mov Rq(reg), [rdi + r9 - depth * SLOT + 8]
+      )
+    }
+    Ok(())
+  }
+
+  /// Load the value in rax and the tag in rdx of an operand of any type
+  fn load_tagged(&mut self, operand: Operand) {
+    match operand {
+      Operand::Immediate(value) => {
+        let Slot { tag, value } = StackValue::from(value).into();
+        dynasm!(self.ops ; .arch x64 ; mov rax, QWORD value as i64 ; mov rdx, QWORD tag as i64);
+      }
+      Operand::Stack(depth) => dynasm!(self.ops
+        ; .arch x64
+        ; mov rdx, [rdi + r9 - depth * SLOT]
+        ; mov rax, [rdi + r9 - depth * SLOT + 8]
+      )
+    }
+  }
+
+  /// Load two operands of the same type, the values in rax and rcx and the tag in r8, leaving otherwise.
+  /// Jumps to the local label `float` with the values also in xmm0 and xmm1 when they are floats
+  fn load_same_type(&mut self, a: Operand, b: Operand) {
+    let side = self.side();
+    self.load_tagged(b);
+    dynasm!(self.ops ; .arch x64 ; mov rcx, rax ; mov r8, rdx);
+    self.load_tagged(a);
+    dynasm!(self.ops
+      ; .arch x64
+      ; cmp rdx, r8
+      ; jne =>side
+      ; cmp r8, FLOAT
+      ; jne >int
+      ; movq xmm0, rax
+      ; movq xmm1, rcx
+      ; jmp >float
+      ; int:
+    );
+  }
+
+  /// Load the tag in rax and the value in rcx of an operand of any type
+  fn load_any(&mut self, operand: Operand) {
+    match operand {
+      Operand::Immediate(value) => {
+        let Slot { tag, value } = StackValue::from(value).into();
+        dynasm!(self.ops ; .arch x64 ; mov rax, QWORD tag as i64 ; mov rcx, QWORD value as i64);
+      }
+      Operand::Stack(depth) => dynasm!(self.ops
+        ; .arch x64
+        ; mov rax, [rdi + r9 - depth * SLOT]
+        ; mov rcx, [rdi + r9 - depth * SLOT + 8]
+      )
+    }
+  }
+
+  fn pop(&mut self, pops: i32) {
+    if pops > 0 {
+      dynasm!(self.ops ; .arch x64 ; sub r9, pops * SLOT);
+    }
+  }
+
+  fn push(&mut self, reg: u8, tag: i32) {
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov QWORD [rdi + r9], tag
+      ; mov [rdi + r9 + 8], Rq(reg)
+      ; add r9, SLOT
+    );
+  }
+
+  /// Push the tag in rax and the value in rcx
+  fn push_any(&mut self) {
+    dynasm!(self.ops
+      ; .arch x64
+      ; mov [rdi + r9], rax
+      ; mov [rdi + r9 + 8], rcx
+      ; add r9, SLOT
+    );
+  }
+
+  /// Check the address in rax can be accessed with the given size
+  fn check_memory(&mut self, size: i32) {
+    let side = self.side();
+    dynasm!(self.ops
+      ; .arch x64
+      ; test r10, r10
+      ; jz =>side
+      ; mov rdx, r11
+      ; sub rdx, size
+      ; jb =>side
+      ; cmp rax, rdx
+      ; ja =>side
+    );
+  }
+
+  /// Check the access to the address in rax with the given size is out of the read only span
+  fn check_writable(&mut self, size: i32) {
+    let side = self.side();
+    dynasm!(self.ops
+      ; .arch x64
+      ; cmp rax, [rdi + READ_ONLY_END]
+      ; jae >writable
+      ; lea rdx, [rax + size]
+      ; cmp rdx, [rdi + READ_ONLY_START]
+      ; ja =>side
+      ; writable:
+    );
+  }
+
+  fn instruction(&mut self, instruction: Instruction) -> Emit {
+    let side = self.side();
+    match instruction.0 {
+      Opcode::Noop => (),
+
+      Opcode::Add | Opcode::Sub | Opcode::Mul | Opcode::Div => {
+        let ([a, b], pops) = both(&instruction);
+        self.check_stack(pops, 1);
+        self.load_same_type(a, b);
+        match instruction.0 {
+          Opcode::Add => dynasm!(self.ops ; .arch x64 ; add rax, rcx ; jo =>side ; jmp >done ; float: ; addsd xmm0, xmm1),
+          Opcode::Sub => dynasm!(self.ops ; .arch x64 ; sub rax, rcx ; jo =>side ; jmp >done ; float: ; subsd xmm0, xmm1),
+          Opcode::Mul => dynasm!(self.ops ; .arch x64 ; imul rax, rcx ; jo =>side ; jmp >done ; float: ; mulsd xmm0, xmm1),
+          _ => dynasm!(self.ops
+            ; .arch x64
+            ; test rcx, rcx
+            ; jz =>side
+            ; cmp rcx, -1
+            ; jne >divide
+            ; mov rdx, QWORD i64::MIN
+            ; cmp rax, rdx
+            ; je =>side
+            ; divide:
+            ; cqo
+            ; idiv rcx
+            ; jmp >done
+            ; float:
+            ; divsd xmm0, xmm1
+          )
+        }
+        dynasm!(self.ops ; .arch x64 ; movq rax, xmm0 ; done:);
+        self.pop(pops);
+        dynasm!(self.ops
+          ; .arch x64
+          ; mov [rdi + r9], r8
+          ; mov [rdi + r9 + 8], rax
+          ; add r9, SLOT
+        );
+      }
+
+      Opcode::Gt | Opcode::Ls | Opcode::Gteq | Opcode::Lseq | Opcode::Eq | Opcode::Noteq => {
+        let ([a, b], pops) = both(&instruction);
+        self.check_stack(pops, 1);
+        self.load_same_type(a, b);
+        dynasm!(self.ops ; .arch x64 ; cmp rax, rcx);
+        match instruction.0 {
+          Opcode::Gt => dynasm!(self.ops ; .arch x64 ; setg al),
+          Opcode::Ls => dynasm!(self.ops ; .arch x64 ; setl al),
+          Opcode::Gteq => dynasm!(self.ops ; .arch x64 ; setge al),
+          Opcode::Lseq => dynasm!(self.ops ; .arch x64 ; setle al),
+          Opcode::Eq => dynasm!(self.ops ; .arch x64 ; sete al),
+          _ => dynasm!(self.ops ; .arch x64 ; setne al)
+        }
+        // unordered floats are only different
+        dynasm!(self.ops ; .arch x64 ; jmp >done ; float:);
+        match instruction.0 {
+          Opcode::Gt => dynasm!(self.ops ; .arch x64 ; ucomisd xmm0, xmm1 ; seta al),
+          Opcode::Ls => dynasm!(self.ops ; .arch x64 ; ucomisd xmm1, xmm0 ; seta al),
+          Opcode::Gteq => dynasm!(self.ops ; .arch x64 ; ucomisd xmm0, xmm1 ; setae al),
+          Opcode::Lseq => dynasm!(self.ops ; .arch x64 ; ucomisd xmm1, xmm0 ; setae al),
+          Opcode::Eq => dynasm!(self.ops ; .arch x64 ; ucomisd xmm0, xmm1 ; sete al ; setnp cl ; and al, cl),
+          _ => dynasm!(self.ops ; .arch x64 ; ucomisd xmm0, xmm1 ; setne al ; setp cl ; or al, cl)
+        }
+        dynasm!(self.ops ; .arch x64 ; done: ; movzx rax, al);
+        self.pop(pops);
+        self.push(RAX, INT);
+      }
+
+      Opcode::Int | Opcode::Float => {
+        let (a, pops) = first(&instruction);
+        self.check_stack(pops, 1);
+        self.load_tagged(a);
+        let tag = match instruction.0 {
+          // out of range floats saturate in the interpreter, cvttsd2si gives i64::MIN for them
+          Opcode::Int => {
+            dynasm!(self.ops
+              ; .arch x64
+              ; cmp rdx, INT
+              ; je >done
+              ; movq xmm0, rax
+              ; cvttsd2si rax, xmm0
+              ; mov rcx, QWORD i64::MIN
+              ; cmp rax, rcx
+              ; je =>side
+              ; done:
+            );
+            INT
+          }
+          _ => {
+            dynasm!(self.ops
+              ; .arch x64
+              ; cmp rdx, FLOAT
+              ; je >done
+              ; cvtsi2sd xmm0, rax
+              ; movq rax, xmm0
+              ; done:
+            );
+            FLOAT
+          }
+        };
+        self.pop(pops);
+        self.push(RAX, tag);
+      }
+
+      Opcode::Discard => dynasm!(self.ops
+        ; .arch x64
+        ; test r9, r9
+        ; jz >empty
+        ; sub r9, SLOT
+        ; empty:
+      ),
+      Opcode::Clone => dynasm!(self.ops
+        ; .arch x64
+        ; test r9, r9
+        ; jz >empty
+        ; cmp r9, (STACK_SIZE as i32 - 1) * SLOT
+        ; ja =>side
+        ; mov rax, [rdi + r9 - SLOT]
+        ; mov rcx, [rdi + r9 - SLOT + 8]
+        ; mov [rdi + r9], rax
+        ; mov [rdi + r9 + 8], rcx
+        ; add r9, SLOT
+        ; empty:
+      ),
+      Opcode::Push => {
+        let values: Vec<_> = [instruction.1, instruction.2].into_iter().flatten().collect();
+        self.check_stack(0, values.len() as i32);
+        for value in values {
+          self.load_any(Operand::Immediate(value));
+          self.push_any();
+        }
+      }
+      Opcode::Swap | Opcode::Over => {
+        if instruction.1.is_some() || instruction.2.is_some() {
+          return Err(Unsupported)
+        }
+        let over = matches!(instruction.0, Opcode::Over);
+        self.check_stack(2, if over { 3 } else { 2 });
+        dynasm!(self.ops
+          ; .arch x64
+          ; mov rax, [rdi + r9 - 2 * SLOT]
+          ; mov rcx, [rdi + r9 - 2 * SLOT + 8]
+          ; mov rdx, [rdi + r9 - SLOT]
+          ; mov r8, [rdi + r9 - SLOT + 8]
+        );
+        match over {
+          true => self.push_any(),
+          false => dynasm!(self.ops
+            ; .arch x64
+            ; mov [rdi + r9 - 2 * SLOT], rdx
+            ; mov [rdi + r9 - 2 * SLOT + 8], r8
+            ; mov [rdi + r9 - SLOT], rax
+            ; mov [rdi + r9 - SLOT + 8], rcx
+          )
+        }
+      }
+
+      Opcode::Reg => {
+        let (reg, _) = first(&instruction);
+        let offset = register(reg)?;
+        self.check_stack(0, 1);
+        dynasm!(self.ops
+          ; .arch x64
+          ; mov rax, [rdi + offset]
+          ; mov rcx, [rdi + offset + 8]
+        );
+        self.push_any();
+      }
+      Opcode::SetReg => {
+        let ([reg, value], pops) = both(&instruction);
+        let offset = register(reg)?;
+        self.check_stack(pops, 0);
+        self.load_any(value);
+        self.pop(pops);
+        dynasm!(self.ops
+          ; .arch x64
+          ; mov [rdi + offset], rax
+          ; mov [rdi + offset + 8], rcx
+        );
+      }
+
+      Opcode::ReadInt64 | Opcode::ReadInt32 | Opcode::ReadInt16 | Opcode::ReadInt8 | Opcode::ReadFloat64 => {
+        let (address, pops) = first(&instruction);
+        self.check_stack(pops, 1);
+        self.load(RAX, address, INT)?;
+        let (size, tag) = match instruction.0 {
+          Opcode::ReadInt32 => (4, INT),
+          Opcode::ReadInt16 => (2, INT),
+          Opcode::ReadInt8 => (1, INT),
+          Opcode::ReadFloat64 => (8, FLOAT),
+          _ => (8, INT)
+        };
+        self.check_memory(size);
+        match size {
+          4 => dynasm!(self.ops ; .arch x64 ; movsxd rax, DWORD [r10 + rax]),
+          2 => dynasm!(self.ops ; .arch x64 ; movsx rax, WORD [r10 + rax]),
+          1 => dynasm!(self.ops ; .arch x64 ; movsx rax, BYTE [r10 + rax]),
+          _ => dynasm!(self.ops ; .arch x64 ; mov rax, [r10 + rax])
+        }
+        self.pop(pops);
+        self.push(RAX, tag);
+      }
+      Opcode::WriteInt64 | Opcode::WriteInt32 | Opcode::WriteInt16 | Opcode::WriteInt8 | Opcode::WriteFloat64 => {
+        let ([address, value], pops) = both(&instruction);
+        self.check_stack(pops, 0);
+        let (size, tag) = match instruction.0 {
+          Opcode::WriteInt32 => (4, INT),
+          Opcode::WriteInt16 => (2, INT),
+          Opcode::WriteInt8 => (1, INT),
+          Opcode::WriteFloat64 => (8, FLOAT),
+          _ => (8, INT)
+        };
+        self.load(RAX, address, INT)?;
+        self.load(RCX, value, tag)?;
+        self.check_memory(size);
+        self.check_writable(size);
+        match size {
+          4 => dynasm!(self.ops ; .arch x64 ; mov [r10 + rax], ecx),
+          2 => dynasm!(self.ops ; .arch x64 ; mov [r10 + rax], cx),
+          1 => dynasm!(self.ops ; .arch x64 ; mov [r10 + rax], cl),
+          _ => dynasm!(self.ops ; .arch x64 ; mov [r10 + rax], rcx)
+        }
+        self.pop(pops);
+      }
+
+      Opcode::Jump => {
+        let ([pc, cond], pops) = both(&instruction);
+        self.check_stack(pops, 0);
+        self.load(RCX, cond, INT)?;
+        match pc {
+          Operand::Immediate(InstructionParam::Int(pc)) => match cond {
+            Operand::Immediate(InstructionParam::Int(0)) => (),
+            Operand::Immediate(_) => self.goto(pc),
+            Operand::Stack(_) => {
+              self.pop(pops);
+              dynasm!(self.ops ; .arch x64 ; test rcx, rcx ; jz >skip);
+              self.goto(pc);
+              dynasm!(self.ops ; .arch x64 ; skip:);
+            }
+          }
+          Operand::Immediate(_) => return Err(Unsupported),
+          Operand::Stack(_) => {
+            self.load(RAX, pc, INT)?;
+            self.pop(pops);
+            let len = self.instructions.len() as i32;
+            dynasm!(self.ops
+              ; .arch x64
+              ; test rcx, rcx
+              ; jz >skip
+              ; cmp rax, len
+              ; jae >out
+              ; sub QWORD [rdi + FUEL], 1
+              ; jz >out
+              ; mov rdx, [rdi + TABLE]
+              ; jmp QWORD [rdx + rax * 8]
+              ; out:
+              ; mov [rdi + PC], rax
+              ; jmp ->exit
+              ; skip:
+            );
+          }
+        }
+      }
+      Opcode::Exit => self.leave(self.instructions.len() as i64),
+
+      _ => return Err(Unsupported)
+    }
+    Ok(())
+  }
+}
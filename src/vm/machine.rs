@@ -1,31 +1,94 @@
-use std::{sync::{atomic::AtomicUsize, Arc, Condvar, Mutex, RwLock}, thread::{self, JoinHandle}};
+use std::{
+  collections::HashMap, panic, path::PathBuf,
+  sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Condvar, Mutex, RwLock}, thread::{self, JoinHandle}
+};
+
+use thiserror::Error;
+
+use crate::parser::Parser;
 
 use super::{
-  ffi::{invoke_ffi, invoke_ffi_memory, invoke_ffi_trap, FFILoader},
-  memory::{Memory, MemoryHandler},
-  process::{ProcesSupervisor, Process, PUBLIC_REGISTERS_COUNT},
-  program::Program, stack::StackValue
+  checkpoint::{self, Checkpoint, CheckpointError},
+  ffi::{invoke_ffi, invoke_ffi_memory, invoke_ffi_trap, FFILoader, HostFn},
+  memory::{Access, Memory, MemoryHandler, ProcessMemory, Restricted, UnitFault, UNIT_LIMIT},
+  process::{ProcesSupervisor, Process, ProcessFault, PublicRegisters, PUBLIC_REGISTERS_COUNT},
+  program::{Program, UnknownUnitsError}, stack::StackValue
 };
 
+/// A shared memory unit
+pub type Unit = Arc<RwLock<dyn Memory>>;
+
+/// Why a process did not finish
+#[derive(Debug, Error)]
+pub enum ProcessError {
+  #[error("process {pid} faulted at the instruction {pc}: {fault}")]
+  Fault { pid: usize, pc: usize, fault: ProcessFault },
+
+  #[error("process {0} was killed")]
+  Killed(usize)
+}
+
+/// A program that could not be launched
+#[derive(Debug, Error)]
+pub enum LaunchError<E> {
+  #[error("{0}")]
+  Parse(E),
+
+  #[error("{0}")]
+  UnknownUnits(#[from] UnknownUnitsError)
+}
+
 struct MachineInternal {
-  active: (Mutex<usize>, Condvar),
-  buffers: Vec<Arc<RwLock<dyn Memory>>>,
+  /// The processes running, apart from the machine so they drop it before ending
+  active: Arc<(Mutex<usize>, Condvar)>,
+  /// The shared memory units by index, `None` once destroyed, the indices are not reused
+  units: RwLock<Vec<Option<(Access, Unit)>>>,
+  /// The units named when building the machine, for the `%name` operands
+  names: HashMap<String, usize>,
   ffi: Vec<FFILoader>,
-  pid_counter: AtomicUsize
+  /// Functions of the embedder by name, invoked before looking in the libraries
+  host_fns: HashMap<Vec<u8>, HostFn>,
+  pid_counter: AtomicUsize,
+  /// Count of the processes ended by a fault
+  faults: AtomicUsize,
+  /// Where the processes save their checkpoints, they do not save any without it
+  checkpoint_dir: Option<PathBuf>,
+  /// Count of the checkpoints requested to every process
+  checkpoint_requests: AtomicUsize,
+  #[cfg(feature = "jit")]
+  jit: bool
 }
 
 impl MachineInternal {
   fn new() -> Self {
     MachineInternal {
-      active: (Mutex::new(0), Condvar::new()),
-      buffers: vec![],
+      active: Arc::new((Mutex::new(0), Condvar::new())),
+      units: RwLock::new(vec![]),
+      names: HashMap::new(),
       ffi: vec![],
-      pid_counter: AtomicUsize::new(0)
+      host_fns: HashMap::new(),
+      pid_counter: AtomicUsize::new(0),
+      faults: AtomicUsize::new(0),
+      checkpoint_dir: None,
+      checkpoint_requests: AtomicUsize::new(0),
+      #[cfg(feature = "jit")]
+      jit: false
     }
   }
 
-  pub fn add_memory(&mut self, memory: impl Memory + 'static) {
-    self.buffers.push(Arc::new(RwLock::new(memory)))
+  pub fn add_memory(&self, memory: impl Memory + 'static, access: Access) -> usize {
+    let mut units = self.units.write().unwrap();
+    units.push(Some((access, Arc::new(RwLock::new(memory)))));
+    units.len() - 1
+  }
+
+  /// Remove the unit from the table, the processes with it mounted keep it until they unmount it
+  pub fn destroy_unit(&self, unit: usize) -> bool {
+    self.units.write().unwrap().get_mut(unit).and_then(Option::take).is_some()
+  }
+
+  pub fn unit(&self, unit: usize) -> Option<(Access, Unit)> {
+    self.units.read().unwrap().get(unit).cloned().flatten()
   }
 
   pub fn add_ffi_loader(&mut self, loader: FFILoader) {
@@ -33,24 +96,35 @@ impl MachineInternal {
   }
 
   pub fn get_new_pid(&self) -> usize {
-    self.pid_counter.fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+    self.pid_counter.fetch_add(1, Ordering::Relaxed)
   }
 }
 
 struct MachineProcessSupervisor {
   machine: Arc<MachineInternal>,
-  memory: Vec<u8>,
-  external_memory: Option<Arc<RwLock<dyn Memory>>>,
+  memory: ProcessMemory,
+  external_memory: Option<(usize, Access, Unit)>,
   pid: usize,
+  /// Names the checkpoint files, the pid unless the process was resumed from a checkpoint
+  checkpoint_id: usize,
+  /// The checkpoint requests of the machine already answered
+  checkpoints: usize,
+  killed: Arc<AtomicBool>
 }
 
 impl MachineProcessSupervisor {
-  pub fn new(pid: usize, machine: Arc<MachineInternal>, memory: Vec<u8>) -> Self {
+  pub fn new(
+    pid: usize, checkpoint_id: usize, machine: Arc<MachineInternal>, memory: ProcessMemory, killed: Arc<AtomicBool>
+  ) -> Self {
+    let checkpoints = machine.checkpoint_requests.load(Ordering::Relaxed);
     MachineProcessSupervisor {
       machine,
       memory,
       external_memory: None,
-      pid
+      pid,
+      checkpoint_id,
+      checkpoints,
+      killed
     }
   }
 }
@@ -60,67 +134,244 @@ impl ProcesSupervisor for MachineProcessSupervisor {
     self.pid
   }
 
-  fn set_memory(&mut self, unit: Option<usize>) {
+  fn set_memory(&mut self, unit: Option<usize>, access: Access) -> Result<(), UnitFault> {
     self.external_memory = match unit {
-      Some(idx) => Some(self.machine.buffers[idx].clone()),
-      None => None,
-    }
+      Some(idx) => {
+        let (unit_access, memory) = self.machine.unit(idx).ok_or(UnitFault::Unknown(idx))?;
+        Some((idx, unit_access.intersect(access), memory))
+      }
+      None => None
+    };
+    Ok(())
   }
 
-  fn get_memory(&mut self) -> MemoryHandler {
+  fn get_memory(&mut self) -> MemoryHandler<'_> {
     self.external_memory.as_ref()
-      .map(|x| MemoryHandler::MemoryLock(x.clone()))
+      .map(|(unit, access, memory)| MemoryHandler::MemoryLock(*unit, *access, memory.clone()))
       .unwrap_or(MemoryHandler::MemoryRef(&mut self.memory))
   }
 
+  fn unit_memory(&mut self, unit: Option<usize>) -> Result<MemoryHandler<'_>, UnitFault> {
+    match unit {
+      Some(idx) => {
+        let (access, memory) = self.machine.unit(idx).ok_or(UnitFault::Unknown(idx))?;
+        Ok(MemoryHandler::MemoryLock(idx, access, memory))
+      }
+      None => Ok(MemoryHandler::MemoryRef(&mut self.memory))
+    }
+  }
+
+  fn process_memory(&mut self) -> &mut ProcessMemory {
+    &mut self.memory
+  }
+
+  /// Save the process when the machine requested a checkpoint since the last one, false once it is killed
+  fn poll(&mut self, process: &Process) -> bool {
+    if self.machine.checkpoint_requests.load(Ordering::Relaxed) != self.checkpoints {
+      self.checkpoint(process)
+    }
+    !self.killed.load(Ordering::Relaxed)
+  }
+
+  fn local_memory(&mut self) -> Option<&mut ProcessMemory> {
+    match self.external_memory {
+      Some(_) => None,
+      None => Some(&mut self.memory)
+    }
+  }
+
+  fn create_unit(&mut self, size: usize) -> Result<usize, UnitFault> {
+    if size > UNIT_LIMIT {
+      return Err(UnitFault::TooLarge(size))
+    }
+    Ok(self.machine.add_memory(vec![0u8; size], Access::READ_WRITE))
+  }
+
+  fn destroy_unit(&mut self, unit: usize) -> Result<(), UnitFault> {
+    match self.machine.destroy_unit(unit) {
+      true => Ok(()),
+      false => Err(UnitFault::Unknown(unit))
+    }
+  }
+
   fn fork(&self, process: Process) {
-    launch(self.machine.clone(), process);
+    launch(self.machine.clone(), process, None);
+  }
+
+  fn checkpoint(&mut self, process: &Process) {
+    self.checkpoints = self.machine.checkpoint_requests.load(Ordering::Relaxed);
+    let Some(dir) = &self.machine.checkpoint_dir else { return };
+    let mount = self.external_memory.as_ref().map(|(unit, access, _)| (*unit, *access));
+    let path = dir.join(format!("{}.checkpoint", self.checkpoint_id));
+    // a failed checkpoint leaves the previous one in place, the process keeps running
+    if let Err(err) = checkpoint::save(path, self.checkpoint_id, process, &self.memory, mount) {
+      eprintln!("unable to checkpoint the process {}: {}", self.pid, err)
+    }
   }
   
-  fn invoke_ffi(&mut self, symbol: &[u8], process: &mut Process) -> Option<StackValue> {
+  fn invoke_ffi(&mut self, symbol: &[u8], process: &mut Process) -> Result<Option<StackValue>, ProcessFault> {
+    let machine = self.machine.clone();
+    if let Some(host) = machine.host_fns.get(symbol) { // host function, whatever the flags
+      let registers: &mut PublicRegisters = (&mut process.registers[..PUBLIC_REGISTERS_COUNT]).try_into().unwrap();
+      return match host {
+        HostFn::Plain(function) => Ok(function(registers)),
+        HostFn::Memory(function) => Ok(match &self.external_memory {
+          Some((_, access, external)) => {
+            let memory = &mut *external.write().unwrap();
+            function(registers, &mut Restricted { memory, access: *access })
+          }
+          None => function(registers, &mut self.memory)
+        }),
+        HostFn::Trap(function) => function(process, self)
+      }
+    }
+
     let registers = &mut process.registers[0..PUBLIC_REGISTERS_COUNT].try_into().unwrap();
 
     if process.get_flag_invoke_trap() { // ffi invoking a trap
-      let machine = self.machine.clone();
-
       unsafe {
         invoke_ffi_trap(&machine.ffi, symbol, process, self)
       }.unwrap()
     } else if process.get_flag_share_memory() { // ffi sharing memory
-      unsafe {
+      Ok(unsafe {
         match &self.external_memory {
-          Some(external) => invoke_ffi_memory(&self.machine.ffi, symbol, registers, &mut *external.write().unwrap()),
+          Some((_, access, external)) => {
+            let memory = &mut *external.write().unwrap();
+            invoke_ffi_memory(&self.machine.ffi, symbol, registers, &mut Restricted { memory, access: *access })
+          }
           None => invoke_ffi_memory(&self.machine.ffi, symbol, registers,  &mut self.memory)
         }
-      }.unwrap()
+      }.unwrap())
     } else { // normal ffi
-      unsafe {
+      Ok(unsafe {
         invoke_ffi(&self.machine.ffi, symbol, registers)
-      }.unwrap()
+      }.unwrap())
     }
   }
 }
 
-fn launch(machine: Arc<MachineInternal>, mut process: Process) -> Option<JoinHandle<Process>> {
-  if process.is_finished() {
-    return None
+/// Instructions interpreted between the checks for the requests of the machine and the kills
+const POLL_INTERVAL: usize = 1 << 16;
+
+/// Run the process until it finishes, answering the requests in between, false when it is killed
+fn interpret(process: &mut Process, supervisor: &mut MachineProcessSupervisor) -> Result<bool, ProcessFault> {
+  loop {
+    for _ in 0..POLL_INTERVAL {
+      if !process.run_next(supervisor)? {
+        return Ok(true)
+      }
+    }
+    if !supervisor.poll(process) {
+      return Ok(false)
+    }
+  }
+}
+
+#[cfg(feature = "jit")]
+fn run(process: &mut Process, supervisor: &mut MachineProcessSupervisor) -> Result<bool, ProcessFault> {
+  match supervisor.machine.jit {
+    true => super::jit::run(process, supervisor),
+    false => interpret(process, supervisor)
+  }
+}
+
+#[cfg(not(feature = "jit"))]
+fn run(process: &mut Process, supervisor: &mut MachineProcessSupervisor) -> Result<bool, ProcessFault> {
+  interpret(process, supervisor)
+}
+
+/// Counts a running process until dropped, even by the fault ending it
+struct Running(Arc<(Mutex<usize>, Condvar)>);
+
+impl Running {
+  fn new(active: Arc<(Mutex<usize>, Condvar)>) -> Self {
+    *active.0.lock().unwrap() += 1;
+    Running(active)
+  }
+}
+
+impl Drop for Running {
+  fn drop(&mut self) {
+    let (count, process_ended) = &*self.0;
+    *count.lock().unwrap() -= 1;
+    process_ended.notify_all();
   }
+}
 
-  *machine.active.0.lock().unwrap() += 1;
+/// The id, the memory and the mounted unit of a process resumed from a checkpoint
+type Resumed = (usize, ProcessMemory, Option<(usize, Access)>);
 
+/// Run the process in a thread, with a fresh memory or the one of a checkpoint
+fn launch(machine: Arc<MachineInternal>, mut process: Process, restored: Option<Resumed>) -> ProcessHandle {
+  let running = Running::new(machine.active.clone());
+  let killed = Arc::new(AtomicBool::new(false));
   let pid = machine.get_new_pid();
-  Some(thread::Builder::new().name(format!("process_{}", pid)).spawn(move || {
-    let mut supervisor = MachineProcessSupervisor::new(pid, machine, process.program.memory());
+  let process_killed = killed.clone();
+  let thread = thread::Builder::new().name(format!("process_{}", pid)).spawn(move || {
+    // dropped last, the units are released before waiting ends, like the shared memory objects removed by their drop
+    let _running = running;
+    let (checkpoint_id, memory, mount) = restored.unwrap_or_else(|| (pid, ProcessMemory::new(&process.program), None));
+    let mut supervisor = MachineProcessSupervisor::new(pid, checkpoint_id, machine, memory, process_killed);
+    // a unit destroyed since the checkpoint faults before the first instruction
+    let mounted = mount.map_or(Ok(()), |(unit, access)| supervisor.set_memory(Some(unit), access));
+    match mounted.map_err(ProcessFault::from).and_then(|_| run(&mut process, &mut supervisor)) {
+      Ok(true) => (),
+      Ok(false) => return Err(ProcessError::Killed(pid)),
+      Err(fault) => {
+        // reported here as the processes forked or launched by the command line are never joined
+        let error = ProcessError::Fault { pid, pc: process.pc, fault };
+        eprintln!("{}", error);
+        supervisor.machine.faults.fetch_add(1, Ordering::Relaxed);
+        return Err(error)
+      }
+    }
+    let (allocations, bytes) = supervisor.memory.allocations().fold((0, 0), |(count, total), (_, size)| (count + 1, total + size));
+    if allocations > 0 {
+      eprintln!("process {} leaked {} bytes in {} allocations", pid, bytes, allocations);
+    }
+    Ok(process)
+  }).expect("error creating thread");
+  ProcessHandle { pid, thread, killed }
+}
+
+/// A process launched in the machine, dropping the handle leaves it running
+pub struct ProcessHandle {
+  pid: usize,
+  thread: JoinHandle<Result<Process, ProcessError>>,
+  killed: Arc<AtomicBool>
+}
+
+impl ProcessHandle {
+  pub fn pid(&self) -> usize {
+    self.pid
+  }
+
+  pub fn is_finished(&self) -> bool {
+    self.thread.is_finished()
+  }
+
+  /// Stop the process within the next instructions it runs
+  ///
+  /// The process checks it between its instructions every 65536 of them, or at the jumps of the native code under
+  /// the jit, so a process blocked in an ffi function or a `ThreadSleep` only stops once it gets back to run them
+  pub fn kill(&self) {
+    self.killed.store(true, Ordering::Relaxed)
+  }
 
-    process.run_until_finish(&mut supervisor);
-    *supervisor.machine.active.0.lock().unwrap() -= 1;
-    supervisor.machine.active.1.notify_all();
-    process
-  }).expect("error creating thread"))
+  /// Wait for the process to end, returns its final state or why it did not finish
+  pub fn join(self) -> Result<Process, ProcessError> {
+    self.thread.join().unwrap_or_else(|payload| panic::resume_unwind(payload))
+  }
 }
 
 pub struct Machine(Arc<MachineInternal>);
 
+impl Default for Machine {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Machine {
   pub fn new() -> Self {
     Machine(Arc::new(MachineInternal::new()))
@@ -130,12 +381,72 @@ impl Machine {
     Machine(Arc::new(internal))
   }
 
-  pub fn launch(&mut self, program: Program) {
-    launch(self.0.clone(), program.into());
+  /// Launch a process running the program, once its unit names are resolved
+  pub fn launch(&mut self, mut program: Program) -> Result<ProcessHandle, UnknownUnitsError> {
+    self.resolve_units(&mut program)?;
+    Ok(launch(self.0.clone(), program.into(), None))
+  }
+
+  /// Parse the source with a front-end, like `machine.launch_source::<Simple>("main", source)`, and launch it
+  pub fn launch_source<P: Parser>(&mut self, name: &str, source: &str) -> Result<ProcessHandle, LaunchError<P::Err>> {
+    let mut program = Program::with_name(name);
+    P::parse(&mut program, source).map_err(LaunchError::Parse)?;
+    Ok(self.launch(program)?)
+  }
+
+  /// Launch the process of a checkpoint where it was saved, the unit it had mounted must exist
+  ///
+  /// It gets a new pid but saves its checkpoints with the id of the checkpoint, the pids launched after it skip the id
+  pub fn resume(&mut self, checkpoint: Checkpoint) -> Result<ProcessHandle, CheckpointError> {
+    let Checkpoint { id, process, memory, mount, path } = checkpoint;
+    if let Some((unit, _)) = mount.filter(|&(unit, _)| self.0.unit(unit).is_none()) {
+      return Err(CheckpointError::UnknownUnit { path, unit })
+    }
+    self.0.pid_counter.fetch_max(id + 1, Ordering::Relaxed);
+    Ok(launch(self.0.clone(), process, Some((id, memory, mount))))
+  }
+
+  /// Ask the running processes to save a checkpoint, they do it within the next instructions they interpret
+  ///
+  /// Nothing is saved without a checkpoint directory
+  pub fn request_checkpoint(&self) {
+    self.0.checkpoint_requests.fetch_add(1, Ordering::Relaxed);
+  }
+
+  /// Replace the `%name` operands of the program by the index of the unit, needed before optimizing it
+  pub fn resolve_units(&self, program: &mut Program) -> Result<(), UnknownUnitsError> {
+    program.resolve_units(|name| self.0.names.get(name).copied())
+  }
+
+  /// The index of a named unit
+  pub fn unit_index(&self, name: &str) -> Option<usize> {
+    self.0.names.get(name).copied()
+  }
+
+  /// Add a shared memory unit the processes can mount, returns its index
+  pub fn create_unit(&mut self, memory: impl Memory + 'static, access: Access) -> usize {
+    self.0.add_memory(memory, access)
+  }
+
+  /// Remove a shared memory unit, the processes with it mounted keep it until they unmount it
+  ///
+  /// Returns whether the unit existed
+  pub fn destroy_unit(&mut self, unit: usize) -> bool {
+    self.0.destroy_unit(unit)
+  }
+
+  /// A shared memory unit and the access the processes have to it
+  pub fn unit(&self, unit: usize) -> Option<(Access, Unit)> {
+    self.0.unit(unit)
+  }
+
+  /// The processes ended by a fault so far, the fault is also written to the standard error
+  pub fn faults(&self) -> usize {
+    self.0.faults.load(Ordering::Relaxed)
   }
 
   pub fn wait(&mut self) {
-    let (count, process_ended) = &self.0.active;
+    let (count, process_ended) = &*self.0.active;
     let mut count_lock = count.lock().unwrap();
     while *count_lock > 0 {
       count_lock = process_ended.wait(count_lock).unwrap();
@@ -145,13 +456,32 @@ impl Machine {
 
 pub struct MachineBuilder(MachineInternal);
 
+impl Default for MachineBuilder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl MachineBuilder {
   pub fn new() -> Self {
     MachineBuilder(MachineInternal::new())
   }
 
-  pub fn add_memory(mut self, memory: impl Memory + 'static) -> Self {
-    self.0.add_memory(memory);
+  pub fn add_memory(self, memory: impl Memory + 'static) -> Self {
+    self.0.add_memory(memory, Access::READ_WRITE);
+    self
+  }
+
+  /// Add a memory unit the processes can only access in the given way
+  pub fn add_memory_with_access(self, memory: impl Memory + 'static, access: Access) -> Self {
+    self.0.add_memory(memory, access);
+    self
+  }
+
+  /// Add a memory unit the programs can mount by name, `Mount %name`, replacing an earlier unit with the same name
+  pub fn add_named_memory(mut self, name: impl Into<String>, memory: impl Memory + 'static, access: Access) -> Self {
+    let unit = self.0.add_memory(memory, access);
+    self.0.names.insert(name.into(), unit);
     self
   }
 
@@ -160,6 +490,26 @@ impl MachineBuilder {
     self
   }
 
+  /// Add a function the programs invoke by name, found before the functions of the ffi libraries so it shadows the
+  /// one with the same name in a library
+  pub fn add_host_fn(mut self, name: impl AsRef<str>, function: HostFn) -> Self {
+    self.0.host_fns.insert(name.as_ref().as_bytes().to_vec(), function);
+    self
+  }
+
+  /// Save the checkpoints of the processes in the directory, as `pid.checkpoint` with the pid of the first one saved
+  pub fn checkpoint_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+    self.0.checkpoint_dir = Some(dir.into());
+    self
+  }
+
+  /// Compile the programs of the processes to native code once they are hot
+  #[cfg(feature = "jit")]
+  pub fn jit(mut self, enabled: bool) -> Self {
+    self.0.jit = enabled;
+    self
+  }
+
   pub fn build(self) -> Machine {
     Machine::with_content(self.0)
   }
@@ -0,0 +1,135 @@
+//! Memory units mapping a window of a file
+//!
+//! The window starts at an offset of the file and is mapped read only, for input data, or read-write, extending the
+//! file to cover it. A growable map extends the file and maps it again when a write goes past its end, up to the size
+//! of the largest unit, the writes reach the disk when the kernel decides or when [`Memory::sync`] flushes them
+
+use std::{fs::{File, OpenOptions}, io, path::Path};
+
+use memmap2::{Mmap, MmapMut, MmapOptions};
+use thiserror::Error;
+
+use super::memory::{bytes, bytes_mut, FaultKind, Memory, MemoryFault, UNIT_LIMIT};
+
+#[derive(Debug, Error)]
+pub enum MappedFileError {
+  #[error("{path}: {source}")]
+  Io { path: String, source: io::Error },
+
+  #[error("{path} is {len} bytes, shorter than the {end} bytes to map")]
+  TooShort { path: String, len: u64, end: u64 },
+
+  #[error("{0} is mapped read only, it can not grow")]
+  ReadOnlyGrowth(String)
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MapOptions {
+  /// Start of the window in the file
+  pub offset: u64,
+  pub read_only: bool,
+  /// Extend the file when writing past the end of the window
+  pub grow: bool
+}
+
+enum Map {
+  ReadOnly(Mmap),
+  ReadWrite(MmapMut)
+}
+
+pub struct MappedFile {
+  file: File,
+  map: Map,
+  options: MapOptions
+}
+
+impl MappedFile {
+  /// Map `size` bytes of the file, a read only file must already hold them and a read-write one is created or extended
+  pub fn open(path: impl AsRef<Path>, size: usize, options: MapOptions) -> Result<MappedFile, MappedFileError> {
+    let path = path.as_ref();
+    let name = || path.display().to_string();
+    let io = |source| MappedFileError::Io { path: name(), source };
+    if options.read_only && options.grow {
+      return Err(MappedFileError::ReadOnlyGrowth(name()))
+    }
+
+    let file = OpenOptions::new().read(true).write(!options.read_only).create(!options.read_only).truncate(false)
+      .open(path).map_err(io)?;
+    let (len, end) = (file.metadata().map_err(io)?.len(), options.offset + size as u64);
+    if len < end {
+      match options.read_only {
+        true => return Err(MappedFileError::TooShort { path: name(), len, end }),
+        false => file.set_len(end).map_err(io)?
+      }
+    }
+    let map = Self::map(&file, size, options).map_err(io)?;
+    Ok(MappedFile { file, map, options })
+  }
+
+  fn map(file: &File, size: usize, options: MapOptions) -> io::Result<Map> {
+    let mut map = MmapOptions::new();
+    map.offset(options.offset).len(size);
+    Ok(match options.read_only {
+      true => Map::ReadOnly(unsafe { map.map(file)? }),
+      false => Map::ReadWrite(unsafe { map.map_mut(file)? })
+    })
+  }
+
+  /// Extend the file and the window to at least `end` bytes, doubling the window to amortize the remaps
+  fn grow(&mut self, end: usize) -> io::Result<()> {
+    let size = end.max(self.size() * 2).min(UNIT_LIMIT);
+    let len = self.options.offset + size as u64;
+    if self.file.metadata()?.len() < len {
+      self.file.set_len(len)?;
+    }
+    self.map = Self::map(&self.file, size, self.options)?;
+    Ok(())
+  }
+
+  /// The bytes in `[offset, offset + length)` to write, growing the file first when allowed
+  fn writable(&mut self, offset: usize, length: usize) -> Result<&mut [u8], MemoryFault> {
+    let end = offset.checked_add(length).ok_or(MemoryFault::new(offset, length))?;
+    // growth stops at the largest unit, the writes past it are out of bounds
+    if self.options.grow && end > self.size() && end <= UNIT_LIMIT {
+      self.grow(end).map_err(|err| MemoryFault::new(offset, length).with_kind(FaultKind::Io(err.kind())))?;
+    }
+    match &mut self.map {
+      Map::ReadOnly(_) => Err(MemoryFault::new(offset, length).with_kind(FaultKind::ReadOnly)),
+      Map::ReadWrite(map) => bytes_mut(map, offset, length)
+    }
+  }
+
+  fn bytes(&self) -> &[u8] {
+    match &self.map {
+      Map::ReadOnly(map) => map,
+      Map::ReadWrite(map) => map
+    }
+  }
+}
+
+impl Memory for MappedFile {
+  fn size(&self) -> usize {
+    self.bytes().len()
+  }
+
+  fn try_write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryFault> {
+    self.writable(offset, data.len())?.copy_from_slice(data);
+    Ok(())
+  }
+
+  fn try_fill(&mut self, offset: usize, length: usize, byte: u8) -> Result<(), MemoryFault> {
+    self.writable(offset, length)?.fill(byte);
+    Ok(())
+  }
+
+  fn try_read(&self, offset: usize, size: usize) -> Result<&[u8], MemoryFault> {
+    bytes(self.bytes(), offset, size)
+  }
+
+  fn sync(&self) -> io::Result<()> {
+    match &self.map {
+      Map::ReadOnly(_) => Ok(()),
+      Map::ReadWrite(map) => map.flush()
+    }
+  }
+}
@@ -1,37 +1,467 @@
-use std::{ops::DerefMut, sync::{Arc, RwLock}};
+use std::{collections::BTreeMap, io, ops::DerefMut, sync::{Arc, RwLock}};
 
-pub trait Memory: Send + Sync {
-  fn write(&mut self, offset: usize, data: &[u8]);
-  fn read(&self, offset: usize, size: usize) -> &[u8];
+use thiserror::Error;
+
+use super::program::Program;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FaultKind {
+  OutOfBounds,
+  ReadOnly,
+  WriteOnly,
+  /// The file backing the memory could not be extended or synced
+  Io(io::ErrorKind)
 }
 
-impl<T> Memory for T where T : DerefMut<Target = [u8]> + Send + Sync {
+/// An access out of the bounds of a memory or not allowed by it
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+#[error(
+  "memory fault accessing {length} bytes at {address} of {}{}",
+  .unit.map_or("the process memory".into(), |unit| format!("the unit {}", unit)),
+  match .kind {
+    FaultKind::OutOfBounds => "".into(),
+    FaultKind::ReadOnly => ", it is read only".into(),
+    FaultKind::WriteOnly => ", it is write only".into(),
+    FaultKind::Io(kind) => format!(", the file backing it failed: {}", kind)
+  }
+)]
+pub struct MemoryFault {
+  /// The shared memory unit, `None` for the process memory
+  pub unit: Option<usize>,
+  pub address: usize,
+  pub length: usize,
+  pub kind: FaultKind
+}
+
+impl MemoryFault {
+  pub fn new(address: usize, length: usize) -> Self {
+    MemoryFault { unit: None, address, length, kind: FaultKind::OutOfBounds }
+  }
+
+  pub fn in_unit(self, unit: Option<usize>) -> Self {
+    MemoryFault { unit, ..self }
+  }
+
+  pub fn with_kind(self, kind: FaultKind) -> Self {
+    MemoryFault { kind, ..self }
+  }
+}
+
+/// A misuse of the heap of a process
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum HeapFault {
+  #[error("double free of the address {0}")]
+  DoubleFree(usize),
+  #[error("free of the address {0}, it is not an allocation")]
+  InvalidFree(usize),
+  #[error("allocation of {0} bytes, the heap can not grow past the address {HEAP_LIMIT}")]
+  OutOfMemory(usize)
+}
+
+/// A shared memory unit that can not be used
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum UnitFault {
+  #[error("there is no memory unit {0}")]
+  Unknown(usize),
+  #[error("unit of {0} bytes, larger than the limit of {UNIT_LIMIT}")]
+  TooLarge(usize)
+}
+
+/// Largest unit created by a process, like the heap limit for its own memory
+pub const UNIT_LIMIT: usize = 1 << 32;
+
+/// Alignment of the allocations, their sizes are rounded up to it
+pub const HEAP_ALIGN: usize = 8;
+
+/// End of the heap, the allocations not fitting below it are faults instead of growing the memory without bound
+pub const HEAP_LIMIT: usize = 1 << 32;
+
+/// Bytes written at once by the default [`Memory::try_fill`]
+const FILL_CHUNK: usize = 4096;
+
+/// The bytes in `[address, address + length)`
+pub fn bytes(memory: &[u8], address: usize, length: usize) -> Result<&[u8], MemoryFault> {
+  address.checked_add(length).and_then(|end| memory.get(address..end)).ok_or(MemoryFault::new(address, length))
+}
+
+/// The bytes in `[address, address + length)`
+pub fn bytes_mut(memory: &mut [u8], address: usize, length: usize) -> Result<&mut [u8], MemoryFault> {
+  address.checked_add(length).and_then(|end| memory.get_mut(address..end)).ok_or(MemoryFault::new(address, length))
+}
+
+pub trait Memory: Send + Sync {
+  fn size(&self) -> usize;
+  fn try_write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryFault>;
+  fn try_read(&self, offset: usize, size: usize) -> Result<&[u8], MemoryFault>;
+
+  /// Fill the buffer with the bytes at the offset, memories shared with other processes read them atomically
+  fn try_read_into(&self, offset: usize, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+    buffer.copy_from_slice(self.try_read(offset, buffer.len())?);
+    Ok(())
+  }
+
+  /// Set `length` bytes at the offset to the byte, the bounds are checked before writing any of them
+  fn try_fill(&mut self, offset: usize, length: usize, byte: u8) -> Result<(), MemoryFault> {
+    let end = offset.checked_add(length).filter(|&end| end <= self.size()).ok_or(MemoryFault::new(offset, length))?;
+    for start in (offset..end).step_by(FILL_CHUNK) {
+      self.try_write(start, &[byte; FILL_CHUNK][..FILL_CHUNK.min(end - start)])?;
+    }
+    Ok(())
+  }
+
+  /// Flush the writes to the file backing the memory, if any
+  fn sync(&self) -> io::Result<()> {
+    Ok(())
+  }
+
+  /// Like [`Memory::try_write`], panics on a fault
   fn write(&mut self, offset: usize, data: &[u8]) {
-    self[offset..(offset+data.len())].copy_from_slice(data)
+    self.try_write(offset, data).unwrap_or_else(|fault| panic!("{}", fault))
   }
 
+  /// Like [`Memory::try_read`], panics on a fault
   fn read(&self, offset: usize, size: usize) -> &[u8] {
-    &self[offset..(offset+size)]
+    self.try_read(offset, size).unwrap_or_else(|fault| panic!("{}", fault))
+  }
+}
+
+impl<T> Memory for T where T : DerefMut<Target = [u8]> + Send + Sync {
+  fn size(&self) -> usize {
+    self.len()
+  }
+
+  fn try_write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryFault> {
+    bytes_mut(self, offset, data.len())?.copy_from_slice(data);
+    Ok(())
+  }
+
+  fn try_read(&self, offset: usize, size: usize) -> Result<&[u8], MemoryFault> {
+    bytes(self, offset, size)
+  }
+
+  fn try_fill(&mut self, offset: usize, length: usize, byte: u8) -> Result<(), MemoryFault> {
+    bytes_mut(self, offset, length)?.fill(byte);
+    Ok(())
+  }
+}
+
+/// What can be done with a memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Access {
+  pub read: bool,
+  pub write: bool
+}
+
+impl Access {
+  pub const READ_WRITE: Access = Access { read: true, write: true };
+  pub const READ_ONLY: Access = Access { read: true, write: false };
+  pub const WRITE_ONLY: Access = Access { read: false, write: true };
+
+  /// Allowed by both
+  pub fn intersect(self, other: Access) -> Access {
+    Access { read: self.read && other.read, write: self.write && other.write }
+  }
+
+  fn check_read(self, address: usize, length: usize) -> Result<(), MemoryFault> {
+    match self.read {
+      true => Ok(()),
+      false => Err(MemoryFault::new(address, length).with_kind(FaultKind::WriteOnly))
+    }
+  }
+
+  fn check_write(self, address: usize, length: usize) -> Result<(), MemoryFault> {
+    match self.write {
+      true => Ok(()),
+      false => Err(MemoryFault::new(address, length).with_kind(FaultKind::ReadOnly))
+    }
+  }
+}
+
+impl Default for Access {
+  fn default() -> Self {
+    Access::READ_WRITE
+  }
+}
+
+/// A memory restricted to an access, like a unit mounted read only
+pub struct Restricted<'a> {
+  pub memory: &'a mut dyn Memory,
+  pub access: Access
+}
+
+impl<'a> Memory for Restricted<'a> {
+  fn size(&self) -> usize {
+    self.memory.size()
+  }
+
+  fn try_write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryFault> {
+    self.access.check_write(offset, data.len())?;
+    self.memory.try_write(offset, data)
+  }
+
+  fn try_read(&self, offset: usize, size: usize) -> Result<&[u8], MemoryFault> {
+    self.access.check_read(offset, size)?;
+    self.memory.try_read(offset, size)
+  }
+
+  fn try_read_into(&self, offset: usize, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+    self.access.check_read(offset, buffer.len())?;
+    self.memory.try_read_into(offset, buffer)
+  }
+
+  fn try_fill(&mut self, offset: usize, length: usize, byte: u8) -> Result<(), MemoryFault> {
+    self.access.check_write(offset, length)?;
+    self.memory.try_fill(offset, length, byte)
+  }
+
+  fn sync(&self) -> io::Result<()> {
+    self.memory.sync()
+  }
+}
+
+/// Allocations past the initial memory of a process, reusing the first free block that fits or growing the memory
+#[derive(Debug, Clone)]
+struct Heap {
+  /// End of the heap, the memory is at least this long
+  top: usize,
+  allocated: BTreeMap<usize, usize>,
+  /// Freed blocks, adjacent ones merged
+  free: BTreeMap<usize, usize>
+}
+
+impl Heap {
+  fn new(start: usize) -> Self {
+    Heap { top: start.next_multiple_of(HEAP_ALIGN), allocated: BTreeMap::new(), free: BTreeMap::new() }
+  }
+
+  fn alloc(&mut self, size: usize) -> Result<usize, HeapFault> {
+    let out_of_memory = HeapFault::OutOfMemory(size);
+    let size = size.max(1).checked_next_multiple_of(HEAP_ALIGN).filter(|&size| size <= HEAP_LIMIT).ok_or(out_of_memory)?;
+    let address = match self.free.iter().find(|(_, &free)| free >= size).map(|(&address, &free)| (address, free)) {
+      Some((address, free)) => {
+        self.free.remove(&address);
+        if free > size {
+          self.free.insert(address + size, free - size);
+        }
+        address
+      }
+      None => {
+        // a free block at the end grows instead of leaving a gap
+        let address = match self.free.last_key_value() {
+          Some((&address, &free)) if address + free == self.top => address,
+          _ => self.top
+        };
+        if address + size > HEAP_LIMIT {
+          return Err(out_of_memory)
+        }
+        self.free.remove(&address);
+        self.top = address + size;
+        address
+      }
+    };
+    self.allocated.insert(address, size);
+    Ok(address)
+  }
+
+  fn size_of(&self, address: usize) -> Result<usize, HeapFault> {
+    match self.allocated.get(&address) {
+      Some(&size) => Ok(size),
+      None => Err(match self.free.range(..=address).next_back() {
+        Some((&start, &size)) if address < start + size => HeapFault::DoubleFree(address),
+        _ => HeapFault::InvalidFree(address)
+      })
+    }
+  }
+
+  fn free(&mut self, address: usize) -> Result<(), HeapFault> {
+    let size = self.size_of(address)?;
+    self.allocated.remove(&address);
+    let (mut start, mut end) = (address, address + size);
+    if let Some(next) = self.free.remove(&end) {
+      end += next;
+    }
+    if let Some((&previous, &size)) = self.free.range(..start).next_back() {
+      if previous + size == start {
+        self.free.remove(&previous);
+        start = previous;
+      }
+    }
+    self.free.insert(start, end - start);
+    Ok(())
+  }
+}
+
+/// The memory of a process, initialized from its program, where the chunks of the static data listed in
+/// [`Program::static_data_meta`] are read only, the rest of the static data are variables of the front-ends
+///
+/// The heap starts after the initial memory and grows it on demand
+#[derive(Debug, Clone)]
+pub struct ProcessMemory {
+  bytes: Vec<u8>,
+  read_only: Vec<(usize, usize)>,
+  heap: Heap
+}
+
+impl ProcessMemory {
+  pub fn new(program: &Program) -> Self {
+    let mut read_only: Vec<_> = program.static_data_meta.iter().copied().filter(|&(_, size)| size > 0).collect();
+    read_only.sort_unstable();
+    let bytes = program.memory();
+    let heap = Heap::new(bytes.len());
+    ProcessMemory { bytes, read_only, heap }
+  }
+
+  /// A memory of the program with the bytes and the heap, as (top, allocations, free blocks), saved from another
+  pub fn restore(program: &Program, bytes: Vec<u8>, heap: (usize, BTreeMap<usize, usize>, BTreeMap<usize, usize>)) -> Self {
+    let (top, allocated, free) = heap;
+    let mut memory = ProcessMemory::new(program);
+    memory.bytes = bytes;
+    memory.heap = Heap { top, allocated, free };
+    memory
+  }
+
+  pub fn bytes(&self) -> &[u8] {
+    &self.bytes
+  }
+
+  /// The heap as (top, allocations, free blocks)
+  pub fn heap(&self) -> (usize, &BTreeMap<usize, usize>, &BTreeMap<usize, usize>) {
+    (self.heap.top, &self.heap.allocated, &self.heap.free)
+  }
+
+  /// The bytes, without the protection of the read only chunks
+  pub fn bytes_mut(&mut self) -> &mut [u8] {
+    &mut self.bytes
+  }
+
+  /// The range covering all the read only chunks, empty when there are none
+  pub fn read_only_span(&self) -> (usize, usize) {
+    match (self.read_only.first(), self.read_only.iter().map(|&(address, size)| address + size).max()) {
+      (Some(&(start, _)), Some(end)) => (start, end),
+      _ => (0, 0)
+    }
+  }
+
+  pub fn is_writable(&self, address: usize, length: usize) -> bool {
+    let end = address.saturating_add(length);
+    !self.read_only.iter().any(|&(chunk, size)| chunk < end && address < chunk + size)
+  }
+
+  /// Allocate at least `size` bytes, their content is not cleared when reused
+  pub fn alloc(&mut self, size: usize) -> Result<usize, HeapFault> {
+    let address = self.heap.alloc(size)?;
+    if self.heap.top > self.bytes.len() {
+      self.bytes.resize(self.heap.top, 0);
+    }
+    Ok(address)
+  }
+
+  pub fn free(&mut self, address: usize) -> Result<(), HeapFault> {
+    self.heap.free(address)
+  }
+
+  /// Move an allocation to one of `size` bytes keeping its content, the old address is freed
+  pub fn realloc(&mut self, address: usize, size: usize) -> Result<usize, HeapFault> {
+    let old = self.heap.size_of(address)?;
+    let moved = self.alloc(size)?;
+    self.bytes.copy_within(address..address + old.min(size), moved);
+    self.heap.free(address)?;
+    Ok(moved)
+  }
+
+  /// The allocations not freed, as (address, size)
+  pub fn allocations(&self) -> impl Iterator<Item = (usize, usize)> + '_ {
+    self.heap.allocated.iter().map(|(&address, &size)| (address, size))
+  }
+}
+
+impl Memory for ProcessMemory {
+  fn size(&self) -> usize {
+    self.bytes.len()
+  }
+
+  fn try_write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryFault> {
+    if !self.is_writable(offset, data.len()) {
+      return Err(MemoryFault::new(offset, data.len()).with_kind(FaultKind::ReadOnly))
+    }
+    bytes_mut(&mut self.bytes, offset, data.len())?.copy_from_slice(data);
+    Ok(())
+  }
+
+  fn try_read(&self, offset: usize, size: usize) -> Result<&[u8], MemoryFault> {
+    bytes(&self.bytes, offset, size)
+  }
+
+  fn try_fill(&mut self, offset: usize, length: usize, byte: u8) -> Result<(), MemoryFault> {
+    if !self.is_writable(offset, length) {
+      return Err(MemoryFault::new(offset, length).with_kind(FaultKind::ReadOnly))
+    }
+    bytes_mut(&mut self.bytes, offset, length)?.fill(byte);
+    Ok(())
   }
 }
 
 pub enum MemoryHandler<'a> {
   MemoryRef(&'a mut dyn Memory),
-  MemoryLock(Arc<RwLock<dyn Memory>>)
+  /// A shared memory unit, its index and the access allowed
+  MemoryLock(usize, Access, Arc<RwLock<dyn Memory>>)
 }
 
 impl<'a> MemoryHandler<'a> {
   pub fn memory<T>(&self, effect: impl FnOnce(&dyn Memory) -> T) -> T {
     match self {
       Self::MemoryRef(memory) => effect(*memory),
-      Self::MemoryLock(lock) => effect(& *lock.read().unwrap())
+      Self::MemoryLock(_, _, lock) => effect(& *lock.read().unwrap())
     }
   }
 
   pub fn memory_mut<T>(&mut self, effect: impl FnOnce(&mut dyn Memory) -> T) -> T {
     match self {
       Self::MemoryRef(memory) => effect(*memory),
-      Self::MemoryLock(lock) => effect(&mut *lock.write().unwrap())
+      Self::MemoryLock(_, _, lock) => effect(&mut *lock.write().unwrap())
+    }
+  }
+
+  /// The shared memory unit, `None` for the process memory
+  pub fn unit(&self) -> Option<usize> {
+    match self {
+      Self::MemoryRef(_) => None,
+      Self::MemoryLock(unit, _, _) => Some(*unit)
+    }
+  }
+
+  pub fn access(&self) -> Access {
+    match self {
+      Self::MemoryRef(_) => Access::READ_WRITE,
+      Self::MemoryLock(_, access, _) => *access
     }
   }
-}
\ No newline at end of file
+
+  /// Fill the buffer with the bytes at the address
+  pub fn try_read_into(&self, address: usize, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+    self.access().check_read(address, buffer.len())
+      .and_then(|_| self.memory(|memory| memory.try_read_into(address, buffer)))
+      .map_err(|fault| fault.in_unit(self.unit()))
+  }
+
+  /// Copy of the bytes at the address, out of bounds lengths fault before anything is copied
+  pub fn try_read(&self, address: usize, length: usize) -> Result<Vec<u8>, MemoryFault> {
+    self.access().check_read(address, length)
+      .and_then(|_| self.memory(|memory| memory.try_read(address, length).map(Vec::from)))
+      .map_err(|fault| fault.in_unit(self.unit()))
+  }
+
+  pub fn try_write(&mut self, address: usize, data: &[u8]) -> Result<(), MemoryFault> {
+    let unit = self.unit();
+    self.access().check_write(address, data.len())
+      .and_then(|_| self.memory_mut(|memory| memory.try_write(address, data)))
+      .map_err(|fault| fault.in_unit(unit))
+  }
+
+  pub fn try_fill(&mut self, address: usize, length: usize, byte: u8) -> Result<(), MemoryFault> {
+    let unit = self.unit();
+    self.access().check_write(address, length)
+      .and_then(|_| self.memory_mut(|memory| memory.try_fill(address, length, byte)))
+      .map_err(|fault| fault.in_unit(unit))
+  }
+}
@@ -4,4 +4,11 @@ pub mod process;
 pub mod instruction;
 pub mod memory;
 pub mod machine;
-pub mod ffi;
\ No newline at end of file
+pub mod mapped;
+#[cfg(unix)]
+pub mod shm;
+pub mod checkpoint;
+pub mod ffi;
+pub mod optimizer;
+#[cfg(feature = "jit")]
+pub mod jit;
\ No newline at end of file
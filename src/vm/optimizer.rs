@@ -0,0 +1,332 @@
+//! Optimization passes over the instructions of a [`Program`]
+//!
+//! The passes are repeated until nothing changes:
+//! - fusion: a `Push` of a single value is moved into the empty operand of the next instruction
+//! - constant folding: arithmetic, comparisons and casts with constant operands become a `Push`
+//! - jump threading: jumps to unconditional jumps go to the final target, jumps to the next instruction disappear
+//! - dead code: unreachable instructions and `Noop` are removed
+//!
+//! Every time instructions are removed the targets of `Jump` and `Fork` and the operands in
+//! [`Program::code_references`] are rewritten. Programs with jumps to a computed address only get the passes changing
+//! instructions in place, constant folding and jump threading, as any integer could be the address of an instruction.
+//! Programs with unit names still to resolve in [`Program::unit_references`] are left untouched
+
+use std::collections::HashSet;
+
+use super::program::{Instruction, InstructionParam, Opcode, Program};
+
+/// Optimize the program in place, keeping its behaviour
+pub fn optimize(program: &mut Program) {
+  if !program.unit_references.is_empty() {
+    return
+  }
+  let mut optimizer = Optimizer::new(program);
+  if optimizer.has_dynamic_jumps() {
+    while optimizer.fold() | optimizer.thread() {}
+  } else {
+    while optimizer.fuse() | optimizer.fold() | optimizer.thread() | optimizer.remove_dead() {}
+  }
+  let mut references: Vec<_> = optimizer.references.into_iter().collect();
+  references.sort();
+  program.code_references = references;
+}
+
+struct Optimizer<'a> {
+  instructions: &'a mut Vec<Instruction>,
+  references: HashSet<(usize, usize)>
+}
+
+/// Opcodes taking both operands, the first one from the top of the stack
+fn takes_both(opcode: Opcode) -> bool {
+  use Opcode::*;
+  matches!(opcode,
+    Add | Sub | Mul | Div | Gt | Ls | Gteq | Lseq | Eq | Noteq | Swap | Over | SetReg |
+    WriteInt64 | WriteInt32 | WriteInt16 | WriteInt8 | WriteFloat64 | WriteFloat32 |
+    WriteInt64BE | WriteInt32BE | WriteInt16BE | WriteFloat64BE | WriteFloat32BE |
+    MemCopy | MemFill | MemCompare | MemFind | Realloc | Jump | PrepareInvoke | FastInvoke
+  )
+}
+
+/// Opcodes taking only the first operand
+fn takes_first(opcode: Opcode) -> bool {
+  use Opcode::*;
+  matches!(opcode,
+    Int | Float | Reg | ReadInt64 | ReadInt32 | ReadInt16 | ReadInt8 | ReadFloat64 | ReadFloat32 |
+    ReadUInt32 | ReadUInt16 | ReadUInt8 | ReadInt64BE | ReadInt32BE | ReadUInt32BE | ReadInt16BE | ReadUInt16BE |
+    ReadFloat64BE | ReadFloat32BE |
+    Mount | MountReadOnly | CreateUnit | DestroyUnit | Alloc | Free | Fork | ThreadSleep
+  )
+}
+
+/// The immediate target of a jump or a fork
+fn target(instruction: &Instruction) -> Option<usize> {
+  match instruction {
+    Instruction(Opcode::Jump | Opcode::Fork, Some(InstructionParam::Int(pc)), _) => Some(*pc as usize),
+    _ => None
+  }
+}
+
+fn is_target(instruction: &Instruction, operand: usize) -> bool {
+  operand == 0 && target(instruction).is_some()
+}
+
+fn is_unconditional(instruction: &Instruction) -> bool {
+  matches!(instruction, Instruction(Opcode::Jump, _, Some(InstructionParam::Int(cond))) if *cond != 0)
+}
+
+/// The value pushed by a push of a single value and the operand holding it
+fn single_push(instruction: &Instruction) -> Option<(InstructionParam, usize)> {
+  match instruction {
+    Instruction(Opcode::Push, Some(value), None) => Some((*value, 0)),
+    Instruction(Opcode::Push, None, Some(value)) => Some((*value, 1)),
+    _ => None
+  }
+}
+
+fn fold_arithmetic(opcode: Opcode, a: InstructionParam, b: InstructionParam) -> Option<InstructionParam> {
+  use InstructionParam::{Float, Int};
+  let value = match (opcode, a, b) {
+    (Opcode::Add, Int(x), Int(y)) => Int(x.checked_add(y)?),
+    (Opcode::Sub, Int(x), Int(y)) => Int(x.checked_sub(y)?),
+    (Opcode::Mul, Int(x), Int(y)) => Int(x.checked_mul(y)?),
+    (Opcode::Div, Int(x), Int(y)) => Int(x.checked_div(y)?),
+    (Opcode::Add, Float(x), Float(y)) => Float(x + y),
+    (Opcode::Sub, Float(x), Float(y)) => Float(x - y),
+    (Opcode::Mul, Float(x), Float(y)) => Float(x * y),
+    (Opcode::Div, Float(x), Float(y)) => Float(x / y),
+    (opcode, Int(x), Int(y)) => Int(compare(opcode, x, y)? as i64),
+    (opcode, Float(x), Float(y)) => Int(compare(opcode, x, y)? as i64),
+    _ => return None
+  };
+  Some(value)
+}
+
+fn compare<T: PartialOrd>(opcode: Opcode, a: T, b: T) -> Option<bool> {
+  Some(match opcode {
+    Opcode::Gt => a > b,
+    Opcode::Ls => a < b,
+    Opcode::Gteq => a >= b,
+    Opcode::Lseq => a <= b,
+    Opcode::Eq => a == b,
+    Opcode::Noteq => a != b,
+    _ => return None
+  })
+}
+
+impl<'a> Optimizer<'a> {
+  fn new(program: &'a mut Program) -> Self {
+    let instructions = &mut program.instructions;
+    // the targets of jumps and forks are rewritten anyway
+    let references = program.code_references.iter().copied()
+      .filter(|&(idx, operand)| instructions.get(idx).is_some_and(|instruction| !is_target(instruction, operand)))
+      .collect();
+    Optimizer { instructions, references }
+  }
+
+  fn has_dynamic_jumps(&self) -> bool {
+    self.instructions.iter().any(|instruction| matches!(instruction, Instruction(Opcode::Jump | Opcode::Fork, None, _)))
+  }
+
+  fn is_reference(&self, idx: usize, operand: usize) -> bool {
+    self.references.contains(&(idx, operand))
+  }
+
+  /// Instructions that can be reached from a place other than the previous instruction
+  fn leaders(&self) -> Vec<bool> {
+    let mut leaders = vec![false; self.instructions.len() + 1];
+    let targets = self.instructions.iter().filter_map(target);
+    let referenced = self.references.iter().filter_map(|&(idx, operand)| self.referenced(idx, operand));
+    for pc in targets.chain(referenced) {
+      if let Some(leader) = leaders.get_mut(pc) {
+        *leader = true;
+      }
+    }
+    leaders
+  }
+
+  fn referenced(&self, idx: usize, operand: usize) -> Option<usize> {
+    let instruction = &self.instructions[idx];
+    match if operand == 0 { instruction.1 } else { instruction.2 } {
+      Some(InstructionParam::Int(pc)) => Some(pc as usize),
+      _ => None
+    }
+  }
+
+  /// Move the reference of an operand into another, it is dropped when it becomes a jump target
+  fn move_reference(&mut self, from: (usize, usize), to: (usize, usize)) {
+    if self.references.remove(&from) && !is_target(&self.instructions[to.0], to.1) {
+      self.references.insert(to);
+    }
+  }
+
+  fn fuse(&mut self) -> bool {
+    let leaders = self.leaders();
+    let mut removed = vec![false; self.instructions.len()];
+    // backwards so a chain of pushes ends in the instruction using them
+    for idx in (0..self.instructions.len().saturating_sub(1)).rev() {
+      let next = idx + 1;
+      let Some((value, source)) = single_push(&self.instructions[idx]) else { continue };
+      if leaders[next] || removed[next] {
+        continue
+      }
+      let Instruction(opcode, first, second) = self.instructions[next];
+      let operand = match (opcode, first, second) {
+        (Opcode::Discard, _, _) => {
+          self.references.remove(&(idx, source));
+          removed[next] = true;
+          None
+        }
+        (Opcode::Clone, _, _) => {
+          self.instructions[next] = Instruction(Opcode::Push, Some(value), Some(value));
+          if self.is_reference(idx, source) {
+            self.references.insert((next, 0));
+          }
+          Some(1)
+        }
+        (Opcode::Push, Some(pushed), None) | (Opcode::Push, None, Some(pushed)) => {
+          if self.references.remove(&(next, if first.is_some() { 0 } else { 1 })) {
+            self.references.insert((next, 1));
+          }
+          self.instructions[next] = Instruction(Opcode::Push, Some(value), Some(pushed));
+          Some(0)
+        }
+        (opcode, None, None) if takes_both(opcode) || takes_first(opcode) => {
+          self.instructions[next].1 = Some(value);
+          Some(0)
+        }
+        (opcode, None, Some(_)) if takes_both(opcode) || takes_first(opcode) => {
+          self.instructions[next].1 = Some(value);
+          Some(0)
+        }
+        (opcode, Some(_), None) if takes_both(opcode) => {
+          self.instructions[next].2 = Some(value);
+          Some(1)
+        }
+        _ => continue
+      };
+      if let Some(operand) = operand {
+        self.move_reference((idx, source), (next, operand));
+      }
+      removed[idx] = true;
+    }
+    self.compact(removed)
+  }
+
+  fn fold(&mut self) -> bool {
+    let mut changed = false;
+    for idx in 0..self.instructions.len() {
+      let Instruction(opcode, first, second) = self.instructions[idx];
+      if self.is_reference(idx, 0) || self.is_reference(idx, 1) {
+        continue
+      }
+      let value = match (opcode, first, second) {
+        (Opcode::Int, Some(InstructionParam::Int(x)), None) => InstructionParam::Int(x),
+        (Opcode::Int, Some(InstructionParam::Float(x)), None) => InstructionParam::Int(x as i64),
+        (Opcode::Float, Some(InstructionParam::Int(x)), None) => InstructionParam::Float(x as f64),
+        (Opcode::Float, Some(InstructionParam::Float(x)), None) => InstructionParam::Float(x),
+        (opcode, Some(a), Some(b)) => match fold_arithmetic(opcode, a, b) {
+          Some(value) => value,
+          None => continue
+        }
+        _ => continue
+      };
+      self.instructions[idx] = Instruction(Opcode::Push, Some(value), None);
+      changed = true;
+    }
+    changed
+  }
+
+  fn thread(&mut self) -> bool {
+    let mut changed = false;
+    let len = self.instructions.len();
+    for idx in 0..len {
+      let instruction = self.instructions[idx];
+      let Instruction(Opcode::Jump, Some(InstructionParam::Int(_)), cond) = instruction else { continue };
+      let mut pc = target(&instruction).unwrap();
+      // follow the chain of unconditional jumps, a loop of them is left as it is
+      let mut steps = 0;
+      while pc < len && is_unconditional(&self.instructions[pc]) && steps < len {
+        match target(&self.instructions[pc]) {
+          Some(next) if next != pc => pc = next,
+          _ => break
+        }
+        steps += 1;
+      }
+
+      let replacement = match cond {
+        Some(InstructionParam::Int(0)) => Instruction::new(Opcode::Noop),
+        None if pc == idx + 1 => Instruction::new(Opcode::Discard),
+        Some(InstructionParam::Int(_)) if pc == idx + 1 => Instruction::new(Opcode::Noop),
+        _ if is_unconditional(&instruction) && (pc >= len || matches!(self.instructions[pc].0, Opcode::Exit)) =>
+          Instruction::new(Opcode::Exit),
+        _ if pc != target(&instruction).unwrap() => Instruction(Opcode::Jump, Some(InstructionParam::Int(pc as i64)), cond),
+        _ => continue
+      };
+      if !matches!(replacement.0, Opcode::Jump) {
+        self.references.remove(&(idx, 1));
+      }
+      self.instructions[idx] = replacement;
+      changed = true;
+    }
+    changed
+  }
+
+  fn remove_dead(&mut self) -> bool {
+    let len = self.instructions.len();
+    let mut reachable = vec![false; len];
+    let mut pending = vec![0];
+    pending.extend(self.references.iter().filter_map(|&(idx, operand)| self.referenced(idx, operand)));
+    while let Some(pc) = pending.pop() {
+      if pc >= len || reachable[pc] {
+        continue
+      }
+      reachable[pc] = true;
+      let instruction = &self.instructions[pc];
+      pending.extend(target(instruction));
+      if !matches!(instruction.0, Opcode::Exit) && !is_unconditional(instruction) {
+        pending.push(pc + 1);
+      }
+    }
+
+    let removed = self.instructions.iter().zip(reachable)
+      .map(|(instruction, reachable)| !reachable || matches!(instruction.0, Opcode::Noop))
+      .collect();
+    self.compact(removed)
+  }
+
+  /// Drop the removed instructions, the jumps to them go to the next kept instruction
+  fn compact(&mut self, removed: Vec<bool>) -> bool {
+    if !removed.contains(&true) {
+      return false
+    }
+    let len = self.instructions.len();
+    let mut map = Vec::with_capacity(len + 1);
+    let mut kept = 0;
+    for &removed in removed.iter() {
+      map.push(kept);
+      if !removed {
+        kept += 1;
+      }
+    }
+    map.push(kept);
+    let relocate = |param: &mut Option<InstructionParam>| if let Some(InstructionParam::Int(pc)) = param {
+      *pc = map[(*pc as usize).min(len)] as i64;
+    };
+
+    for idx in 0..len {
+      if target(&self.instructions[idx]).is_some() {
+        relocate(&mut self.instructions[idx].1);
+      }
+    }
+    let references: Vec<_> = self.references.drain().filter(|&(idx, _)| !removed[idx]).collect();
+    for &(idx, operand) in references.iter() {
+      let instruction = &mut self.instructions[idx];
+      relocate(if operand == 0 { &mut instruction.1 } else { &mut instruction.2 });
+    }
+    self.references = references.into_iter().map(|(idx, operand)| (map[idx], operand)).collect();
+
+    let mut removed = removed.into_iter();
+    self.instructions.retain(|_| !removed.next().unwrap());
+    true
+  }
+}
@@ -1,6 +1,12 @@
 use std::{thread, time::Duration};
 
-use super::{instruction::ProcessInstruction, memory::MemoryHandler, program::{Instruction, Opcode, Program}, stack::{Stack, StackValue}};
+use thiserror::Error;
+
+use super::{
+  instruction::{self, BinaryOp, DecodedInstruction, ProcessInstruction},
+  memory::{Access, FaultKind, HeapFault, Memory, MemoryFault, MemoryHandler, ProcessMemory, UnitFault},
+  program::{Instruction, Opcode, Program}, stack::{Stack, StackValue}
+};
 
 macro_rules! same_type_op {
   ($a: ident $op: tt $b: ident) => {
@@ -20,23 +26,31 @@ macro_rules! same_type_op {
   };
 }
 
+/// Memory access of a value, little endian unless the byte order methods are given
 macro_rules! mem {
   ($supervisor: ident msg_type($msg_type_name: tt) write($stack_value: path => $cast: ty)) => {
+    mem!($supervisor msg_type($msg_type_name) write($stack_value => $cast) to_le_bytes)
+  };
+
+  ($supervisor: ident read($stack_value: path, $read_type: ty => $cast: ty)) => {
+    mem!($supervisor read($stack_value, $read_type => $cast) from_le_bytes)
+  };
+
+  ($supervisor: ident msg_type($msg_type_name: tt) write($stack_value: path => $cast: ty) $to_bytes: ident) => {
     match arg!(both) {
-      (StackValue::Int(address), $stack_value(value)) =>
-        $supervisor.get_memory().memory_mut(|memory| memory.write(address as usize, &(value as $cast).to_le_bytes())),
+      (StackValue::Int(address), $stack_value(value)) => check!($supervisor.get_memory()
+        .try_write(address as usize, &(value as $cast).$to_bytes())),
       _ => panic!(concat!("expecting: address :: int, value :: ", stringify!($msg_type_name)))
     }
   };
 
-  ($supervisor: ident read($stack_value: path, $read_type: ty => $cast: ty)) => {
+  ($supervisor: ident read($stack_value: path, $read_type: ty => $cast: ty) $from_bytes: ident) => {
     match arg!(first) {
-      StackValue::Int(address) =>
-        $stack_value(
-          $supervisor.get_memory().memory(|memory| <$read_type>::from_le_bytes(
-            memory.read(address as usize, std::mem::size_of::<$read_type>()).try_into().unwrap()
-          )) as $cast
-        ),
+      StackValue::Int(address) => {
+        let mut bytes = [0; std::mem::size_of::<$read_type>()];
+        check!($supervisor.get_memory().try_read_into(address as usize, &mut bytes));
+        $stack_value(<$read_type>::$from_bytes(bytes) as $cast)
+      }
       _ => panic!("expecting: address :: int")
     }
   };
@@ -44,10 +58,93 @@ macro_rules! mem {
 
 pub trait ProcesSupervisor {
   fn get_pid(&self) -> usize;
-  fn set_memory(&mut self, unit: Option<usize>);
-  fn get_memory(&mut self) -> MemoryHandler;
+  /// Set a shared unit as the active memory, restricting its access further, or the process memory with `None`
+  fn set_memory(&mut self, unit: Option<usize>, access: Access) -> Result<(), UnitFault>;
+  fn get_memory(&mut self) -> MemoryHandler<'_>;
+  /// Add a zeroed shared memory unit, returns its index
+  fn create_unit(&mut self, size: usize) -> Result<usize, UnitFault>;
+  /// Remove a shared memory unit, the processes with it mounted keep it until they unmount it
+  fn destroy_unit(&mut self, unit: usize) -> Result<(), UnitFault>;
   fn fork(&self, process: Process);
-  fn invoke_ffi(&mut self, symbol: &[u8], process: &mut Process) -> Option<StackValue>;
+  /// Save the process with its memory, the pc already points to the instruction to resume at
+  fn checkpoint(&mut self, process: &Process);
+  /// Call the function, a trap returns the fault of the instructions it ran
+  fn invoke_ffi(&mut self, symbol: &[u8], process: &mut Process) -> Result<Option<StackValue>, ProcessFault>;
+
+  /// The process memory with `None` or a shared unit, whichever is active
+  fn unit_memory(&mut self, unit: Option<usize>) -> Result<MemoryHandler<'_>, UnitFault>;
+  /// The memory of the process, even when a shared unit is active, where the heap lives
+  fn process_memory(&mut self) -> &mut ProcessMemory;
+
+  /// The active memory when it is the process memory, accessed directly instead of through [`MemoryHandler`]
+  fn local_memory(&mut self) -> Option<&mut ProcessMemory> {
+    None
+  }
+
+  /// Answer the requests pending for the process between its instructions, false when it must stop
+  fn poll(&mut self, _process: &Process) -> bool {
+    true
+  }
+}
+
+/// A fault of the instruction a process runs, it ends that process while the others keep running
+#[derive(Debug, Error, Clone, Copy, PartialEq, Eq)]
+pub enum ProcessFault {
+  #[error("{0}")]
+  Memory(#[from] MemoryFault),
+  #[error("{0}")]
+  Heap(#[from] HeapFault),
+  #[error("{0}")]
+  Unit(#[from] UnitFault)
+}
+
+/// The memory selected by a unit operand, `-1` for the process memory
+fn selected_unit(value: StackValue) -> Option<Option<usize>> {
+  match value {
+    StackValue::Int(-1) => Some(None),
+    StackValue::Int(unit) if unit >= 0 => Some(Some(unit as usize)),
+    _ => None
+  }
+}
+
+fn binary(op: BinaryOp, a: StackValue, b: StackValue) -> StackValue {
+  match op {
+    BinaryOp::Add => same_type_op!(a + b),
+    BinaryOp::Sub => same_type_op!(a - b),
+    BinaryOp::Mul => same_type_op!(a * b),
+    BinaryOp::Div => same_type_op!(a / b),
+    BinaryOp::Gt => same_type_op!((StackValue::Int => i64) a > b),
+    BinaryOp::Ls => same_type_op!((StackValue::Int => i64) a < b),
+    BinaryOp::Gteq => same_type_op!((StackValue::Int => i64) a >= b),
+    BinaryOp::Lseq => same_type_op!((StackValue::Int => i64) a <= b),
+    BinaryOp::Eq => same_type_op!((StackValue::Int => i64) a == b),
+    BinaryOp::Noteq => same_type_op!((StackValue::Int => i64) a != b)
+  }
+}
+
+fn read_int64<S: ProcesSupervisor + ?Sized>(supervisor: &mut S, address: StackValue) -> Result<StackValue, MemoryFault> {
+  let StackValue::Int(address) = address else { panic!("expecting: address :: int") };
+  let address = address as usize;
+  let mut bytes = [0; 8];
+  let read = match supervisor.local_memory() {
+    Some(memory) => memory.try_read(address, 8).map(|memory| bytes.copy_from_slice(memory)),
+    None => supervisor.get_memory().try_read_into(address, &mut bytes)
+  };
+  read?;
+  Ok(StackValue::Int(i64::from_le_bytes(bytes)))
+}
+
+fn write_int64<S: ProcesSupervisor + ?Sized>(
+  supervisor: &mut S, address: StackValue, value: StackValue
+) -> Result<(), MemoryFault> {
+  let (StackValue::Int(address), StackValue::Int(value)) = (address, value) else {
+    panic!("expecting: address :: int, value :: int")
+  };
+  let address = address as usize;
+  match supervisor.local_memory() {
+    Some(memory) => memory.try_write(address, &value.to_le_bytes()),
+    None => supervisor.get_memory().try_write(address, &value.to_le_bytes())
+  }
 }
 
 pub const PUBLIC_REGISTERS_COUNT: usize = 10;
@@ -64,7 +161,7 @@ pub type ProcessRegisters = [StackValue; PROCESS_REGISTERS_COUNT];
 #[derive(Clone)]
 pub struct Process {
   pub program: Program,
-  pub instructions: Vec<ProcessInstruction>,
+  pub instructions: Vec<DecodedInstruction>,
   pub pc: usize,
   pub stack: Stack,
   pub registers: ProcessRegisters,
@@ -73,7 +170,7 @@ pub struct Process {
 
 impl Process {
   pub fn new(program: Program) -> Self {
-    let instructions = program.instructions.iter().map(|x| (*x).into()).collect();
+    let instructions = instruction::decode(&program.instructions);
 
     Process {
       program,
@@ -107,24 +204,132 @@ impl Process {
     self.program.instructions.get(self.pc)
   }
 
-  pub fn run_next(&mut self, supervisor: &mut dyn ProcesSupervisor) -> bool {
-    if let Some(&instruction) = self.instructions.get(self.pc) {
-      self.pc += 1;
-      self.run_instruction(supervisor, instruction);
-      true
-    } else {
-      false
-    }
+  /// Run the instruction at the pc, false once the process is finished, a fault leaves the pc on the faulting instruction
+  pub fn run_next<S: ProcesSupervisor + ?Sized>(&mut self, supervisor: &mut S) -> Result<bool, ProcessFault> {
+    let Some(&instruction) = self.instructions.get(self.pc) else { return Ok(false) };
+    self.pc += 1;
+    self.run_decoded(supervisor, instruction)?;
+    Ok(true)
+  }
+
+  pub fn run_until_finish<S: ProcesSupervisor + ?Sized>(&mut self, supervisor: &mut S) -> Result<(), ProcessFault> {
+    while self.run_next(supervisor)? {}
+    Ok(())
   }
 
-  pub fn run_until_finish(&mut self, supervisor: &mut dyn ProcesSupervisor) {
-    while let Some(&instruction) = self.instructions.get(self.pc) {
-      self.pc += 1;
-      self.run_instruction(supervisor, instruction)
+  /// Run a decoded instruction, the pc already points to the next one
+  #[inline(always)]
+  pub fn run_decoded<S: ProcesSupervisor + ?Sized>(
+    &mut self, supervisor: &mut S, instruction: DecodedInstruction
+  ) -> Result<(), ProcessFault> {
+    // the faulting instruction is the last one of a superinstruction, right before the pc
+    macro_rules! check {
+      ($result: expr) => {
+        match $result {
+          Ok(value) => value,
+          Err(fault) => {
+            self.pc -= 1;
+            return Err(fault.into())
+          }
+        }
+      };
+    }
+
+    macro_rules! pop {
+      () => {
+        self.stack.pop().expect("expecting argument on the stack")
+      };
     }
+
+    match instruction {
+      DecodedInstruction::Generic(instruction) => self.run_instruction(supervisor, instruction)?,
+      DecodedInstruction::Noop => (),
+      DecodedInstruction::Push(a) => self.stack.push(a),
+      DecodedInstruction::Push2(a, b) => {
+        self.stack.push(a);
+        self.stack.push(b);
+      }
+      DecodedInstruction::Discard => { self.stack.pop(); }
+      DecodedInstruction::Clone => if let Some(item) = self.stack.peek() { self.stack.push(item) }
+      DecodedInstruction::Swap => {
+        let (a, b) = self.stack.pop2().expect("expecting arguments 1 & 2 on the stack");
+        self.stack.push(a);
+        self.stack.push(b);
+      }
+      DecodedInstruction::Over => {
+        let (a, b) = self.stack.pop2().expect("expecting arguments 1 & 2 on the stack");
+        self.stack.push(b);
+        self.stack.push(a);
+        self.stack.push(b);
+      }
+      DecodedInstruction::Binary(op) => {
+        let (a, b) = self.stack.pop2().expect("expecting arguments 1 & 2 on the stack");
+        self.stack.push(binary(op, a, b));
+      }
+      DecodedInstruction::BinaryRight(op, b) => {
+        let a = pop!();
+        self.stack.push(binary(op, a, b));
+      }
+      DecodedInstruction::Reg(reg) => self.stack.push(self.registers[reg]),
+      DecodedInstruction::SetReg(reg) => self.registers[reg] = pop!(),
+      DecodedInstruction::ReadInt64 => {
+        let address = pop!();
+        self.stack.push(check!(read_int64(supervisor, address)));
+      }
+      DecodedInstruction::WriteInt64 => {
+        let (address, value) = self.stack.pop2().expect("expecting arguments 1 & 2 on the stack");
+        check!(write_int64(supervisor, address, value));
+      }
+      DecodedInstruction::Goto(pc) => self.pc = pc,
+      DecodedInstruction::JumpIf(pc) => match pop!() {
+        StackValue::Int(0) => (),
+        StackValue::Int(_) => self.pc = pc,
+        _ => panic!("expecting: pc :: int, cond :: int")
+      }
+      DecodedInstruction::Exit => self.pc = self.instructions.len(),
+
+      DecodedInstruction::RegOffset(reg, k) => {
+        self.pc += 1;
+        self.stack.push(binary(BinaryOp::Add, self.registers[reg], StackValue::Int(k)));
+      }
+      DecodedInstruction::ReadOffset(reg, k) => {
+        self.pc += 2;
+        let address = binary(BinaryOp::Add, self.registers[reg], StackValue::Int(k));
+        self.stack.push(check!(read_int64(supervisor, address)));
+      }
+      DecodedInstruction::WriteOffset(reg, k) => {
+        self.pc += 2;
+        let address = binary(BinaryOp::Add, self.registers[reg], StackValue::Int(k));
+        let value = pop!();
+        check!(write_int64(supervisor, address, value));
+      }
+      DecodedInstruction::JumpIfEqual(b, pc) => {
+        let a = pop!();
+        self.pc = match binary(BinaryOp::Eq, a, b) {
+          StackValue::Int(0) => self.pc + 1,
+          _ => pc
+        };
+      }
+    }
+    Ok(())
   }
 
-  pub fn run_instruction(&mut self, supervisor: &mut dyn ProcesSupervisor, instruction: ProcessInstruction) {
+  pub fn run_instruction<S: ProcesSupervisor + ?Sized>(
+    &mut self, supervisor: &mut S, instruction: ProcessInstruction
+  ) -> Result<(), ProcessFault> {
+    // a fault leaves the pc on this instruction, a trap already left it on the instruction it faulted on
+    macro_rules! check {
+      ($result: expr) => {
+        match $result {
+          Ok(value) => value,
+          Err(fault) => {
+            self.pc -= 1;
+            return Err(fault.into())
+          }
+        }
+      };
+    }
+
     macro_rules! expect_arg_stack {
       (both) => {
         self.stack.pop2().expect(concat!("expecting arguments 1 & 2 on line ", line!()))
@@ -275,11 +480,146 @@ impl Process {
         self.stack.push(value);
       }
 
+      Opcode::ReadUInt32 => {
+        let value = mem!(supervisor read(StackValue::Int, u32 => i64));
+        self.stack.push(value);
+      }
+      Opcode::ReadUInt16 => {
+        let value = mem!(supervisor read(StackValue::Int, u16 => i64));
+        self.stack.push(value);
+      }
+      Opcode::ReadUInt8 => {
+        let value = mem!(supervisor read(StackValue::Int, u8 => i64));
+        self.stack.push(value);
+      }
+
+      Opcode::WriteInt64BE => mem!(supervisor msg_type(int) write(StackValue::Int => i64) to_be_bytes),
+      Opcode::ReadInt64BE => {
+        let value = mem!(supervisor read(StackValue::Int, i64 => i64) from_be_bytes);
+        self.stack.push(value);
+      }
+      Opcode::WriteInt32BE => mem!(supervisor msg_type(int) write(StackValue::Int => i32) to_be_bytes),
+      Opcode::ReadInt32BE => {
+        let value = mem!(supervisor read(StackValue::Int, i32 => i64) from_be_bytes);
+        self.stack.push(value);
+      }
+      Opcode::ReadUInt32BE => {
+        let value = mem!(supervisor read(StackValue::Int, u32 => i64) from_be_bytes);
+        self.stack.push(value);
+      }
+      Opcode::WriteInt16BE => mem!(supervisor msg_type(int) write(StackValue::Int => i16) to_be_bytes),
+      Opcode::ReadInt16BE => {
+        let value = mem!(supervisor read(StackValue::Int, i16 => i64) from_be_bytes);
+        self.stack.push(value);
+      }
+      Opcode::ReadUInt16BE => {
+        let value = mem!(supervisor read(StackValue::Int, u16 => i64) from_be_bytes);
+        self.stack.push(value);
+      }
+      Opcode::WriteFloat64BE => mem!(supervisor msg_type(float) write(StackValue::Float => f64) to_be_bytes),
+      Opcode::ReadFloat64BE => {
+        let value = mem!(supervisor read(StackValue::Float, f64 => f64) from_be_bytes);
+        self.stack.push(value);
+      }
+      Opcode::WriteFloat32BE => mem!(supervisor msg_type(float) write(StackValue::Float => f32) to_be_bytes),
+      Opcode::ReadFloat32BE => {
+        let value = mem!(supervisor read(StackValue::Float, f32 => f64) from_be_bytes);
+        self.stack.push(value);
+      }
+
       Opcode::Mount => match arg!(first) {
-        StackValue::Int(unit) if unit >= 0 => supervisor.set_memory(Some(unit as usize)),
+        StackValue::Int(unit) if unit >= 0 => check!(supervisor.set_memory(Some(unit as usize), Access::READ_WRITE)),
+        _ => panic!("expecting: unit :: int >= 0")
+      }
+      Opcode::MountReadOnly => match arg!(first) {
+        StackValue::Int(unit) if unit >= 0 => check!(supervisor.set_memory(Some(unit as usize), Access::READ_ONLY)),
+        _ => panic!("expecting: unit :: int >= 0")
+      }
+      Opcode::Unmount => check!(supervisor.set_memory(None, Access::READ_WRITE)),
+      Opcode::Msync => {
+        let memory = supervisor.get_memory();
+        let synced = memory.memory(|memory| memory.sync().map_err(|err| (memory.size(), err.kind())));
+        check!(synced.map_err(|(size, kind)| MemoryFault::new(0, size).with_kind(FaultKind::Io(kind)).in_unit(memory.unit())))
+      }
+      Opcode::Checkpoint => supervisor.checkpoint(self),
+      Opcode::CreateUnit => match arg!(first) {
+        StackValue::Int(size) if size >= 0 => {
+          let unit = check!(supervisor.create_unit(size as usize));
+          self.stack.push(StackValue::Int(unit as i64))
+        }
+        _ => panic!("expecting: size :: int >= 0")
+      }
+      Opcode::DestroyUnit => match arg!(first) {
+        StackValue::Int(unit) if unit >= 0 => check!(supervisor.destroy_unit(unit as usize)),
         _ => panic!("expecting: unit :: int >= 0")
       }
-      Opcode::Unmount => supervisor.set_memory(None),
+
+      Opcode::MemCopy => {
+        let (unit, address) = arg!(both);
+        let (source, from, length) = (expect_arg_stack!(3), expect_arg_stack!(4), expect_arg_stack!(5));
+        match (selected_unit(unit), address, selected_unit(source), from, length) {
+          (Some(unit), StackValue::Int(address), Some(source), StackValue::Int(from), StackValue::Int(length)) if length >= 0 => {
+            let bytes = check!(check!(supervisor.unit_memory(source)).try_read(from as usize, length as usize));
+            let mut target = check!(supervisor.unit_memory(unit));
+            check!(target.try_write(address as usize, &bytes))
+          }
+          _ => panic!("expecting: unit :: int >= -1, address :: int, source :: int >= -1, from :: int, length :: int >= 0")
+        }
+      }
+      Opcode::MemFill => {
+        let (unit, address) = arg!(both);
+        let (byte, length) = (expect_arg_stack!(3), expect_arg_stack!(4));
+        match (selected_unit(unit), address, byte, length) {
+          (Some(unit), StackValue::Int(address), StackValue::Int(byte), StackValue::Int(length)) if length >= 0 => {
+            let mut target = check!(supervisor.unit_memory(unit));
+            check!(target.try_fill(address as usize, length as usize, byte as u8))
+          }
+          _ => panic!("expecting: unit :: int >= -1, address :: int, byte :: int, length :: int >= 0")
+        }
+      }
+      Opcode::MemCompare => {
+        let (unit, address) = arg!(both);
+        let (other_unit, other, length) = (expect_arg_stack!(3), expect_arg_stack!(4), expect_arg_stack!(5));
+        match (selected_unit(unit), address, selected_unit(other_unit), other, length) {
+          (Some(unit), StackValue::Int(address), Some(other_unit), StackValue::Int(other), StackValue::Int(length)) if length >= 0 => {
+            let bytes = check!(check!(supervisor.unit_memory(unit)).try_read(address as usize, length as usize));
+            let other = check!(check!(supervisor.unit_memory(other_unit)).try_read(other as usize, length as usize));
+            self.stack.push(StackValue::Int(bytes.cmp(&other) as i64))
+          }
+          _ => panic!("expecting: unit :: int >= -1, address :: int, other_unit :: int >= -1, other :: int, length :: int >= 0")
+        }
+      }
+      Opcode::MemFind => {
+        let (unit, address) = arg!(both);
+        let (length, byte) = (expect_arg_stack!(3), expect_arg_stack!(4));
+        match (selected_unit(unit), address, length, byte) {
+          (Some(unit), StackValue::Int(address), StackValue::Int(length), StackValue::Int(byte)) if length >= 0 => {
+            let bytes = check!(check!(supervisor.unit_memory(unit)).try_read(address as usize, length as usize));
+            let offset = bytes.iter().position(|&x| x == byte as u8).map_or(-1, |offset| offset as i64);
+            self.stack.push(StackValue::Int(offset))
+          }
+          _ => panic!("expecting: unit :: int >= -1, address :: int, length :: int >= 0, byte :: int")
+        }
+      }
+
+      Opcode::Alloc => match arg!(first) {
+        StackValue::Int(size) if size >= 0 => {
+          let address = check!(supervisor.process_memory().alloc(size as usize));
+          self.stack.push(StackValue::Int(address as i64))
+        }
+        _ => panic!("expecting: size :: int >= 0")
+      }
+      Opcode::Free => match arg!(first) {
+        StackValue::Int(address) => check!(supervisor.process_memory().free(address as usize)),
+        _ => panic!("expecting: address :: int")
+      }
+      Opcode::Realloc => match arg!(both) {
+        (StackValue::Int(address), StackValue::Int(size)) if size >= 0 => {
+          let address = check!(supervisor.process_memory().realloc(address as usize, size as usize));
+          self.stack.push(StackValue::Int(address as i64))
+        }
+        _ => panic!("expecting: address :: int, size :: int >= 0")
+      }
       
       Opcode::Jump => match arg!(both) {
         (StackValue::Int(pc), StackValue::Int(cond)) => if cond != 0 {
@@ -303,23 +643,21 @@ impl Process {
 
       Opcode::PrepareInvoke => match arg!(both) {
         (StackValue::Int(address), StackValue::Int(size)) => {
-          self.invoke_target = supervisor.get_memory()
-            .memory(|memory| memory.read(address as usize, size as usize).into());
+          self.invoke_target = check!(supervisor.get_memory().try_read(address as usize, size as usize));
         },
         _ => panic!("expecting: address :: int, size :: int")
       },
       Opcode::Invoke => {
         let invoke_target = self.invoke_target.clone();
-        if let Some(value) = supervisor.invoke_ffi(&invoke_target, self) {
+        if let Some(value) = supervisor.invoke_ffi(&invoke_target, self)? {
           self.stack.push(value);
         }
       },
       Opcode::FastInvoke => match arg!(both) {
         (StackValue::Int(address), StackValue::Int(size)) => {
-          let invoke_target: Vec<_> = supervisor.get_memory()
-            .memory(|memory| memory.read(address as usize, size as usize).into());
+          let invoke_target = check!(supervisor.get_memory().try_read(address as usize, size as usize));
           self.invoke_target = invoke_target.clone();
-          if let Some(value) = supervisor.invoke_ffi(&invoke_target, self) {
+          if let Some(value) = supervisor.invoke_ffi(&invoke_target, self)? {
             self.stack.push(value);
           }
         },
@@ -328,6 +666,7 @@ impl Process {
 
       Opcode::Pid => self.stack.push(StackValue::Int(supervisor.get_pid() as i64))
     };
+    Ok(())
   }
 }
 
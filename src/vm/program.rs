@@ -1,67 +1,176 @@
 use std::fmt::Display;
 
-use strum_macros::{Display, EnumString};
+use thiserror::Error;
+use strum_macros::{Display, EnumMessage, EnumString, VariantNames};
 
-#[derive(Clone, Debug, Copy, EnumString, Display)]
+/// The message of every opcode is its stack effect (top of the stack on the right) and the detailed message a description
+///
+/// Arguments are named after the operands, a missing first operand is taken from the top of the stack and then the second one
+#[derive(Clone, Debug, Copy, PartialEq, Eq, EnumString, Display, VariantNames, EnumMessage)]
 pub enum Opcode {
-  Noop, // does nothing
-  Debug, // print the stack
+  #[strum(message = "=>", detailed_message = "does nothing")]
+  Noop,
+  #[strum(message = "=>", detailed_message = "print the stack")]
+  Debug,
 
-  Add, // +
-  Sub, // -
-  Mul, // *
-  Div, // /
+  #[strum(message = "b a => a + b", detailed_message = "add two values of the same type")]
+  Add,
+  #[strum(message = "b a => a - b", detailed_message = "subtract two values of the same type")]
+  Sub,
+  #[strum(message = "b a => a * b", detailed_message = "multiply two values of the same type")]
+  Mul,
+  #[strum(message = "b a => a / b", detailed_message = "divide two values of the same type")]
+  Div,
 
-  Gt, // >
-  Ls, // <
-  Gteq, // >=
-  Lseq, // <=
-  Eq, // ==
-  Noteq, // !=
+  #[strum(message = "b a => a > b", detailed_message = "1 if a is greater than b, otherwise 0")]
+  Gt,
+  #[strum(message = "b a => a < b", detailed_message = "1 if a is less than b, otherwise 0")]
+  Ls,
+  #[strum(message = "b a => a >= b", detailed_message = "1 if a is greater than or equal to b, otherwise 0")]
+  Gteq,
+  #[strum(message = "b a => a <= b", detailed_message = "1 if a is less than or equal to b, otherwise 0")]
+  Lseq,
+  #[strum(message = "b a => a == b", detailed_message = "1 if a is equal to b, otherwise 0")]
+  Eq,
+  #[strum(message = "b a => a != b", detailed_message = "1 if a is not equal to b, otherwise 0")]
+  Noteq,
 
-  Int, // any => int
-  Float, // any => float
+  #[strum(message = "a => int", detailed_message = "convert any value into int")]
+  Int,
+  #[strum(message = "a => float", detailed_message = "convert any value into float")]
+  Float,
 
-  Discard, // a =>
-  Clone, // a => a a
-  Push, // => a
-  Swap, // a b => b a
-  Over, // a b => a b a
+  #[strum(message = "a =>", detailed_message = "discard the top of the stack")]
+  Discard,
+  #[strum(message = "a => a a", detailed_message = "clone the top of the stack")]
+  Clone,
+  #[strum(message = "=> a b", detailed_message = "push the operands")]
+  Push,
+  #[strum(message = "b a => a b", detailed_message = "swap the two items on top of the stack")]
+  Swap,
+  #[strum(message = "b a => b a b", detailed_message = "copy the second item over the top of the stack")]
+  Over,
 
-  Reg, // i; reg[i]
-  SetReg, // i a; reg[i] = a
+  #[strum(message = "i => reg[i]", detailed_message = "push the value of a register")]
+  Reg,
+  #[strum(message = "a i =>", detailed_message = "reg[i] = a")]
+  SetReg,
 
+  #[strum(message = "value address =>", detailed_message = "write an int as 64 bits in the active memory")]
   WriteInt64,
+  #[strum(message = "address => int", detailed_message = "read 64 bits from the active memory as int")]
   ReadInt64,
 
+  #[strum(message = "value address =>", detailed_message = "write an int as 32 bits in the active memory")]
   WriteInt32,
+  #[strum(message = "address => int", detailed_message = "read 32 bits from the active memory as int")]
   ReadInt32,
-  
+
+  #[strum(message = "value address =>", detailed_message = "write an int as 16 bits in the active memory")]
   WriteInt16,
+  #[strum(message = "address => int", detailed_message = "read 16 bits from the active memory as int")]
   ReadInt16,
-  
+
+  #[strum(message = "value address =>", detailed_message = "write an int as 8 bits in the active memory")]
   WriteInt8,
+  #[strum(message = "address => int", detailed_message = "read 8 bits from the active memory as int")]
   ReadInt8,
 
+  #[strum(message = "value address =>", detailed_message = "write a float as 64 bits in the active memory")]
   WriteFloat64,
+  #[strum(message = "address => float", detailed_message = "read 64 bits from the active memory as float")]
   ReadFloat64,
 
+  #[strum(message = "value address =>", detailed_message = "write a float as 32 bits in the active memory")]
   WriteFloat32,
+  #[strum(message = "address => float", detailed_message = "read 32 bits from the active memory as float")]
   ReadFloat32,
 
-  Mount, // set the shared memory as active memory
-  Unmount, // set the process memory as active memory
+  #[strum(message = "address => int", detailed_message = "read 32 bits from the active memory as unsigned int")]
+  ReadUInt32,
+  #[strum(message = "address => int", detailed_message = "read 16 bits from the active memory as unsigned int")]
+  ReadUInt16,
+  #[strum(message = "address => int", detailed_message = "read 8 bits from the active memory as unsigned int")]
+  ReadUInt8,
+
+  #[strum(message = "value address =>", detailed_message = "write an int as 64 bits big endian in the active memory")]
+  WriteInt64BE,
+  #[strum(message = "address => int", detailed_message = "read 64 bits big endian from the active memory as int")]
+  ReadInt64BE,
+
+  #[strum(message = "value address =>", detailed_message = "write an int as 32 bits big endian in the active memory")]
+  WriteInt32BE,
+  #[strum(message = "address => int", detailed_message = "read 32 bits big endian from the active memory as int")]
+  ReadInt32BE,
+  #[strum(message = "address => int", detailed_message = "read 32 bits big endian from the active memory as unsigned int")]
+  ReadUInt32BE,
+
+  #[strum(message = "value address =>", detailed_message = "write an int as 16 bits big endian in the active memory")]
+  WriteInt16BE,
+  #[strum(message = "address => int", detailed_message = "read 16 bits big endian from the active memory as int")]
+  ReadInt16BE,
+  #[strum(message = "address => int", detailed_message = "read 16 bits big endian from the active memory as unsigned int")]
+  ReadUInt16BE,
+
+  #[strum(message = "value address =>", detailed_message = "write a float as 64 bits big endian in the active memory")]
+  WriteFloat64BE,
+  #[strum(message = "address => float", detailed_message = "read 64 bits big endian from the active memory as float")]
+  ReadFloat64BE,
+
+  #[strum(message = "value address =>", detailed_message = "write a float as 32 bits big endian in the active memory")]
+  WriteFloat32BE,
+  #[strum(message = "address => float", detailed_message = "read 32 bits big endian from the active memory as float")]
+  ReadFloat32BE,
+
+  #[strum(message = "unit =>", detailed_message = "set the shared memory as active memory")]
+  Mount,
+  #[strum(message = "unit =>", detailed_message = "set the shared memory as active memory, writes to it fault")]
+  MountReadOnly,
+  #[strum(message = "=>", detailed_message = "set the process memory as active memory")]
+  Unmount,
+  #[strum(message = "=>", detailed_message = "flush the writes to the active memory to the file mapped as it")]
+  Msync,
+  #[strum(message = "size => unit", detailed_message = "create a zeroed shared memory of size bytes")]
+  CreateUnit,
+  #[strum(message = "unit =>", detailed_message = "destroy a shared memory, the processes with it mounted keep it until unmounting")]
+  DestroyUnit,
+  #[strum(message = "=>", detailed_message = "save the process with its memory into the checkpoint directory of the machine, if any")]
+  Checkpoint,
+
+  #[strum(message = "length from source address unit =>", detailed_message = "copy length bytes from the source unit to the unit, -1 is the process memory")]
+  MemCopy,
+  #[strum(message = "length byte address unit =>", detailed_message = "fill length bytes of the unit with the byte, -1 is the process memory")]
+  MemFill,
+  #[strum(message = "length other other_unit address unit => order", detailed_message = "-1, 0 or 1 as length bytes of the unit are less, equal or greater than the other ones")]
+  MemCompare,
+  #[strum(message = "byte length address unit => offset", detailed_message = "offset of the first byte equal to byte in length bytes of the unit, -1 if there is none")]
+  MemFind,
+
+  #[strum(message = "size => address", detailed_message = "allocate size bytes in the process memory, growing it when needed")]
+  Alloc,
+  #[strum(message = "address =>", detailed_message = "free an allocation of the process memory")]
+  Free,
+  #[strum(message = "size address => address", detailed_message = "move an allocation to one of size bytes, keeping its content")]
+  Realloc,
 
-  Jump, // a b; pc = a if b != 0
-  Fork, // a; spawn a clone process with pc = a
-  Exit, // pc = last instruction + 1
-  ThreadSleep, // a; sleeps the current thread 'a' milliseconds
+  #[strum(message = "cond pc =>", detailed_message = "jump to the instruction pc if cond != 0")]
+  Jump,
+  #[strum(message = "pc =>", detailed_message = "spawn a clone process starting in the instruction pc")]
+  Fork,
+  #[strum(message = "=>", detailed_message = "finish the process")]
+  Exit,
+  #[strum(message = "millis =>", detailed_message = "sleep the current thread")]
+  ThreadSleep,
 
-  PrepareInvoke, // a b; invoke_target = [a..(a + b)]
-  Invoke, // invoke invoke_target
-  FastInvoke, // a b; PrepareInvoke + Invoke
-  
-  Pid // push the process id onto the stack
+  #[strum(message = "size address =>", detailed_message = "set the ffi function to invoke, its name is in the active memory")]
+  PrepareInvoke,
+  #[strum(message = "=> result?", detailed_message = "invoke the prepared ffi function")]
+  Invoke,
+  #[strum(message = "size address => result?", detailed_message = "PrepareInvoke + Invoke")]
+  FastInvoke,
+
+  #[strum(message = "=> pid", detailed_message = "push the process id onto the stack")]
+  Pid
 }
 
 #[derive(Clone, Debug, Copy)]
@@ -106,15 +215,33 @@ impl Display for Instruction {
 
 const DEFAULT_PROGRAM_MEMORY: usize = 1024;
 
+/// Names of shared memory units used by a program but not configured
+#[derive(Debug, Error)]
+#[error("{program} uses memory units that are not configured: {}", .names.iter().map(|name| format!("%{}", name)).collect::<Vec<_>>().join(", "))]
+pub struct UnknownUnitsError {
+  pub program: String,
+  pub names: Vec<String>
+}
+
 #[derive(Debug, Clone)]
 pub struct Program {
   pub name: String,
   pub instructions: Vec<Instruction>,
   pub static_data: Vec<u8>,
   pub static_data_meta: Vec<(usize, usize)>,
+  /// Operands holding the index of an instruction as (instruction, operand), used to rewrite them when the code moves
+  pub code_references: Vec<(usize, usize)>,
+  /// Operands naming a shared memory unit as (instruction, operand, name), resolved when the program is launched
+  pub unit_references: Vec<(usize, usize, String)>,
   pub required_memory: usize
 }
 
+impl Default for Program {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Program {
   pub fn new() -> Program {
     Program {
@@ -122,6 +249,8 @@ impl Program {
       instructions: Vec::new(),
       static_data: Vec::new(),
       static_data_meta: Vec::new(),
+      code_references: Vec::new(),
+      unit_references: Vec::new(),
       required_memory: DEFAULT_PROGRAM_MEMORY
     }
   }
@@ -132,6 +261,26 @@ impl Program {
     program
   }
 
+  /// Replace the names of the shared memory units in the operands by their index
+  pub fn resolve_units(&mut self, unit: impl Fn(&str) -> Option<usize>) -> Result<(), UnknownUnitsError> {
+    let mut names = vec![];
+    for (idx, operand, name) in self.unit_references.iter() {
+      let Some(index) = unit(name) else {
+        if !names.contains(name) {
+          names.push(name.clone());
+        }
+        continue
+      };
+      let Instruction(_, first, second) = &mut self.instructions[*idx];
+      *(if *operand == 0 { first } else { second }) = Some(InstructionParam::Int(index as i64));
+    }
+    if !names.is_empty() {
+      return Err(UnknownUnitsError { program: self.name.clone(), names })
+    }
+    self.unit_references.clear();
+    Ok(())
+  }
+
   pub fn memory(&self) -> Vec<u8> {
     let mut memory = self.static_data.clone();
     if memory.len() < self.required_memory {
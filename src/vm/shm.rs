@@ -0,0 +1,167 @@
+//! Shared memory units backed by POSIX shared memory objects (`shm_open`), kept in `/dev/shm` on Linux
+//!
+//! Separate avmir instances opening the same name share the memory without touching the disk: the first one creates
+//! the object and the rest attach to it, growing it when they ask for more. A header before the memory counts the
+//! instances attached and the last one to detach removes the object, an instance ending abruptly leaves it behind.
+//!
+//! Reads and writes of 1, 2, 4 or 8 aligned bytes are atomic, a concurrent access from another instance never sees
+//! a value half written. Any other access, like an unaligned value, the bulk opcodes or the bytes of an invoke target,
+//! is a plain copy that can mix bytes written before and after a concurrent write.
+//!
+//! The size of the memory is fixed when an instance attaches, an instance attaching later with a larger size grows
+//! the object but the instances already attached keep their mapping and never see the bytes past it
+
+use std::{
+  ffi::CString, fs::File, io,
+  os::fd::{AsRawFd, FromRawFd},
+  sync::atomic::{AtomicU16, AtomicU32, AtomicU64, AtomicU8, Ordering}
+};
+
+use memmap2::{MmapMut, MmapOptions};
+use thiserror::Error;
+
+use super::memory::{bytes, bytes_mut, Memory, MemoryFault};
+
+/// Bytes before the memory, the count of instances attached is at the start
+const HEADER: usize = 64;
+
+#[derive(Debug, Error)]
+pub enum SharedMemoryError {
+  #[error("invalid shared memory name {0}, expecting /name")]
+  InvalidName(String),
+
+  #[error("shared memory {0}: {1}")]
+  Io(String, io::Error)
+}
+
+/// An exclusive lock of the object while the instances attach and detach
+struct Lock<'a>(&'a File);
+
+impl<'a> Lock<'a> {
+  fn new(file: &'a File) -> io::Result<Self> {
+    match unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX) } {
+      0 => Ok(Lock(file)),
+      _ => Err(io::Error::last_os_error())
+    }
+  }
+}
+
+impl Drop for Lock<'_> {
+  fn drop(&mut self) {
+    unsafe { libc::flock(self.0.as_raw_fd(), libc::LOCK_UN) };
+  }
+}
+
+pub struct SharedMemory {
+  name: CString,
+  file: File,
+  map: MmapMut
+}
+
+impl SharedMemory {
+  /// Create the object or attach to it, the memory is at least `size` bytes or the size of the object
+  pub fn open(name: &str, size: usize) -> Result<SharedMemory, SharedMemoryError> {
+    let name = match name.strip_prefix('/') {
+      Some(rest) if !rest.is_empty() && !rest.contains('/') => name.to_string(),
+      None if !name.is_empty() && !name.contains('/') => format!("/{}", name),
+      _ => return Err(SharedMemoryError::InvalidName(name.into()))
+    };
+    let c_name = CString::new(name.as_str()).map_err(|_| SharedMemoryError::InvalidName(name.clone()))?;
+    Self::attach(&c_name, size).map(|(file, map)| SharedMemory { name: c_name, file, map })
+      .map_err(|err| SharedMemoryError::Io(name, err))
+  }
+
+  fn attach(name: &CString, size: usize) -> io::Result<(File, MmapMut)> {
+    loop {
+      let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR | libc::O_CREAT, 0o600 as libc::mode_t) };
+      if fd < 0 {
+        return Err(io::Error::last_os_error())
+      }
+      let file = unsafe { File::from_raw_fd(fd) };
+      let lock = Lock::new(&file)?;
+      let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+      if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+        return Err(io::Error::last_os_error())
+      }
+      if stat.st_nlink == 0 {
+        continue // removed by the last instance detaching meanwhile
+      }
+
+      // growing keeps the mappings of the instances already attached
+      let len = (stat.st_size as u64).max((HEADER + size) as u64);
+      if (stat.st_size as u64) < len {
+        file.set_len(len)?;
+      }
+      let map = unsafe { MmapOptions::new().len(len as usize).map_mut(&file)? };
+      users(&map).fetch_add(1, Ordering::SeqCst);
+      drop(lock);
+      return Ok((file, map))
+    }
+  }
+
+  /// The name of the object, starting with `/`
+  pub fn name(&self) -> &str {
+    self.name.to_str().unwrap()
+  }
+}
+
+fn users(map: &MmapMut) -> &AtomicU64 {
+  // SAFETY: the mapping is page aligned and at least HEADER bytes
+  unsafe { AtomicU64::from_ptr(map.as_ptr() as *mut u64) }
+}
+
+impl Drop for SharedMemory {
+  fn drop(&mut self) {
+    let _lock = Lock::new(&self.file);
+    if users(&self.map).fetch_sub(1, Ordering::SeqCst) == 1 {
+      unsafe { libc::shm_unlink(self.name.as_ptr()) };
+    }
+  }
+}
+
+macro_rules! atomic_access {
+  ($bytes: ident, $len: expr, $aligned: ident, $copy: expr) => {
+    match $len {
+      1 => $aligned!(AtomicU8, u8),
+      2 if $bytes.as_ptr() as usize % 2 == 0 => $aligned!(AtomicU16, u16),
+      4 if $bytes.as_ptr() as usize % 4 == 0 => $aligned!(AtomicU32, u32),
+      8 if $bytes.as_ptr() as usize % 8 == 0 => $aligned!(AtomicU64, u64),
+      _ => $copy
+    }
+  };
+}
+
+impl Memory for SharedMemory {
+  fn size(&self) -> usize {
+    self.map.len() - HEADER
+  }
+
+  fn try_write(&mut self, offset: usize, data: &[u8]) -> Result<(), MemoryFault> {
+    let target = bytes_mut(&mut self.map[HEADER..], offset, data.len())?;
+    macro_rules! store {
+      ($atomic: ty, $int: ty) => {
+        // SAFETY: aligned for the size, inside the mapping
+        unsafe { <$atomic>::from_ptr(target.as_mut_ptr() as *mut $int) }
+          .store(<$int>::from_ne_bytes(data.try_into().unwrap()), Ordering::Release)
+      };
+    }
+    atomic_access!(target, data.len(), store, target.copy_from_slice(data));
+    Ok(())
+  }
+
+  fn try_read(&self, offset: usize, size: usize) -> Result<&[u8], MemoryFault> {
+    bytes(&self.map[HEADER..], offset, size)
+  }
+
+  fn try_read_into(&self, offset: usize, buffer: &mut [u8]) -> Result<(), MemoryFault> {
+    let source = bytes(&self.map[HEADER..], offset, buffer.len())?;
+    macro_rules! load {
+      ($atomic: ty, $int: ty) => {
+        // SAFETY: aligned for the size, inside the mapping
+        buffer.copy_from_slice(&unsafe { <$atomic>::from_ptr(source.as_ptr() as *mut $int) }.load(Ordering::Acquire).to_ne_bytes())
+      };
+    }
+    atomic_access!(source, buffer.len(), load, buffer.copy_from_slice(source));
+    Ok(())
+  }
+}
@@ -3,7 +3,7 @@ use core::fmt;
 use super::program::InstructionParam;
 
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq)]
 pub enum StackValue {
   Int(i64),
   Float(f64)
@@ -29,11 +29,11 @@ impl fmt::Display for StackValue {
 
 macro_rules! stack_value_cast_into {
   ($t: ty) => {
-    impl Into<$t> for StackValue {
-      fn into(self) -> $t {
-        match self {
-          Self::Int(x) => x as $t,
-          Self::Float(x) => x as $t,
+    impl From<StackValue> for $t {
+      fn from(value: StackValue) -> $t {
+        match value {
+          StackValue::Int(x) => x as $t,
+          StackValue::Float(x) => x as $t,
         }
       }
     }
@@ -44,9 +44,11 @@ stack_value_cast_into!(usize);
 stack_value_cast_into!(i64);
 stack_value_cast_into!(f64);
 
+pub const STACK_SIZE: usize = 32;
+
 #[derive(Copy, Clone)]
 pub struct Stack {
-  items: [StackValue; 32],
+  items: [StackValue; STACK_SIZE],
   offset: u8
 }
 
@@ -56,10 +58,16 @@ impl fmt::Debug for Stack {
   }
 }
 
+impl Default for Stack {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
 impl Stack {
   pub fn new() -> Self {
     Stack {
-      items: [StackValue::Int(0); 32],
+      items: [StackValue::Int(0); STACK_SIZE],
       offset: 0, 
     }
   }
@@ -90,8 +98,13 @@ impl Stack {
     }
   }
 
+  /// The items from the bottom to the top of the stack
+  pub fn as_slice(&self) -> &[StackValue] {
+    &self.items[..self.offset as usize]
+  }
+
   pub fn push(&mut self, value: StackValue) {
-    if self.offset == 32 {
+    if self.offset as usize == STACK_SIZE {
       panic!("stack overflow")
     }
     self.items[self.offset as usize] = value;
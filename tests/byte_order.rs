@@ -0,0 +1,129 @@
+//! The byte order opcodes write the exact bytes of the value and read it back, the unsigned reads zero extend
+
+use avmir::{
+  parser::{v2::Simple, Parser},
+  vm::{
+    memory::{Access, MemoryHandler, ProcessMemory, UnitFault}, process::{ProcesSupervisor, Process, ProcessFault},
+    program::Program, stack::StackValue
+  }
+};
+
+/// A process alone with its memory
+struct Alone {
+  memory: ProcessMemory
+}
+
+impl ProcesSupervisor for Alone {
+  fn get_pid(&self) -> usize {
+    0
+  }
+
+  fn set_memory(&mut self, _: Option<usize>, _: Access) -> Result<(), UnitFault> {
+    unimplemented!()
+  }
+
+  fn get_memory(&mut self) -> MemoryHandler<'_> {
+    MemoryHandler::MemoryRef(&mut self.memory)
+  }
+
+  fn create_unit(&mut self, _: usize) -> Result<usize, UnitFault> {
+    unimplemented!()
+  }
+
+  fn destroy_unit(&mut self, _: usize) -> Result<(), UnitFault> {
+    unimplemented!()
+  }
+
+  fn fork(&self, _: Process) {
+    unimplemented!()
+  }
+
+  fn checkpoint(&mut self, _: &Process) {}
+
+  fn invoke_ffi(&mut self, _: &[u8], _: &mut Process) -> Result<Option<StackValue>, ProcessFault> {
+    unimplemented!()
+  }
+
+  fn unit_memory(&mut self, unit: Option<usize>) -> Result<MemoryHandler<'_>, UnitFault> {
+    assert_eq!(unit, None);
+    Ok(MemoryHandler::MemoryRef(&mut self.memory))
+  }
+
+  fn process_memory(&mut self) -> &mut ProcessMemory {
+    &mut self.memory
+  }
+}
+
+/// Write the value at 0 and read it back with each opcode, the bytes written and the values read
+fn run(write: &str, value: &str, reads: &[&str]) -> (Vec<u8>, Vec<StackValue>) {
+  let mut source = format!("{} 0 {}\n", write, value);
+  reads.iter().for_each(|read| source.push_str(&format!("{} 0\n", read)));
+  let mut program = Program::new();
+  Simple::parse(&mut program, &source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+  let mut supervisor = Alone { memory: ProcessMemory::new(&program) };
+  let mut process = Process::new(program);
+  process.run_until_finish(&mut supervisor).unwrap();
+  (supervisor.memory.bytes()[..8].to_vec(), process.stack.as_slice().to_vec())
+}
+
+fn int(value: i64) -> StackValue {
+  StackValue::Int(value)
+}
+
+#[test]
+fn little_endian_integers() {
+  assert_eq!(
+    run("WriteInt64", "-2", &["ReadInt64", "ReadUInt32", "ReadUInt16", "ReadUInt8"]),
+    (vec![0xfe, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff], vec![int(-2), int(0xffff_fffe), int(0xfffe), int(0xfe)])
+  );
+  assert_eq!(
+    run("WriteInt32", "305419896", &["ReadInt32", "ReadUInt32", "ReadInt16", "ReadUInt8"]),
+    (vec![0x78, 0x56, 0x34, 0x12, 0, 0, 0, 0], vec![int(305419896), int(305419896), int(0x5678), int(0x78)])
+  );
+  assert_eq!(
+    run("WriteInt16", "-3", &["ReadInt16", "ReadUInt16", "ReadInt8", "ReadUInt8"]),
+    (vec![0xfd, 0xff, 0, 0, 0, 0, 0, 0], vec![int(-3), int(0xfffd), int(-3), int(0xfd)])
+  );
+  assert_eq!(
+    run("WriteInt8", "-128", &["ReadInt8", "ReadUInt8", "ReadUInt16"]),
+    (vec![0x80, 0, 0, 0, 0, 0, 0, 0], vec![int(-128), int(0x80), int(0x80)])
+  );
+}
+
+#[test]
+fn big_endian_integers() {
+  assert_eq!(
+    run("WriteInt64BE", "258", &["ReadInt64BE", "ReadInt64"]),
+    (vec![0, 0, 0, 0, 0, 0, 1, 2], vec![int(258), int(0x0201_0000_0000_0000)])
+  );
+  assert_eq!(
+    run("WriteInt64BE", "-2", &["ReadInt64BE", "ReadUInt32BE", "ReadUInt16BE"]),
+    (vec![0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xfe], vec![int(-2), int(0xffff_ffff), int(0xffff)])
+  );
+  assert_eq!(
+    run("WriteInt32BE", "305419896", &["ReadInt32BE", "ReadUInt32BE", "ReadInt32", "ReadUInt16BE"]),
+    (vec![0x12, 0x34, 0x56, 0x78, 0, 0, 0, 0], vec![int(305419896), int(305419896), int(0x7856_3412), int(0x1234)])
+  );
+  assert_eq!(
+    run("WriteInt32BE", "-4", &["ReadInt32BE", "ReadUInt32BE"]),
+    (vec![0xff, 0xff, 0xff, 0xfc, 0, 0, 0, 0], vec![int(-4), int(0xffff_fffc)])
+  );
+  assert_eq!(
+    run("WriteInt16BE", "-3", &["ReadInt16BE", "ReadUInt16BE", "ReadUInt16"]),
+    (vec![0xff, 0xfd, 0, 0, 0, 0, 0, 0], vec![int(-3), int(0xfffd), int(0xfdff)])
+  );
+}
+
+#[test]
+fn floats_in_both_orders() {
+  let (bytes, values) = run("WriteFloat64BE", "1.5", &["ReadFloat64BE"]);
+  assert_eq!((bytes, values), (1.5f64.to_be_bytes().to_vec(), vec![StackValue::Float(1.5)]));
+  let (bytes, values) = run("WriteFloat64", "1.5", &["ReadFloat64"]);
+  assert_eq!((bytes, values), (1.5f64.to_le_bytes().to_vec(), vec![StackValue::Float(1.5)]));
+  let (bytes, values) = run("WriteFloat32BE", "-0.25", &["ReadFloat32BE", "ReadFloat32"]);
+  assert_eq!(bytes[..4], (-0.25f32).to_be_bytes());
+  assert_eq!(values[0], StackValue::Float(-0.25));
+  assert_eq!(values[1], StackValue::Float(f32::from_le_bytes((-0.25f32).to_be_bytes()) as f64));
+  let (bytes, values) = run("WriteFloat32", "-0.25", &["ReadFloat32"]);
+  assert_eq!((&bytes[..4], values), (&(-0.25f32).to_le_bytes()[..], vec![StackValue::Float(-0.25)]));
+}
@@ -0,0 +1,58 @@
+//! The programs translated to C print the same as in the interpreter, built with the system C compiler (`cc`)
+
+mod common;
+
+use std::{env, fs, process::Command};
+
+use common::{interpret, stdout, AVMIR, PROGRAMS};
+
+fn has_compiler() -> bool {
+  Command::new("cc").arg("--version").output().is_ok_and(|output| output.status.success())
+}
+
+fn translate_and_run(program: &str, optimize: bool) -> String {
+  let dir = env::temp_dir().join(format!("avmir_c_backend_{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let name = format!("{}{}", program.replace(['/', '.'], "_"), if optimize { "_optimized" } else { "" });
+  let (source, binary) = (dir.join(format!("{}.c", name)), dir.join(name));
+
+  let mut translate = Command::new(AVMIR);
+  translate.args(["translate", program, "-l", "avmir_std", "-o"]).arg(&source);
+  if optimize {
+    translate.arg("-O");
+  }
+  stdout(&mut translate);
+  stdout(Command::new("cc").args(["-O1", "-Wall", "-Werror", "-o"]).arg(&binary).arg(&source).args(["-ldl", "-lpthread"]));
+  stdout(&mut Command::new(binary))
+}
+
+fn compare(optimize: bool) {
+  if !has_compiler() {
+    eprintln!("skipped, there is no C compiler");
+    return
+  }
+  for program in PROGRAMS {
+    assert_eq!(translate_and_run(program, optimize), interpret(program, if optimize { &["-O"] } else { &[] }), "output of {}", program);
+  }
+}
+
+#[test]
+fn same_output_as_the_interpreter() {
+  compare(false)
+}
+
+#[test]
+fn same_output_as_the_interpreter_optimized() {
+  compare(true)
+}
+
+#[test]
+fn unit_names_are_refused() {
+  let output = Command::new(AVMIR).args(["translate", "tests/units/named.txt"]).output().unwrap();
+  assert!(!output.status.success());
+  assert_eq!(
+    String::from_utf8(output.stderr).unwrap(),
+    "tests/units/named.txt uses memory units that are not configured: %second, %first, \
+    the translated programs do not support memory units\n"
+  );
+}
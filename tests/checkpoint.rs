@@ -0,0 +1,58 @@
+//! The checkpoints of a resumed process replace its own file, not the one of the process that got its pid, a failed
+//! checkpoint is reported without ending the process and a checkpoint with an impossible heap is corrupted
+
+use std::{env, fs};
+
+use avmir::{parser::v2::Simple, vm::{checkpoint::{Checkpoint, CheckpointError}, machine::MachineBuilder}};
+
+#[test]
+fn resumed_process_keeps_its_checkpoint() {
+  let dir = env::temp_dir().join(format!("avmir_checkpoint_{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let mut machine = MachineBuilder::new().checkpoint_dir(&dir).build();
+  machine.launch_source::<Simple>("first", "Checkpoint\n").unwrap().join().unwrap();
+  machine.launch_source::<Simple>("second", "Checkpoint\n").unwrap().join().unwrap();
+
+  // resumed alone and back on the instruction saving it, it is the first process of the new machine
+  let path = dir.join("1.checkpoint");
+  let mut checkpoint = Checkpoint::load(&path).unwrap();
+  fs::remove_file(&path).unwrap();
+  assert_eq!(checkpoint.id, 1);
+  checkpoint.process.pc = 0;
+  let mut machine = MachineBuilder::new().checkpoint_dir(&dir).build();
+  let resumed = machine.resume(checkpoint).unwrap();
+  assert_ne!(resumed.pid(), 1);
+  resumed.join().unwrap();
+
+  let first = Checkpoint::load(dir.join("0.checkpoint")).unwrap();
+  assert_eq!((first.id, first.process.program.name.as_str()), (0, "first"));
+  let second = Checkpoint::load(&path).unwrap();
+  assert_eq!((second.id, second.process.program.name.as_str()), (1, "second"));
+  fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn failed_checkpoint_keeps_the_process_running() {
+  let dir = env::temp_dir().join(format!("avmir_checkpoint_missing_{}", std::process::id()));
+  let mut machine = MachineBuilder::new().checkpoint_dir(&dir).build();
+  machine.launch_source::<Simple>("missing", "Checkpoint\nPush 1\n").unwrap().join().unwrap();
+  assert!(!dir.exists());
+}
+
+#[test]
+fn heap_ranges_past_the_top_are_corrupted() {
+  let dir = env::temp_dir().join(format!("avmir_checkpoint_heap_{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let mut machine = MachineBuilder::new().checkpoint_dir(&dir).build();
+  machine.launch_source::<Simple>("heap", "Alloc 8\nCheckpoint\n").unwrap().join().unwrap();
+  let path = dir.join("0.checkpoint");
+  assert!(Checkpoint::load(&path).is_ok());
+
+  // the size of the only allocation, before the empty free list and the mount
+  let mut content = fs::read(&path).unwrap();
+  let size = content.len() - 17;
+  content[(size - 8)..size].copy_from_slice(&u64::MAX.to_le_bytes());
+  fs::write(&path, content).unwrap();
+  assert!(matches!(Checkpoint::load(&path), Err(CheckpointError::Corrupted { .. })));
+  fs::remove_dir_all(&dir).unwrap();
+}
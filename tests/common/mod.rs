@@ -0,0 +1,55 @@
+//! Running the programs with the avmir binary, shared by the tests comparing their output
+// every test uses a part of it
+#![allow(dead_code)]
+
+use std::{path::{Path, PathBuf}, process::{Command, Output}, sync::OnceLock};
+
+pub const PROGRAMS: [&str; 12] = [
+  "examples/hello_world.txt",
+  "examples/fork.txt",
+  "examples/structured/fibonacci.avs",
+  "examples/forth/squares.fs",
+  "examples/wasm/factorial.wat",
+  "tests/programs/values.txt",
+  "tests/programs/heap.txt",
+  "tests/programs/bulk.txt",
+  "tests/programs/endian.txt",
+  "tests/programs/return_address.txt",
+  "tests/programs/hot_loop.txt",
+  "tests/programs/fused.txt"
+];
+
+pub const AVMIR: &str = env!("CARGO_BIN_EXE_avmir");
+
+/// The directory of the std library, built along the binary
+pub fn library_dir() -> &'static Path {
+  static BUILT: OnceLock<PathBuf> = OnceLock::new();
+  BUILT.get_or_init(|| {
+    let mut build = Command::new(env!("CARGO"));
+    build.args(["build", "-p", "avmir_std"]);
+    if !cfg!(debug_assertions) {
+      build.arg("--release");
+    }
+    assert!(build.status().expect("unable to run cargo").success(), "unable to build avmir_std");
+    Path::new(AVMIR).parent().unwrap().into()
+  })
+}
+
+pub fn stdout(command: &mut Command) -> String {
+  let Output { status, stdout, stderr } = command.env("LD_LIBRARY_PATH", library_dir()).output().unwrap();
+  assert!(status.success(), "{:?} failed: {}", command, String::from_utf8_lossy(&stderr));
+  String::from_utf8(stdout).unwrap()
+}
+
+/// The output of the program run by the interpreter with the options
+pub fn interpret(program: &str, options: &[&str]) -> String {
+  stdout(Command::new(AVMIR).args([program, "-l", "avmir_std"]).args(options))
+}
+
+/// The standard output and error of the program run with the options, which must fail
+pub fn failure(program: &str, options: &[&str]) -> (String, String) {
+  let Output { status, stdout, stderr } = Command::new(AVMIR).args([program, "-l", "avmir_std"]).args(options)
+    .env("LD_LIBRARY_PATH", library_dir()).output().unwrap();
+  assert!(!status.success(), "{} did not fail", program);
+  (String::from_utf8(stdout).unwrap(), String::from_utf8(stderr).unwrap())
+}
@@ -0,0 +1,52 @@
+//! The sequences of instructions decoded as superinstructions, unless a jump goes to the middle of them
+
+mod common;
+
+use avmir::{
+  parser::{v2::Simple, Parser},
+  vm::{
+    instruction::{decode, BinaryOp, DecodedInstruction::{self, *}}, program::{Instruction, InstructionParam, Opcode, Program},
+    stack::StackValue
+  }
+};
+use common::interpret;
+
+fn decoded(source: &str) -> Vec<DecodedInstruction> {
+  let mut program = Program::new();
+  Simple::parse(&mut program, source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+  decode(&program.instructions)
+}
+
+#[test]
+fn sequences_become_superinstructions() {
+  // every instruction of a sequence is also decoded on its own
+  assert_eq!(decoded("Reg 14\nAdd _ 8\nPush 1\n"), [RegOffset(14, 8), BinaryRight(BinaryOp::Add, StackValue::Int(8)), Push(StackValue::Int(1))]);
+  assert_eq!(decoded("Reg 14\nSub _ 8\n"), [RegOffset(14, -8), BinaryRight(BinaryOp::Sub, StackValue::Int(8))]);
+  assert_eq!(decoded("Reg 14\nAdd _ 8\nReadInt64\n"), [ReadOffset(14, 8), BinaryRight(BinaryOp::Add, StackValue::Int(8)), ReadInt64]);
+  assert_eq!(decoded("Reg 14\nAdd _ 8\nWriteInt64\n"), [WriteOffset(14, 8), BinaryRight(BinaryOp::Add, StackValue::Int(8)), WriteInt64]);
+  assert_eq!(decoded("Eq _ 3\nJump 0\n"), [JumpIfEqual(StackValue::Int(3), 0), JumpIf(0)]);
+}
+
+#[test]
+fn operands_not_known_ahead_are_not_fused() {
+  let out_of_bounds = Instruction(Opcode::Reg, Some(InstructionParam::Int(40)), None);
+  assert_eq!(decoded("Reg 40\nAdd _ 8\n")[0], Generic(out_of_bounds.into()));
+  assert_eq!(decoded("Reg 14\nAdd _ 8.0\n")[0], Reg(14));
+  assert_eq!(decoded("Reg 14\nAdd\n")[0], Reg(14));
+  assert_eq!(decoded("Eq _ 3\nJump _ 1\n")[0], BinaryRight(BinaryOp::Eq, StackValue::Int(3)));
+  assert_eq!(decoded("Reg 14\nSub _ -9223372036854775808\n")[0], Reg(14));
+}
+
+#[test]
+fn jump_targets_in_the_middle_block_fusion() {
+  assert_eq!(decoded("Reg 14\nmiddle: Add _ 8\nJump $middle 1\n")[..2], [Reg(14), BinaryRight(BinaryOp::Add, StackValue::Int(8))]);
+  assert_eq!(decoded("Reg 14\nAdd _ 8\nlast: ReadInt64\nJump $last\n")[0], RegOffset(14, 8));
+  assert_eq!(decoded("Eq _ 3\nnext: Jump 0\nFork $next\n")[0], BinaryRight(BinaryOp::Eq, StackValue::Int(3)));
+  // a target on the first instruction keeps the sequence
+  assert_eq!(decoded("first: Reg 14\nAdd _ 8\nJump $first 1\n")[0], RegOffset(14, 8));
+}
+
+#[test]
+fn superinstructions_skip_their_sequence() {
+  assert_eq!(interpret("tests/programs/fused.txt", &[]), "42\n-2\n7\n41\n41\n");
+}
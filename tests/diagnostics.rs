@@ -0,0 +1,51 @@
+//! The command writes every parse error of a file once, with the span it points to, and fails
+
+mod common;
+
+use std::process::{Command, Output};
+
+use common::AVMIR;
+
+#[test]
+fn parse_errors_are_collected_and_written_once() {
+  let Output { status, stdout, stderr } = Command::new(AVMIR).arg("tests/errors/parse.txt").output().unwrap();
+  assert_eq!(status.code(), Some(1));
+  assert!(stdout.is_empty());
+  let expected = "\
+parse error:
+error: unterminated string literal
+ --> tests/errors/parse.txt:1:8
+  |
+1 | hello #\"unterminated
+  |        ^^^^^^^^^^^^^
+
+error: bad line syntax: Push 1 2 3
+ --> tests/errors/parse.txt:2:9
+  |
+2 |         Push 1 2 3
+  |         ^^^^^^^^^^
+
+error: opcode not found: Bogus
+ --> tests/errors/parse.txt:3:9
+  |
+3 |         Bogus 4
+  |         ^^^^^
+
+error: bad tag: nowhere, not found
+ --> tests/errors/parse.txt:4:14
+  |
+4 |         Jump $nowhere
+  |              ^^^^^^^^
+
+";
+  assert_eq!(String::from_utf8(stderr).unwrap(), expected);
+}
+
+#[test]
+fn runtime_errors_are_written_once() {
+  let Output { status, stderr, .. } = Command::new(AVMIR).arg("tests/errors/missing.txt").output().unwrap();
+  assert_eq!(status.code(), Some(1));
+  let stderr = String::from_utf8(stderr).unwrap();
+  assert_eq!(stderr.lines().count(), 1, "{}", stderr);
+  assert!(stderr.starts_with("io error: "), "{}", stderr);
+}
@@ -0,0 +1,55 @@
+//! A fault ends the process running into it, the others keep running and the command fails once they end
+
+mod common;
+
+use common::failure;
+
+#[test]
+fn memory_fault_ends_only_its_process() {
+  let (stdout, stderr) = failure("tests/faults/memory.txt", &[]);
+  assert_eq!(stdout, "1\n");
+  assert!(stderr.contains("process 1 faulted at the instruction 7: memory fault accessing 8 bytes at 1000000"), "{}", stderr);
+}
+
+#[test]
+fn heap_faults_end_the_process() {
+  let (_, stderr) = failure("tests/faults/double_free.txt", &[]);
+  assert!(stderr.contains("process 0 faulted at the instruction 3: double free of the address"), "{}", stderr);
+  let (_, stderr) = failure("tests/faults/huge_alloc.txt", &[]);
+  assert!(stderr.contains("process 0 faulted at the instruction 0: allocation of 9223372036854775807 bytes"), "{}", stderr);
+}
+
+#[test]
+fn fault_in_a_trap_ends_the_process() {
+  let (stdout, stderr) = failure("tests/faults/trap.txt", &[]);
+  assert!(!stdout.contains("DONE!"), "{}", stdout);
+  assert!(stderr.contains("process 0 faulted at the instruction 4: memory fault accessing 8 bytes at 1000000"), "{}", stderr);
+}
+
+#[test]
+fn fault_in_a_superinstruction_reports_its_instruction() {
+  let (_, stderr) = failure("tests/faults/offset.txt", &[]);
+  assert!(stderr.contains("process 0 faulted at the instruction 10: memory fault accessing 8 bytes at 1000008"), "{}", stderr);
+}
+
+#[test]
+fn bulk_faults_check_the_length_first() {
+  let (_, stderr) = failure("tests/faults/fill.txt", &[]);
+  assert!(stderr.contains("process 0 faulted at the instruction 2: memory fault accessing 1099511627776 bytes at 0"), "{}", stderr);
+  let (_, stderr) = failure("tests/faults/copy.txt", &[]);
+  assert!(stderr.contains("process 0 faulted at the instruction 3: memory fault accessing 1099511627776 bytes at 0"), "{}", stderr);
+}
+
+#[test]
+fn unknown_units_end_the_process() {
+  let (_, stderr) = failure("tests/faults/units.txt", &[]);
+  for fault in [
+    "at the instruction 3: there is no memory unit 5",
+    "at the instruction 4: there is no memory unit 7",
+    "at the instruction 5: unit of 9223372036854775807 bytes",
+    "at the instruction 12: there is no memory unit"
+  ] {
+    assert!(stderr.contains(fault), "{}", stderr);
+  }
+  assert!(stderr.contains("processes ended by a fault: 4"), "{}", stderr);
+}
@@ -0,0 +1,41 @@
+//! Formatting keeps the meaning of the sources, the formatted program is the same as the original one
+
+use std::fs;
+
+use avmir::{parser::{format, v2::Simple, Parser}, vm::program::Program};
+
+const SOURCES: [&str; 3] = [
+  "examples/hello_world.txt",
+  "examples/fork.txt",
+  "tests/sources/numeric_tags.txt"
+];
+
+fn program(source: &str) -> String {
+  let mut program = Program::new();
+  Simple::parse(&mut program, source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+  format!("{:?} {:?}", program.instructions, program.static_data)
+}
+
+#[test]
+fn formatted_sources_are_the_same_program() {
+  for path in SOURCES {
+    let source = fs::read_to_string(path).unwrap();
+    let formatted = format::format(&source).unwrap();
+    assert_eq!(program(&source), program(&formatted), "{} changed when formatted:\n{}", path, formatted);
+    assert_eq!(format::format(&formatted).unwrap(), formatted, "{} is not formatted once formatted", path);
+  }
+}
+
+#[test]
+fn exported_tags_are_kept() {
+  let exported = |source: &str| {
+    let module = Simple::parse_module("", source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+    let mut exports: Vec<_> = module.exports.iter().map(|(tag, symbol)| format!("{} {:?}", tag, symbol)).collect();
+    exports.sort();
+    (exports, program(source))
+  };
+  let source = fs::read_to_string("tests/sources/exported_tags.txt").unwrap();
+  let formatted = format::format(&source).unwrap();
+  assert_eq!(exported(&source), exported(&formatted), "exports changed when formatted:\n{}", formatted);
+  assert_eq!(format::format(&formatted).unwrap(), formatted);
+}
@@ -0,0 +1,34 @@
+//! The Forth front-end runs the example with the output a Forth system prints for it
+
+mod common;
+
+use avmir::{parser::{forth::{Forth, InternalForthError}, Parser}, vm::program::Program};
+use common::interpret;
+
+fn error(source: &str) -> InternalForthError {
+  Forth::parse(&mut Program::new(), source).expect_err(source).error
+}
+
+#[test]
+fn squares_example() {
+  let expected = "\
+sum of squares below 10 is 285 
+5 4 3 2 1 
+10! = 3628800 
+ABC
+0 1 10 11 20 21 
+1 2 4 -1 3 2 1 
+";
+  assert_eq!(interpret("examples/forth/squares.fs", &[]), expected);
+  assert_eq!(interpret("examples/forth/squares.fs", &["-O"]), expected);
+}
+
+#[test]
+fn rejected_programs() {
+  assert!(matches!(error("1 squre ."), InternalForthError::UnknownWord(word, _) if word == "squre"));
+  assert!(matches!(error(": f 1 IF 2 ;"), InternalForthError::Unbalanced(..)));
+  assert!(matches!(error("1 RECURSE"), InternalForthError::CompileOnly(word) if word == "RECURSE"));
+  assert!(matches!(error(": f : g ;"), InternalForthError::NestedDefinition));
+  assert!(matches!(error(": f 1"), InternalForthError::UnterminatedDefinition));
+  assert!(matches!(error(".\" open"), InternalForthError::Unterminated('"')));
+}
@@ -0,0 +1,51 @@
+//! The functions of the embedder invoked by name from the programs
+
+use avmir::{
+  parser::v2::Simple,
+  vm::{ffi::HostFn, machine::{MachineBuilder, ProcessError}, memory::MemoryFault, process::ProcessFault, stack::StackValue}
+};
+
+#[test]
+fn plain_function_result_is_pushed() {
+  let mut machine = MachineBuilder::new()
+    .add_host_fn("double", HostFn::plain(|registers| Some(StackValue::Int(i64::from(registers[0]) * 2))))
+    .add_host_fn("nothing", HostFn::plain(|_| None))
+    .build();
+  let source = "double #double\nnothing #nothing\nSetReg 0 21\nFastInvoke $double @double\nFastInvoke $nothing @nothing\n";
+  let process = machine.launch_source::<Simple>("plain", source).unwrap().join().unwrap();
+  assert_eq!(process.stack.as_slice(), [StackValue::Int(42)]);
+}
+
+#[test]
+fn memory_function_uses_the_active_memory() {
+  let mut machine = MachineBuilder::new()
+    .add_memory(vec![0u8; 16])
+    .add_host_fn("store", HostFn::memory(|registers, memory| {
+      memory.write(usize::from(registers[0]), &i64::from(registers[1]).to_le_bytes());
+      None
+    }))
+    .build();
+  // the symbol is read from the process memory, the function writes into the mounted unit
+  let source = "store #store\nPrepareInvoke $store @store\nSetReg 0 8\nSetReg 1 7\nMount 0\nInvoke\nReadInt64 8\n";
+  let process = machine.launch_source::<Simple>("memory", source).unwrap().join().unwrap();
+  assert_eq!(process.stack.as_slice(), [StackValue::Int(7)]);
+}
+
+#[test]
+fn trap_function_runs_the_process() {
+  // the trap runs the next instruction itself and skips the one after it
+  let mut machine = MachineBuilder::new()
+    .add_host_fn("step", HostFn::trap(|process, supervisor| {
+      process.run_next(supervisor)?;
+      process.pc += 1;
+      Ok(Some(StackValue::Int(process.pc as i64)))
+    }))
+    .build();
+  let source = "step #step\nFastInvoke $step @step\nPush 1\nPush 2\nPush 3\n";
+  let process = machine.launch_source::<Simple>("trap", source).unwrap().join().unwrap();
+  assert_eq!(process.stack.as_slice(), [StackValue::Int(1), StackValue::Int(3), StackValue::Int(3)]);
+
+  let source = "step #step\nFastInvoke $step @step\nReadInt64 1000000\nPush 1\n";
+  let fault = machine.launch_source::<Simple>("trap", source).unwrap().join().err();
+  assert!(matches!(fault, Some(ProcessError::Fault { pc: 1, fault: ProcessFault::Memory(MemoryFault { address: 1000000, .. }), .. })));
+}
@@ -0,0 +1,61 @@
+//! The programs compiled by the jit print the same as in the interpreter, `tests/programs/hot_loop.txt` runs past
+//! the threshold with side exits and computed jumps in the native code
+#![cfg(feature = "jit")]
+
+mod common;
+
+use std::{env, fs, thread, time::Duration};
+
+use avmir::{parser::v2::Simple, vm::{checkpoint::Checkpoint, machine::{MachineBuilder, ProcessError}}};
+use common::{failure, interpret, PROGRAMS};
+
+fn compare(options: &[&str]) {
+  for program in PROGRAMS {
+    let jit = [options, &["--jit"]].concat();
+    assert_eq!(interpret(program, &jit), interpret(program, options), "output of {}", program);
+  }
+}
+
+#[test]
+fn same_output_as_the_interpreter() {
+  compare(&[])
+}
+
+#[test]
+fn same_output_as_the_interpreter_optimized() {
+  compare(&["-O"])
+}
+
+#[test]
+fn same_fault_as_the_interpreter() {
+  for program in ["tests/faults/offset.txt", "tests/faults/memory.txt"] {
+    assert_eq!(failure(program, &["--jit"]), failure(program, &[]), "fault of {}", program);
+  }
+}
+
+#[test]
+fn killed_in_native_code() {
+  let mut machine = MachineBuilder::new().jit(true).build();
+  let process = machine.launch_source::<Simple>("forever", "forever: Jump $forever 1\n").unwrap();
+  thread::sleep(Duration::from_millis(100));
+  process.kill();
+  assert!(matches!(process.join(), Err(ProcessError::Killed(0))));
+}
+
+#[test]
+fn checkpointed_in_native_code() {
+  let dir = env::temp_dir().join(format!("avmir_jit_checkpoint_{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let mut machine = MachineBuilder::new().jit(true).checkpoint_dir(&dir).build();
+  let process = machine.launch_source::<Simple>("forever", "forever: Jump $forever 1\n").unwrap();
+  thread::sleep(Duration::from_millis(100));
+  machine.request_checkpoint();
+  let path = dir.join("0.checkpoint");
+  while !path.exists() {
+    thread::sleep(Duration::from_millis(10));
+  }
+  process.kill();
+  assert!(matches!(process.join(), Err(ProcessError::Killed(0))));
+  assert_eq!(Checkpoint::load(&path).unwrap().process.program.name, "forever");
+  fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,36 @@
+//! Killing the processes of the interpreter, the jit has its own tests in `tests/jit.rs`
+
+use std::{thread, time::Duration};
+
+use avmir::{parser::v2::Simple, vm::machine::{MachineBuilder, ProcessError}};
+
+#[test]
+fn killed_in_a_loop() {
+  let mut machine = MachineBuilder::new().build();
+  let process = machine.launch_source::<Simple>("forever", "forever: Jump $forever 1\n").unwrap();
+  let pid = process.pid();
+  thread::sleep(Duration::from_millis(100));
+  assert!(!process.is_finished());
+  process.kill();
+  assert!(matches!(process.join(), Err(ProcessError::Killed(killed)) if killed == pid));
+  assert_eq!(machine.faults(), 0);
+}
+
+#[test]
+fn killed_after_a_sleep() {
+  let mut machine = MachineBuilder::new().build();
+  let process = machine.launch_source::<Simple>("sleeping", "ThreadSleep 200\nforever: Jump $forever 1\n").unwrap();
+  process.kill();
+  assert!(matches!(process.join(), Err(ProcessError::Killed(_))));
+}
+
+#[test]
+fn killing_a_finished_process() {
+  let mut machine = MachineBuilder::new().build();
+  let process = machine.launch_source::<Simple>("short", "Push 1\n").unwrap();
+  while !process.is_finished() {
+    thread::sleep(Duration::from_millis(10));
+  }
+  process.kill();
+  assert!(process.join().is_ok());
+}
@@ -0,0 +1,71 @@
+//! Linking modules resolves the imported tags to the exports of the other modules
+
+mod common;
+
+use avmir::{parser::{linker::{Linker, LinkerError}, v2::Simple}, vm::program::Program};
+
+use common::interpret;
+
+const MAIN: &str = "\
+.import data entry
+        Push $data @data
+        Push ^data
+        Jump $entry 0
+";
+
+const DATA: &str = "\
+.export data entry
+pad     #xx
+data    #hello
+entry:  Exit
+";
+
+fn link(sources: &[(&str, &str)]) -> Result<Program, LinkerError> {
+  sources.iter()
+    .map(|&(name, source)| Simple::parse_module(name, source).unwrap_or_else(|err| panic!("{}\n{}", err, source)))
+    .fold(Linker::new(), |linker, module| linker.add_module(module))
+    .link("linked")
+}
+
+fn instructions(program: &Program) -> Vec<String> {
+  program.instructions.iter().map(|x| format!("{:?}", x)).collect()
+}
+
+#[test]
+fn linked_example_runs() {
+  assert_eq!(interpret("examples/linking/main.txt", &["--link", "examples/linking/print.txt"]), "hello from another file!\n");
+}
+
+#[test]
+fn imports_are_resolved() {
+  let program = link(&[("main", MAIN), ("data", DATA)]).unwrap();
+  // every module ends with an Exit, the data module starts after the 3 instructions of main and its Exit
+  assert_eq!(instructions(&program), [
+    "Instruction(Push, Some(Int(2)), Some(Int(5)))",
+    "Instruction(Push, Some(Int(7)), None)",
+    "Instruction(Jump, Some(Int(4)), Some(Int(0)))",
+    "Instruction(Exit, None, None)",
+    "Instruction(Exit, None, None)",
+    "Instruction(Exit, None, None)"
+  ]);
+  assert_eq!(program.static_data, b"xxhello");
+  assert!(program.code_references.contains(&(2, 0)), "the imported instruction is not relocated with the code");
+}
+
+#[test]
+fn missing_export() {
+  let err = link(&[("main", MAIN), ("data", ".export data\ndata #hello\n")]).unwrap_err();
+  assert_eq!(err.to_string(), "unresolved symbol: entry, imported by main");
+}
+
+#[test]
+fn duplicate_export() {
+  let err = link(&[("main", MAIN), ("data", DATA), ("again", ".export data\ndata #world\n")]).unwrap_err();
+  assert_eq!(err.to_string(), "duplicate symbol: data, exported by data and again");
+}
+
+#[test]
+fn instruction_imported_as_memory() {
+  let err = link(&[("main", ".import entry\n        Push @entry\n"), ("data", DATA)]).unwrap_err();
+  assert_eq!(err.to_string(), "bad symbol: entry, only $ is valid for instruction symbol, imported by main");
+}
@@ -0,0 +1,143 @@
+//! The language server answers over stdio like an editor would use it
+
+mod common;
+
+use std::{
+  fs, io::{BufReader, Write},
+  process::{Child, ChildStdin, ChildStdout, Command, Stdio}
+};
+
+use lsp_server::{Message, Notification, Request, RequestId, Response};
+use serde_json::{json, Value};
+
+use common::AVMIR;
+
+const PRINT: &str = "print   #std_println\n        FastInvoke $print @print\n";
+
+struct Client {
+  server: Child,
+  input: ChildStdin,
+  output: BufReader<ChildStdout>,
+  id: i32
+}
+
+impl Client {
+  fn start() -> Self {
+    let mut server = Command::new(AVMIR).arg("lsp").stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    let input = server.stdin.take().unwrap();
+    let output = BufReader::new(server.stdout.take().unwrap());
+    let mut client = Client { server, input, output, id: 0 };
+    client.request("initialize", json!({ "capabilities": {} })).result.unwrap();
+    client.send(Notification::new("initialized".into(), json!({})));
+    client
+  }
+
+  fn send(&mut self, message: impl Into<Message>) {
+    message.into().write(&mut self.input).unwrap();
+    self.input.flush().unwrap();
+  }
+
+  fn receive(&mut self) -> Message {
+    Message::read(&mut self.output).unwrap().expect("the server closed the connection")
+  }
+
+  fn request(&mut self, method: &str, params: Value) -> Response {
+    self.id += 1;
+    let id = RequestId::from(self.id);
+    self.send(Request::new(id.clone(), method.into(), params));
+    loop {
+      if let Message::Response(response) = self.receive() {
+        assert_eq!(response.id, id);
+        return response
+      }
+    }
+  }
+
+  /// Open a document and return the diagnostics published for it
+  fn open(&mut self, uri: &str, text: &str) -> Value {
+    let document = json!({ "uri": uri, "languageId": "avmir", "version": 0, "text": text });
+    self.send(Notification::new("textDocument/didOpen".into(), json!({ "textDocument": document })));
+    loop {
+      if let Message::Notification(notification) = self.receive() {
+        assert_eq!(notification.method, "textDocument/publishDiagnostics");
+        return notification.params["diagnostics"].clone()
+      }
+    }
+  }
+
+  fn at(&mut self, method: &str, uri: &str, (line, character): (u32, u32), params: Value) -> Response {
+    let mut params = params;
+    params["textDocument"] = json!({ "uri": uri });
+    params["position"] = json!({ "line": line, "character": character });
+    self.request(method, params)
+  }
+
+  fn stop(mut self) {
+    assert!(self.request("shutdown", Value::Null).error.is_none());
+    self.send(Notification::new("exit".into(), Value::Null));
+    assert!(self.server.wait().unwrap().success());
+  }
+}
+
+fn range(line: u32, start: u32, end: u32) -> Value {
+  json!({ "start": { "line": line, "character": start }, "end": { "line": line, "character": end } })
+}
+
+#[test]
+fn diagnostics_of_the_errors() {
+  let mut client = Client::start();
+  assert_eq!(client.open("file:///print.txt", PRINT), json!([]));
+  let diagnostics = client.open("file:///bad.txt", "        Jump $missing 0\n");
+  assert_eq!(diagnostics.as_array().unwrap().len(), 1, "{}", diagnostics);
+  assert_eq!(diagnostics[0]["range"]["start"]["line"], 0);
+  assert!(diagnostics[0]["message"].as_str().unwrap().contains("missing"), "{}", diagnostics);
+  client.stop();
+}
+
+#[test]
+fn definition_and_references() {
+  let mut client = Client::start();
+  client.open("file:///print.txt", PRINT);
+
+  let definition = client.at("textDocument/definition", "file:///print.txt", (1, 21), json!({}));
+  assert_eq!(definition.result.unwrap(), json!([{ "uri": "file:///print.txt", "range": range(0, 0, 5) }]));
+
+  let references = client.at("textDocument/references", "file:///print.txt", (0, 2), json!({ "context": { "includeDeclaration": false } }));
+  assert_eq!(references.result.unwrap(), json!([
+    { "uri": "file:///print.txt", "range": range(1, 20, 25) },
+    { "uri": "file:///print.txt", "range": range(1, 27, 32) }
+  ]));
+
+  let hover = client.at("textDocument/hover", "file:///print.txt", (1, 10), json!({}));
+  assert!(hover.result.unwrap()["contents"]["value"].as_str().unwrap().starts_with("**FastInvoke**"));
+  client.stop();
+}
+
+#[test]
+fn rename_of_a_tag() {
+  let mut client = Client::start();
+  client.open("file:///print.txt", PRINT);
+
+  let rename = client.at("textDocument/rename", "file:///print.txt", (1, 21), json!({ "newName": "out" }));
+  let edit = |range| json!({ "range": range, "newText": "out" });
+  assert_eq!(
+    rename.result.unwrap(),
+    json!({ "changes": { "file:///print.txt": [edit(range(0, 0, 5)), edit(range(1, 20, 25)), edit(range(1, 27, 32))] } })
+  );
+
+  let rename = client.at("textDocument/rename", "file:///print.txt", (1, 21), json!({ "newName": "two words" }));
+  assert!(rename.error.unwrap().message.contains("not a valid tag"));
+  client.stop();
+}
+
+#[test]
+fn rename_of_an_imported_tag_is_refused() {
+  let mut client = Client::start();
+  client.open("file:///main.txt", &fs::read_to_string("examples/linking/main.txt").unwrap());
+
+  for position in [(1, 10), (8, 15)] {
+    let rename = client.at("textDocument/rename", "file:///main.txt", position, json!({ "newName": "print" }));
+    assert_eq!(rename.error.unwrap().message, "bad request: print_message is imported, rename it where it is exported");
+  }
+  client.stop();
+}
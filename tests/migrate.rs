@@ -0,0 +1,27 @@
+//! Migrating a v1 source writes the expected v2 source, which is the same program
+
+#![allow(deprecated)]
+
+use std::{fmt::Display, fs};
+
+use avmir::{parser::{migrate, v1, v2, Parser}, vm::program::Program};
+
+fn program<P: Parser<Err: Display>>(source: &str) -> String {
+  let mut program = Program::new();
+  P::parse(&mut program, source).unwrap_or_else(|err| panic!("{}\n{}", err, source));
+  format!("{:?} {:?}", program.instructions, program.static_data)
+}
+
+#[test]
+fn migrated_source() {
+  let source = fs::read_to_string("tests/sources/v1.txt").unwrap();
+  let migrated = migrate::v1_to_v2(&source).unwrap();
+  assert_eq!(migrated, fs::read_to_string("tests/sources/v1_migrated.txt").unwrap());
+  assert_eq!(program::<v1::Simple>(&source), program::<v2::Simple>(&migrated));
+}
+
+#[test]
+fn bad_v1_source() {
+  let err = migrate::v1_to_v2("#hello\n    Push $1\n").unwrap_err();
+  assert_eq!(err.to_string(), "not a valid v1 source: Error [LINE: 2] :: bad memory item: 1");
+}
@@ -0,0 +1,12 @@
+//! The optimized programs print the same as the programs as written
+
+mod common;
+
+use common::{interpret, PROGRAMS};
+
+#[test]
+fn same_output_optimized() {
+  for program in PROGRAMS {
+    assert_eq!(interpret(program, &["-O"]), interpret(program, &[]), "output of {}", program);
+  }
+}
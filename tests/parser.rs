@@ -0,0 +1,27 @@
+//! The memory chunks of v2 sources, verbatim or quoted with escape sequences
+
+use avmir::{parser::{v2::{InternalSimpleParserError, Simple}, Parser}, vm::program::Program};
+
+fn chunk(source: &str) -> Result<Vec<u8>, Vec<InternalSimpleParserError>> {
+  let mut program = Program::new();
+  Simple::parse(&mut program, source).map_err(|errors| errors.0.into_iter().map(|err| err.error).collect::<Vec<_>>())?;
+  Ok(program.static_data)
+}
+
+#[test]
+fn escapes_in_quoted_chunks() {
+  assert_eq!(chunk("msg #  hello \"world\"").unwrap(), b"  hello \"world\"");
+  assert_eq!(chunk(r#"msg #"a\n\r\t\0\\\"b" ; comment"#).unwrap(), b"a\n\r\t\0\\\"b");
+  assert_eq!(chunk(r#"msg #"\x41\xff\x0a""#).unwrap(), b"A\xff\n");
+  assert_eq!(chunk(r#"msg #"\u{41}\u{e9}\u{1F600}""#).unwrap(), "Aé😀".as_bytes());
+}
+
+#[test]
+fn rejected_escapes() {
+  for source in [r#"m #"\x+1""#, r#"m #"\x4""#, r#"m #"\xg1""#, r#"m #"\u{+41}""#, r#"m #"\u{}""#, r#"m #"\u{110000}""#, r#"m #"\q""#] {
+    let errors = chunk(source).unwrap_err();
+    assert!(matches!(errors[..], [InternalSimpleParserError::BadEscape(_)]), "{}: {:?}", source, errors);
+  }
+  assert!(matches!(chunk(r#"m #"open"#).unwrap_err()[..], [InternalSimpleParserError::UnterminatedString]));
+  assert!(matches!(chunk(r#"m #"a" b"#).unwrap_err()[..], [InternalSimpleParserError::TrailingContent(_)]));
+}
@@ -0,0 +1,58 @@
+//! Machines attached to the same POSIX shared memory object see the writes of each other, the object is removed once
+//! the last one detaches
+#![cfg(unix)]
+
+mod common;
+
+use std::path::Path;
+
+use avmir::{parser::v2::Simple, vm::{machine::MachineBuilder, memory::Memory, shm::SharedMemory}};
+use common::{failure, interpret};
+
+fn object(test: &str) -> String {
+  format!("/avmir_{}_{}", test, std::process::id())
+}
+
+#[test]
+fn machines_share_the_object() {
+  let name = object("machines");
+  let mut writer = MachineBuilder::new().add_memory(SharedMemory::open(&name, 64).unwrap()).build();
+  let mut reader = MachineBuilder::new().add_memory(SharedMemory::open(&name, 16).unwrap()).build();
+  writer.launch_source::<Simple>("writer", "Mount 0\nWriteInt64 56 42\n").unwrap().join().unwrap();
+
+  // the second machine attached with a smaller size sees the whole object
+  let memory = reader.unit(0).unwrap().1;
+  assert_eq!(memory.read().unwrap().size(), 64);
+  assert_eq!(memory.read().unwrap().read(56, 8), 42i64.to_le_bytes());
+  reader.launch_source::<Simple>("reader", "Mount 0\nWriteInt64 0 7\n").unwrap().join().unwrap();
+  assert_eq!(writer.unit(0).unwrap().1.read().unwrap().read(0, 8), 7i64.to_le_bytes());
+  drop(memory);
+
+  let path = Path::new("/dev/shm").join(&name[1..]);
+  drop(writer);
+  assert!(path.exists());
+  drop(reader);
+  assert!(!path.exists());
+}
+
+#[test]
+fn instances_share_the_object() {
+  let name = object("instances");
+  let unit = format!("16:shm:{}", name);
+  // the object is removed between the runs, the second instance starts from zeroes again
+  assert_eq!(interpret("tests/units/shm.txt", &["-m", &unit]), "0\n");
+  assert_eq!(interpret("tests/units/shm.txt", &["-m", &unit]), "0\n");
+  assert!(!Path::new("/dev/shm").join(&name[1..]).exists());
+
+  let shared = SharedMemory::open(&name, 16).unwrap();
+  assert_eq!(interpret("tests/units/shm.txt", &["-m", &unit]), "0\n");
+  assert_eq!(interpret("tests/units/shm.txt", &["-m", &format!("{}:rw", unit)]), "9\n");
+  assert_eq!(shared.read(0, 8), 9i64.to_le_bytes());
+
+  for invalid in [format!("{}:grow", unit), format!("{}:offset=8", unit)] {
+    let (_, stderr) = failure("tests/units/shm.txt", &["-m", &invalid]);
+    assert!(stderr.contains("not a valid memory unit syntax"), "{}: {}", invalid, stderr);
+  }
+  let (_, stderr) = failure("tests/units/shm.txt", &["-m", "16:shm:/a/b"]);
+  assert!(stderr.contains("invalid shared memory name /a/b"), "{}", stderr);
+}
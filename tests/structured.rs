@@ -0,0 +1,25 @@
+//! The structured front-end compiles the example into a program printing the same as the source reads
+
+mod common;
+
+use avmir::{parser::{structured::{InternalStructuredError, Structured}, Parser}, vm::program::Program};
+use common::interpret;
+
+fn error(source: &str) -> InternalStructuredError {
+  Structured::parse(&mut Program::new(), source).expect_err(source).error
+}
+
+#[test]
+fn fibonacci_example() {
+  let expected = "fibonacci:\n0\n1\n1\n2\n3\n5\n8\n13\n21\n34\n55\nthe average is between 1.5 and 2\n";
+  assert_eq!(interpret("examples/structured/fibonacci.avs", &[]), expected);
+  assert_eq!(interpret("examples/structured/fibonacci.avs", &["-O"]), expected);
+}
+
+#[test]
+fn rejected_programs() {
+  assert!(matches!(error("fn main() { x = 1; }"), InternalStructuredError::VariableNotFound(name) if name == "x"));
+  assert!(matches!(error("fn main() { let x = 1; x = 2.0; }"), InternalStructuredError::MismatchedTypes { .. }));
+  assert!(matches!(error("fn f() -> int { }\nfn main() { }"), InternalStructuredError::MissingReturn(name) if name == "f"));
+  assert!(matches!(error("fn start() { }"), InternalStructuredError::MainNotFound));
+}
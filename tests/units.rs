@@ -0,0 +1,84 @@
+//! The shared memory units created by the programs and the embedders, and the ones of the command line: zeroed,
+//! named or mapping a file
+
+mod common;
+
+use std::{env, fs};
+
+use avmir::{
+  parser::v2::Simple, vm::{machine::{MachineBuilder, ProcessError}, memory::{Access, UnitFault}, process::ProcessFault}
+};
+use common::{failure, interpret};
+
+#[test]
+fn shared_unit_example() {
+  assert_eq!(interpret("examples/shared_unit.txt", &[]), "42\n");
+}
+
+#[test]
+fn units_of_the_embedder() {
+  let mut machine = MachineBuilder::new().build();
+  let unit = machine.create_unit(vec![0u8; 16], Access::READ_WRITE);
+  let source = format!("Mount {}\nWriteInt64 8 7\n", unit);
+  machine.launch_source::<Simple>("write", &source).unwrap().join().unwrap();
+  let (access, memory) = machine.unit(unit).unwrap();
+  assert_eq!(access, Access::READ_WRITE);
+  assert_eq!(memory.read().unwrap().read(8, 8), 7i64.to_le_bytes());
+
+  assert!(machine.destroy_unit(unit));
+  assert!(!machine.destroy_unit(unit));
+  assert!(machine.unit(unit).is_none());
+  let fault = machine.launch_source::<Simple>("write", &source).unwrap().join().err();
+  assert!(matches!(fault, Some(ProcessError::Fault { pc: 0, fault: ProcessFault::Unit(UnitFault::Unknown(0)), .. })));
+}
+
+#[test]
+fn named_units_of_the_command_line() {
+  let program = "tests/units/named.txt";
+  assert_eq!(interpret(program, &["-m", "first=16", "-m", "second=16"]), "5\n");
+  assert_eq!(interpret(program, &["-m", "second=16", "-m", "first=16", "-m", "32"]), "5\n");
+
+  let (_, stderr) = failure(program, &["-m", "first=16"]);
+  assert_eq!(stderr, "tests/units/named.txt uses memory units that are not configured: %second\n");
+  let (_, stderr) = failure(program, &["-m", "first=16", "-m", "first=8"]);
+  assert_eq!(stderr, "the memory unit %first is configured twice\n");
+  for invalid in ["a-b=16", "=16", "first=", "first=16:offset=4"] {
+    let (_, stderr) = failure(program, &["-m", invalid]);
+    assert!(stderr.contains("not a valid memory unit syntax"), "{}: {}", invalid, stderr);
+  }
+}
+
+#[test]
+fn mapped_files() {
+  let dir = env::temp_dir().join(format!("avmir_units_{}", std::process::id()));
+  fs::create_dir_all(&dir).unwrap();
+  let file = |name: &str| dir.join(name).display().to_string();
+  let unit = |size: usize, name: &str, options: &str| format!("{}:{}{}", size, file(name), options);
+
+  // created with the size of the window, the offset keeps the bytes before it
+  interpret("tests/units/write.txt", &["-m", &unit(16, "whole", "")]);
+  assert_eq!(fs::read(file("whole")).unwrap(), [[2, 1, 0, 0, 0, 0, 0, 0], [0; 8]].concat());
+  fs::write(file("window"), [9; 8]).unwrap();
+  interpret("tests/units/write.txt", &["-m", &unit(8, "window", ":offset=8")]);
+  assert_eq!(fs::read(file("window")).unwrap(), [[9; 8], [2, 1, 0, 0, 0, 0, 0, 0]].concat());
+
+  // read only maps need the whole window and fault on a write
+  let (_, stderr) = failure("tests/units/write.txt", &["-m", &unit(32, "whole", ":ro")]);
+  assert!(stderr.contains("is 16 bytes, shorter than the 32 bytes to map"), "{}", stderr);
+  let (_, stderr) = failure("tests/units/write.txt", &["-m", &unit(16, "whole", ":ro")]);
+  assert!(stderr.contains("memory fault accessing 8 bytes at 0 of the unit 0, it is read only"), "{}", stderr);
+  let (_, stderr) = failure("tests/units/write.txt", &["-m", &unit(16, "whole", ":ro:grow")]);
+  assert!(stderr.contains("is mapped read only, it can not grow"), "{}", stderr);
+
+  // growing extends the file, up to the largest unit
+  let (_, stderr) = failure("tests/units/grow.txt", &["-m", &unit(16, "grown", "")]);
+  assert!(stderr.contains("memory fault accessing 8 bytes at 100 of the unit 0"), "{}", stderr);
+  interpret("tests/units/grow.txt", &["-m", &unit(16, "grown", ":grow")]);
+  let grown = fs::read(file("grown")).unwrap();
+  assert!(grown.len() >= 108);
+  assert_eq!(grown[100..108], [2, 1, 0, 0, 0, 0, 0, 0]);
+  let (_, stderr) = failure("tests/units/huge.txt", &["-m", &unit(16, "grown", ":grow")]);
+  assert!(stderr.contains("memory fault accessing 8 bytes at 8589934592 of the unit 0\n"), "{}", stderr);
+  assert_eq!(fs::metadata(file("grown")).unwrap().len(), grown.len() as u64);
+  fs::remove_dir_all(&dir).unwrap();
+}
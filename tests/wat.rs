@@ -0,0 +1,26 @@
+//! The WebAssembly text translator runs the example with the output a WebAssembly runtime prints for it
+
+mod common;
+
+use avmir::{parser::{wat::{InternalWatError, Wat}, Parser}, vm::program::Program};
+use common::interpret;
+
+fn error(source: &str) -> InternalWatError {
+  Wat::parse(&mut Program::new(), source).expect_err(source).error
+}
+
+#[test]
+fn factorial_example() {
+  let expected = "factorials:\n1\n1\n2\n6\n24\n120\n3628800\n10\n3\n10\n";
+  assert_eq!(interpret("examples/wasm/factorial.wat", &[]), expected);
+  assert_eq!(interpret("examples/wasm/factorial.wat", &["-O"]), expected);
+}
+
+#[test]
+fn rejected_modules() {
+  assert!(matches!(error("(module (func $f))"), InternalWatError::NoEntry));
+  assert!(matches!(error("(module (func $main (export \"main\") i64.add))"), InternalWatError::StackUnderflow));
+  assert!(matches!(error("(module (func $main (export \"main\") (call $missing)))"), InternalWatError::NotFound(_)));
+  assert!(matches!(error("(module (func $main (export \"main\")) (func $main))"), InternalWatError::Duplicate(_)));
+  assert!(matches!(error("(module (func $main (export \"main\")"), InternalWatError::Unterminated(')')));
+}